@@ -0,0 +1,202 @@
+//! Friendly names for devices, so commands can take `--name kids-ipad`
+//! instead of an address, and named groups of devices for `--group`.
+
+use crate::device::print_table;
+use crate::mac::MacAddr;
+use crate::store;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Aliases by name, in the data directory.
+const ALIASES_FILE: &str = "aliases.json";
+/// Groups by name, next to the aliases.
+const GROUPS_FILE: &str = "groups.json";
+
+/// What an alias points at. MAC aliases keep working when the device gets a
+/// new IP, since they are resolved through the ARP cache on every use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AliasTarget {
+    Ip(IpAddr),
+    Mac(MacAddr),
+}
+
+impl FromStr for AliasTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(mac) = s.parse() {
+            return Ok(AliasTarget::Mac(mac));
+        }
+        s.parse()
+            .map(AliasTarget::Ip)
+            .map_err(|_| format!("{} is neither an IP nor a MAC address", s))
+    }
+}
+
+impl fmt::Display for AliasTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AliasTarget::Ip(ip) => write!(f, "{}", ip),
+            AliasTarget::Mac(mac) => write!(f, "{}", mac),
+        }
+    }
+}
+
+pub fn load() -> Result<BTreeMap<String, AliasTarget>> {
+    store::load(ALIASES_FILE)
+}
+
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.chars().any(char::is_whitespace) {
+        return Err(anyhow!(
+            "{} names can't be empty or contain whitespace: {:?}",
+            kind,
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Points `name` at `target`, replacing any previous alias of that name.
+pub fn set(name: &str, target: AliasTarget) -> Result<()> {
+    check_name("Alias", name)?;
+    let mut aliases = load()?;
+    aliases.insert(name.to_string(), target);
+    store::save(ALIASES_FILE, &aliases)
+}
+
+/// Like [`set`], but refuses to replace an alias that exists already.
+pub fn add(name: &str, target: AliasTarget) -> Result<()> {
+    if let Some(existing) = load()?.get(name) {
+        return Err(anyhow!(
+            "An alias named {} already exists, for {}; `alias set {} <ip|mac>` repoints it",
+            name,
+            existing,
+            name
+        ));
+    }
+    set(name, target)
+}
+
+/// Deletes `name`, returning false if there was no such alias. Rules on the
+/// device it named stay in place.
+pub fn remove(name: &str) -> Result<bool> {
+    let mut aliases = load()?;
+    if aliases.remove(name).is_none() {
+        return Ok(false);
+    }
+    store::save(ALIASES_FILE, &aliases)?;
+    Ok(true)
+}
+
+/// The names of the aliases for a device at `ip` with `mac`, joined by
+/// commas, or `None` if it has none.
+pub fn names_for(
+    aliases: &BTreeMap<String, AliasTarget>,
+    ip: Option<IpAddr>,
+    mac: Option<MacAddr>,
+) -> Option<String> {
+    let names: Vec<&str> = aliases
+        .iter()
+        .filter(|(_, target)| match target {
+            AliasTarget::Ip(alias_ip) => ip == Some(*alias_ip),
+            AliasTarget::Mac(alias_mac) => mac == Some(*alias_mac),
+        })
+        .map(|(name, _)| name.as_str())
+        .collect();
+    (!names.is_empty()).then(|| names.join(", "))
+}
+
+/// The target `name` stands for.
+pub fn lookup(name: &str) -> Result<AliasTarget> {
+    load()?.remove(name).ok_or_else(|| {
+        anyhow!(
+            "No alias named {}; see `alias list`, or add it with `alias add {} <ip|mac>`",
+            name,
+            name
+        )
+    })
+}
+
+pub fn print_aliases(json: bool) -> Result<()> {
+    let aliases = load()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&aliases)?);
+        return Ok(());
+    }
+    if aliases.is_empty() {
+        println!("No aliases defined. Add one with `alias set <name> <ip|mac>`.");
+        return Ok(());
+    }
+    let rows: Vec<[String; 2]> = aliases
+        .iter()
+        .map(|(name, target)| [name.clone(), target.to_string()])
+        .collect();
+    print_table(["NAME", "TARGET"], &rows, |_| false);
+    Ok(())
+}
+
+pub fn load_groups() -> Result<BTreeMap<String, Vec<AliasTarget>>> {
+    store::load(GROUPS_FILE)
+}
+
+/// Adds `members` to the group `name`, creating it if needed. Members
+/// already in the group are not added twice.
+pub fn add_to_group(name: &str, members: &[AliasTarget]) -> Result<()> {
+    check_name("Group", name)?;
+    let mut groups = load_groups()?;
+    let group = groups.entry(name.to_string()).or_default();
+    for member in members {
+        if !group.contains(member) {
+            group.push(*member);
+        }
+    }
+    store::save(GROUPS_FILE, &groups)
+}
+
+/// Deletes the group `name`, returning false if there was no such group.
+pub fn remove_group(name: &str) -> Result<bool> {
+    let mut groups = load_groups()?;
+    if groups.remove(name).is_none() {
+        return Ok(false);
+    }
+    store::save(GROUPS_FILE, &groups)?;
+    Ok(true)
+}
+
+/// The members of the group `name`.
+pub fn group_members(name: &str) -> Result<Vec<AliasTarget>> {
+    load_groups()?.remove(name).ok_or_else(|| {
+        anyhow!(
+            "No group named {}; see `group list`, or create it with `group add {} <ip|mac>...`",
+            name,
+            name
+        )
+    })
+}
+
+pub fn print_groups(json: bool) -> Result<()> {
+    let groups = load_groups()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+        return Ok(());
+    }
+    if groups.is_empty() {
+        println!("No groups defined. Create one with `group add <name> <ip|mac>...`.");
+        return Ok(());
+    }
+    let rows: Vec<[String; 2]> = groups
+        .iter()
+        .map(|(name, members)| {
+            let members: Vec<String> = members.iter().map(AliasTarget::to_string).collect();
+            [name.clone(), members.join(", ")]
+        })
+        .collect();
+    print_table(["GROUP", "MEMBERS"], &rows, |_| false);
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! Signs of ARP spoofing or clashing static addresses: one IP answering
+//! with several MACs, within a scan or compared to the previous one, and the
+//! gateway's MAC changing.
+
+use crate::device::{Device, HostRole};
+use crate::mac::MacAddr;
+use crate::oui;
+use serde::Serialize;
+use std::net::Ipv4Addr;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Conflict {
+    /// Several MACs answered for `ip` in the same scan
+    DuplicateIp { ip: Ipv4Addr, macs: Vec<MacAddr> },
+    /// `ip` belonged to `previous` in the last scan
+    MacChanged {
+        ip: Ipv4Addr,
+        previous: MacAddr,
+        current: MacAddr,
+    },
+    /// The default gateway answers with a different MAC than last scan
+    GatewayChanged {
+        ip: Ipv4Addr,
+        previous: MacAddr,
+        current: MacAddr,
+    },
+}
+
+/// Compares `current` with itself and with the `previous` scan of the same
+/// interface.
+pub fn find(previous: Option<&[Device]>, current: &[Device]) -> Vec<Conflict> {
+    let mut conflicts: Vec<Conflict> = current
+        .iter()
+        .filter(|d| !d.other_macs.is_empty())
+        .filter_map(|d| {
+            let mut macs = vec![d.mac?];
+            macs.extend(&d.other_macs);
+            Some(Conflict::DuplicateIp { ip: d.ip, macs })
+        })
+        .collect();
+    let Some(previous) = previous else {
+        return conflicts;
+    };
+
+    let gateway = |devices: &[Device]| {
+        devices
+            .iter()
+            .find(|d| d.role == Some(HostRole::Gateway))
+            .and_then(|d| Some((d.ip, d.mac?)))
+    };
+    let gateway_ip = match (gateway(previous), gateway(current)) {
+        (Some((_, old)), Some((ip, new))) if old != new => {
+            conflicts.push(Conflict::GatewayChanged {
+                ip,
+                previous: old,
+                current: new,
+            });
+            Some(ip)
+        }
+        _ => None,
+    };
+
+    for device in current {
+        let Some(mac) = device.mac else {
+            continue;
+        };
+        if Some(device.ip) == gateway_ip {
+            continue;
+        }
+        let old = previous
+            .iter()
+            .find(|d| d.ip == device.ip)
+            .and_then(|d| d.mac);
+        if let Some(old) = old.filter(|old| *old != mac) {
+            conflicts.push(Conflict::MacChanged {
+                ip: device.ip,
+                previous: old,
+                current: mac,
+            });
+        }
+    }
+    conflicts
+}
+
+fn describe(mac: &MacAddr) -> String {
+    match oui::lookup_vendor(mac) {
+        Some(vendor) => format!("{} ({})", mac, vendor),
+        None => mac.to_string(),
+    }
+}
+
+/// One line per conflict, for the warnings section.
+pub fn message(conflict: &Conflict) -> String {
+    match conflict {
+        Conflict::DuplicateIp { ip, macs } => {
+            let macs: Vec<String> = macs.iter().map(describe).collect();
+            format!("{} answered from several MACs: {}", ip, macs.join(", "))
+        }
+        Conflict::MacChanged {
+            ip,
+            previous,
+            current,
+        } => format!(
+            "{} was {} in the last scan, now {}",
+            ip,
+            describe(previous),
+            describe(current)
+        ),
+        Conflict::GatewayChanged {
+            ip,
+            previous,
+            current,
+        } => format!(
+            "the gateway {} changed MAC from {} to {}; this can mean ARP spoofing",
+            ip,
+            describe(previous),
+            describe(current)
+        ),
+    }
+}
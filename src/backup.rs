@@ -0,0 +1,200 @@
+//! Snapshots of the PF configuration we modify (`/etc/pf.conf` and the
+//! anchors directory), so the original firewall setup can be put back.
+
+use crate::{runner, store, RunContext};
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const PF_CONF: &str = "/etc/pf.conf";
+const PF_ANCHORS_DIR: &str = "/etc/pf.anchors";
+/// Our anchor, relative to `/` as stored in the archives.
+const OUR_ANCHOR: &str = "etc/pf.anchors/com.wifi-kicker";
+
+/// Where the archives go, in the data directory.
+pub const BACKUP_DIR: &str = "backups";
+
+fn backup_dir() -> Result<PathBuf> {
+    let dir = store::data_dir()?.join(BACKUP_DIR);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    store::hand_back(&dir);
+    Ok(dir)
+}
+
+/// A sortable local timestamp for file names, from `date`.
+fn timestamp() -> Result<String> {
+    let output = Command::new("date")
+        .arg("+%Y%m%d-%H%M%S")
+        .output()
+        .context("Failed to read the local time")?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Archives `/etc/pf.conf` and every anchor into a timestamped tarball
+/// under `~/.wifi-kicker/backups/` and returns its path.
+pub fn backup(ctx: &RunContext) -> Result<PathBuf> {
+    if !Path::new(PF_CONF).exists() {
+        return Err(anyhow!(
+            "{} not found; there is no PF configuration to back up",
+            PF_CONF
+        ));
+    }
+    let path = backup_dir()?.join(format!("pf-{}.tar.gz", timestamp()?));
+    let mut members = vec!["etc/pf.conf"];
+    if Path::new(PF_ANCHORS_DIR).is_dir() {
+        members.push("etc/pf.anchors");
+    }
+    let path_arg = path.to_string_lossy();
+    let mut args = vec!["-czf", &path_arg, "-C", "/"];
+    args.extend(members);
+    ctx.runner.run("tar", &args)?;
+    store::hand_back(&path);
+    info!("Backed up the PF configuration to {}", path.display());
+    Ok(path)
+}
+
+/// Takes a backup unless one exists already, so the configuration from
+/// before our first edit is always recoverable.
+pub fn ensure_initial_backup(ctx: &RunContext) -> Result<()> {
+    let has_backup = fs::read_dir(backup_dir()?)?
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name().to_string_lossy().ends_with(".tar.gz"));
+    if !has_backup {
+        let path = backup(ctx)?;
+        println!(
+            "Saved your original PF configuration to {}; `wifi-kicker restore` puts it back",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Lists an archive with `tar -t`, plus `-v` for the verbose form.
+fn list_archive(path: &Path, verbose: bool) -> Result<String> {
+    let listing = Command::new("tar")
+        .arg(if verbose { "-tvzf" } else { "-tzf" })
+        .arg(path)
+        .output()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if !listing.status.success() {
+        return Err(anyhow!(
+            "{} is not a readable backup archive",
+            path.display()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&listing.stdout).into_owned())
+}
+
+/// The files to extract from an archive whose `tar -t` listing is `names`
+/// and `tar -tv` listing is `verbose` (one line per member, in the same
+/// order). Archives sit in a directory the user can write to and are
+/// extracted as root, so anything but `etc/pf.conf` and plain files under
+/// `etc/pf.anchors/` is refused: other paths, absolute paths, `..` and
+/// links.
+fn restorable_members<'a>(names: &'a str, verbose: &str) -> Result<Vec<&'a str>> {
+    let names: Vec<&str> = names.lines().collect();
+    let kinds: Vec<char> = verbose
+        .lines()
+        .map(|line| line.chars().next().unwrap_or('?'))
+        .collect();
+    if names.len() != kinds.len() {
+        return Err(anyhow!("The archive listings don't agree"));
+    }
+    let mut files = Vec::new();
+    for (name, kind) in names.into_iter().zip(kinds) {
+        let in_anchors = name == "etc/pf.anchors/"
+            || name == "etc/pf.anchors"
+            || name.starts_with("etc/pf.anchors/");
+        if !(name == "etc/pf.conf" || in_anchors) || name.split('/').any(|part| part == "..") {
+            return Err(anyhow!(
+                "The archive contains {}, which is not part of the PF configuration",
+                name
+            ));
+        }
+        match kind {
+            '-' => files.push(name),
+            'd' if in_anchors => {}
+            _ => return Err(anyhow!("The archive member {} is not a regular file", name)),
+        }
+    }
+    if !files.contains(&"etc/pf.conf") {
+        return Err(anyhow!("The archive does not contain etc/pf.conf"));
+    }
+    Ok(files)
+}
+
+/// Extracts a backup over `/etc` and reloads PF from the restored
+/// `pf.conf`. Our anchor is deleted if the backup predates it.
+pub fn restore(ctx: &RunContext, path: &Path) -> Result<()> {
+    let names = list_archive(path, false)?;
+    let verbose = list_archive(path, true)?;
+    let members = restorable_members(&names, &verbose)
+        .with_context(|| format!("Refusing to restore {}", path.display()))?;
+
+    let path_arg = path.to_string_lossy();
+    let mut args = vec!["-xzf", &path_arg, "-C", "/"];
+    args.extend(&members);
+    ctx.runner.run("tar", &args)?;
+    let anchor = format!("/{}", OUR_ANCHOR);
+    if !members.contains(&OUR_ANCHOR) && Path::new(&anchor).exists() {
+        ctx.runner.run("rm", &[&anchor])?;
+    }
+    runner::run_retry(
+        ctx.runner,
+        "pfctl",
+        &["-f", PF_CONF],
+        runner::DEFAULT_ATTEMPTS,
+    )?;
+
+    info!("Restored the PF configuration from {}", path.display());
+    println!(
+        "Note: devices in `list` keep their entries; run `status` to see which rules are gone."
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_pf_conf_and_anchor_files() {
+        let names = "etc/pf.conf\netc/pf.anchors/\netc/pf.anchors/com.apple\netc/pf.anchors/com.wifi-kicker\n";
+        let verbose = "-rw-r--r--  0 root wheel 1027 Oct 14 10:00 etc/pf.conf\n\
+                       drwxr-xr-x  0 root wheel    0 Oct 14 10:00 etc/pf.anchors/\n\
+                       -rw-r--r--  0 root wheel  263 Oct 14 10:00 etc/pf.anchors/com.apple\n\
+                       -rw-r--r--  0 root wheel   80 Oct 14 10:00 etc/pf.anchors/com.wifi-kicker\n";
+        assert_eq!(
+            restorable_members(names, verbose).unwrap(),
+            [
+                "etc/pf.conf",
+                "etc/pf.anchors/com.apple",
+                "etc/pf.anchors/com.wifi-kicker"
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_members_outside_the_pf_configuration() {
+        for name in [
+            "etc/sudoers",
+            "usr/local/bin/pfctl",
+            "/etc/pf.anchors/x",
+            "etc/pf.anchors/../sudoers",
+        ] {
+            let names = format!("etc/pf.conf\n{}\n", name);
+            let verbose = format!("-rw-r--r-- etc/pf.conf\n-rw-r--r-- {}\n", name);
+            assert!(restorable_members(&names, &verbose).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn refuses_links_and_archives_without_pf_conf() {
+        let names = "etc/pf.conf\netc/pf.anchors/evil\n";
+        let verbose = "-rw-r--r-- etc/pf.conf\nlrwxr-xr-x etc/pf.anchors/evil -> /etc/sudoers\n";
+        assert!(restorable_members(names, verbose).is_err());
+        assert!(restorable_members("etc/pf.anchors/a\n", "-rw-r--r-- etc/pf.anchors/a\n").is_err());
+    }
+}
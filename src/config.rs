@@ -0,0 +1,65 @@
+//! Defaults for command-line flags, read from
+//! `~/.config/wifi-kicker/config.toml`:
+//!
+//! ```toml
+//! interface = "en0"
+//! persistent = true
+//! upload = 100      # KB/s, used by `limit` when neither cap is given
+//! download = 500
+//! log_level = "info"
+//! nmap_path = "/nix/var/nix/profiles/default/bin/nmap"
+//! nmap_args = ["--min-rate", "500"]
+//! ```
+//!
+//! Flags given on the command line win over the file.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Interface to use when `--interface` isn't given
+    pub interface: Option<String>,
+    /// Make `monitor` and `limit` rules persistent unless `--no-persistent`
+    pub persistent: bool,
+    /// Upload cap in KB/s for `limit`
+    pub upload: Option<u32>,
+    /// Download cap in KB/s for `limit`
+    pub download: Option<u32>,
+    /// nmap to run when `scan --nmap-path` isn't given
+    pub nmap_path: Option<PathBuf>,
+    /// Extra nmap arguments when `scan --nmap-arg` isn't given
+    pub nmap_args: Vec<String>,
+    /// `log` filter used when neither `RUST_LOG` nor `-v`/`-q` is given,
+    /// e.g. "debug"
+    pub log_level: Option<String>,
+}
+
+/// Where the file is expected: under `$XDG_CONFIG_HOME` when set, else
+/// `~/.config`.
+pub fn path() -> Result<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            let home = env::var_os("HOME").ok_or_else(|| anyhow!("HOME is not set"))?;
+            PathBuf::from(home).join(".config")
+        }
+    };
+    Ok(base.join("wifi-kicker").join("config.toml"))
+}
+
+/// Reads the config file; a missing file means all defaults.
+pub fn load() -> Result<Config> {
+    let path = path()?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            toml::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
@@ -0,0 +1,84 @@
+//! `daemon`: a long-running loop that enforces schedules and puts the rules
+//! back if something flushed the firewall under us.
+
+use crate::schedule;
+use crate::state::State;
+use crate::{RunContext, RunSettings};
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+
+/// One enforcement pass.
+fn enforce(ctx: &RunContext) -> Result<()> {
+    let mut state = State::load()?;
+    let schedules_changed = schedule::enforce(&mut state)?;
+    if schedules_changed {
+        info!("Applying schedule changes");
+    } else if !ctx.firewall.is_applied(ctx.runner, &state.targets)? {
+        warn!("Rules for managed devices are missing, reapplying");
+    } else {
+        return Ok(());
+    }
+    ctx.firewall.apply(ctx, &state.targets)?;
+    ctx.save(&state)
+}
+
+/// Drops the rules of non-persistent targets, leaving persistent ones in
+/// place for the next boot.
+fn shutdown(ctx: &RunContext) -> Result<()> {
+    let mut state = State::load()?;
+    let (persistent, removed): (Vec<_>, Vec<_>) =
+        state.targets.drain(..).partition(|t| t.persistent);
+    state.targets = persistent;
+    if removed.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Removing rules for {} non-persistent device(s)",
+        removed.len()
+    );
+    ctx.firewall.remove(ctx, &removed)?;
+    ctx.firewall.apply(ctx, &state.targets)?;
+    ctx.save(&state)
+}
+
+/// Enforces every `interval` until SIGINT or SIGTERM. Failed passes are
+/// logged and retried on the next tick. Passes run on the blocking pool, so
+/// a signal is noticed even while a command hangs; shutdown then waits for
+/// the running pass, which the command timeout bounds.
+pub async fn run(settings: &RunSettings, interval: Duration) -> Result<()> {
+    settings.with_context(|ctx| ctx.check_root())?;
+    let mut terminate =
+        signal(SignalKind::terminate()).context("Failed to install the SIGTERM handler")?;
+    let mut ticks = tokio::time::interval(interval);
+    // A slow pass shouldn't be followed by a burst of catch-up passes
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut passes = JoinSet::new();
+    info!("Daemon started, checking every {}s", interval.as_secs());
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick(), if passes.is_empty() => {
+                let settings = settings.clone();
+                passes.spawn_blocking(move || settings.with_context(enforce));
+            }
+            Some(result) = passes.join_next() => {
+                if let Err(e) = result.context("Enforcement pass failed").and_then(|r| r) {
+                    error!("Enforcement failed: {:#}", e);
+                }
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+
+    info!("Shutting down");
+    if !passes.is_empty() {
+        info!("Waiting for the running enforcement pass to finish");
+        while passes.join_next().await.is_some() {}
+    }
+    settings.run_blocking(shutdown).await
+}
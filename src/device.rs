@@ -0,0 +1,645 @@
+use crate::fingerprint::DeviceClass;
+use crate::mac::MacAddr;
+use crate::oui;
+use crate::ping::Latency;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceSource {
+    Nmap,
+    Sweep,
+    Arp,
+    /// The router's ARP table, read with `scan --snmp`
+    Snmp,
+}
+
+/// Where a device's hostname came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameSource {
+    /// Reverse DNS, including the names nmap and `arp -a` print
+    Dns,
+    /// The name the device sent with its DHCP request
+    Dhcp,
+    /// The `.local` name the device answered an mDNS reverse query with
+    Mdns,
+    /// The machine name from a NetBIOS node status query
+    Netbios,
+}
+
+/// Scan rows that deserve a second look before blocking them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostRole {
+    Gateway,
+    ThisHost,
+}
+
+impl HostRole {
+    pub fn label(self) -> &'static str {
+        match self {
+            HostRole::Gateway => "gateway",
+            HostRole::ThisHost => "this host",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub ip: Ipv4Addr,
+    pub mac: Option<MacAddr>,
+    pub vendor: Option<String>,
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub hostname_source: Option<NameSource>,
+    /// Friendly name advertised over Bonjour
+    pub mdns_name: Option<String>,
+    /// Names given to the device with `alias add`; filled in from the
+    /// aliases each time a scan is shown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// IPv6 neighbor addresses with the same MAC
+    #[serde(default)]
+    pub ipv6: Vec<Ipv6Addr>,
+    /// Bonjour service types the device advertises, e.g. "_airplay._tcp"
+    #[serde(default)]
+    pub services: BTreeSet<String>,
+    /// Best guess at what kind of device this is
+    #[serde(default)]
+    pub class: Option<DeviceClass>,
+    /// nmap's operating system guess, from `scan --os-detect`
+    #[serde(default)]
+    pub os: Option<String>,
+    /// Product name from an Apple device's Bonjour device-info record, or
+    /// the raw model identifier when it isn't in the table
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Whether the device answered the ping scan
+    pub up: bool,
+    /// Echo round trip, if the device answered pings
+    #[serde(default)]
+    pub latency: Option<Latency>,
+    /// TTL of the echo replies
+    #[serde(default)]
+    pub ttl: Option<u8>,
+    /// Unix timestamp of the scan in which the device answered, if it did
+    pub last_seen: Option<u64>,
+    pub source: DeviceSource,
+    /// Set for the default gateway and the scanning machine itself
+    #[serde(default)]
+    pub role: Option<HostRole>,
+    /// What wifi-kicker's rules do to the device, e.g. "blocked"; filled in
+    /// from the saved state each time a scan is shown
+    #[serde(default)]
+    pub rules: Option<String>,
+    /// Further MACs that answered for the same IP in this scan, which
+    /// points at an address clash or ARP spoofing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_macs: Vec<MacAddr>,
+    /// Open TCP ports found by `scan --profile thorough`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub open_ports: Vec<u16>,
+    /// In a range given to `scan --exclude`, so only passively seen (in the
+    /// ARP cache) and never probed
+    #[serde(default)]
+    pub excluded: bool,
+}
+
+impl Device {
+    pub fn new(ip: Ipv4Addr, source: DeviceSource) -> Self {
+        Device {
+            ip,
+            mac: None,
+            vendor: None,
+            hostname: None,
+            hostname_source: None,
+            mdns_name: None,
+            alias: None,
+            ipv6: Vec::new(),
+            services: BTreeSet::new(),
+            class: None,
+            model: None,
+            os: None,
+            up: !matches!(source, DeviceSource::Arp | DeviceSource::Snmp),
+            latency: None,
+            ttl: None,
+            last_seen: None,
+            source,
+            role: None,
+            rules: None,
+            other_macs: Vec::new(),
+            open_ports: Vec::new(),
+            excluded: false,
+        }
+    }
+
+    /// A device is stale when it is only known from the ARP cache and did
+    /// not answer the ping scan.
+    pub fn is_stale(&self) -> bool {
+        !self.up
+    }
+}
+
+/// Case-insensitive substring filters over a device's vendor, hostname,
+/// Bonjour name and MAC. A device is kept if it matches any `include`
+/// pattern (or there are none) and no `exclude` pattern.
+#[derive(Debug, Default)]
+pub struct DeviceFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl DeviceFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Self {
+        // MACs are displayed colon separated, so accept dashes in patterns too
+        let normalize = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| p.to_lowercase().replace('-', ":"))
+                .collect()
+        };
+        DeviceFilter {
+            include: normalize(include),
+            exclude: normalize(exclude),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn matches(device: &Device, pattern: &str) -> bool {
+        let mac = device.mac.map(|mac| mac.to_string());
+        let fields = [
+            device.vendor.as_deref(),
+            device.hostname.as_deref(),
+            device.mdns_name.as_deref(),
+            mac.as_deref(),
+        ];
+        fields
+            .iter()
+            .flatten()
+            .any(|field| field.to_lowercase().contains(pattern))
+    }
+
+    pub fn keeps(&self, device: &Device) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| Self::matches(device, p)))
+            && !self.exclude.iter().any(|p| Self::matches(device, p))
+    }
+
+    /// Splits `devices` into the ones the filter keeps and the number hidden.
+    pub fn apply(&self, devices: Vec<Device>) -> (Vec<Device>, usize) {
+        let total = devices.len();
+        let kept: Vec<Device> = devices.into_iter().filter(|d| self.keeps(d)).collect();
+        let hidden = total - kept.len();
+        (kept, hidden)
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Renders a past Unix timestamp relative to now, e.g. "5m ago".
+pub fn format_age(timestamp: u64) -> String {
+    let secs = unix_now().saturating_sub(timestamp);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// `bytes` with a binary unit, e.g. `1.5 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 {
+            return if unit == "B" {
+                format!("{} B", bytes)
+            } else {
+                format!("{:.1} {}", value, unit)
+            };
+        }
+        value /= 1024.0;
+    }
+    format!("{:.1} TiB", value)
+}
+
+/// Extracts each host's OS from `nmap -O` normal output: the "OS details"
+/// line for an exact match, otherwise the likeliest of the "Aggressive OS
+/// guesses" that `--osscan-guess` prints.
+pub fn parse_nmap_os(output: &str) -> HashMap<Ipv4Addr, String> {
+    let mut guesses = HashMap::new();
+    let mut host: Option<Ipv4Addr> = None;
+    for line in output.lines() {
+        if let Some(report) = line.strip_prefix("Nmap scan report for ") {
+            // "Nmap scan report for router.lan (192.168.1.1)" or just the IP
+            host = report
+                .rsplit(' ')
+                .next()
+                .and_then(|ip| ip.trim_matches(['(', ')']).parse().ok());
+        } else if let (Some(ip), Some(details)) = (host, line.strip_prefix("OS details: ")) {
+            guesses.insert(ip, details.trim().to_string());
+        } else if let (Some(ip), Some(list)) = (host, line.strip_prefix("Aggressive OS guesses: "))
+        {
+            // "Linux 4.15 - 5.8 (96%), Linux 5.0 - 5.4 (95%), ..."
+            if let Some(best) = list.split(", ").next() {
+                guesses.entry(ip).or_insert_with(|| best.trim().to_string());
+            }
+        }
+    }
+    guesses
+}
+
+/// Parses nmap's greppable (`-oG`) output. Only hosts reported as
+/// `Status: Up` are returned; `Status: Down` hosts (printed with `-v`) and
+/// the `# Nmap done` trailer are skipped.
+pub fn parse_nmap_greppable(output: &str) -> Vec<Device> {
+    let seen = unix_now();
+    output
+        .lines()
+        .filter(|line| line.starts_with("Host:"))
+        .filter(|line| line.split('\t').any(|field| field.trim() == "Status: Up"))
+        .filter_map(|line| {
+            // Host: 192.168.1.1 (router.lan)\tStatus: Up
+            let mut parts = line["Host:".len()..].split_whitespace();
+            let ip = parts.next()?.parse().ok()?;
+            let hostname = parts
+                .next()
+                .map(|h| h.trim_start_matches('(').trim_end_matches(')'))
+                .filter(|h| !h.is_empty())
+                .map(String::from);
+            Some(Device {
+                hostname_source: hostname.is_some().then_some(NameSource::Dns),
+                hostname,
+                last_seen: Some(seen),
+                ..Device::new(ip, DeviceSource::Nmap)
+            })
+        })
+        .collect()
+}
+
+/// Parses `arp -a` output, skipping incomplete entries.
+pub fn parse_arp_table(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // router.lan (192.168.1.1) at 0:11:22:33:44:55 on en0 ifscope [ethernet]
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let ip = parts
+                .next()?
+                .trim_start_matches('(')
+                .trim_end_matches(')')
+                .parse()
+                .ok()?;
+            if parts.next()? != "at" {
+                return None;
+            }
+            // "(incomplete)" entries fail to parse and are skipped
+            let mac: MacAddr = parts.next()?.parse().ok()?;
+            Some(Device {
+                mac: Some(mac),
+                vendor: oui::lookup_vendor(&mac),
+                hostname: (name != "?").then(|| name.to_string()),
+                hostname_source: (name != "?").then_some(NameSource::Dns),
+                ..Device::new(ip, DeviceSource::Arp)
+            })
+        })
+        .collect()
+}
+
+/// Parses Linux `ip neigh show` output, skipping entries without a MAC
+/// (`FAILED` and `INCOMPLETE` ones).
+pub fn parse_ip_neigh(output: &str) -> Vec<Device> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // 192.168.1.1 dev eth0 lladdr 00:11:22:33:44:55 REACHABLE
+            let mut parts = line.split_whitespace();
+            let ip = parts.next()?.parse().ok()?;
+            parts.find(|p| *p == "lladdr")?;
+            let mac: MacAddr = parts.next()?.parse().ok()?;
+            Some(Device {
+                mac: Some(mac),
+                vendor: oui::lookup_vendor(&mac),
+                ..Device::new(ip, DeviceSource::Arp)
+            })
+        })
+        .collect()
+}
+
+/// Parses IPv6 neighbor tables into (address, MAC) pairs: macOS `ndp -an`
+///
+/// ```text
+/// Neighbor                        Linklayer Address  Netif Expire    St Flgs Prbs
+/// fe80::1%en0                     0:11:22:33:44:55     en0 23h59m58s S  R
+/// ```
+///
+/// or Linux `ip -6 neigh show`
+/// (`fe80::1 dev eth0 lladdr 00:11:22:33:44:55 router REACHABLE`).
+/// Link-local addresses lose their `%scope` suffix; entries without a MAC
+/// are skipped.
+pub fn parse_ipv6_neighbors(output: &str) -> Vec<(Ipv6Addr, MacAddr)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let ip = parts.next()?.split('%').next()?.parse().ok()?;
+            let mac = match line.contains(" lladdr ") {
+                true => {
+                    parts.find(|p| *p == "lladdr")?;
+                    parts.next()?
+                }
+                false => parts.next()?,
+            };
+            Some((ip, mac.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Combines ping scan and ARP cache results into one entry per IP, sorted by
+/// address. Devices that answered the scan keep their `Nmap` source and pick
+/// up the MAC (and hostname, if missing) from the ARP cache.
+pub fn merge_devices(scanned: Vec<Device>, cached: Vec<Device>) -> Vec<Device> {
+    let mut by_ip: BTreeMap<Ipv4Addr, Device> = BTreeMap::new();
+
+    for device in scanned.into_iter().chain(cached) {
+        match by_ip.get_mut(&device.ip) {
+            Some(existing) => {
+                match (existing.mac, device.mac) {
+                    (None, _) => {
+                        existing.mac = device.mac;
+                        existing.vendor = device.vendor;
+                    }
+                    (Some(known), Some(other))
+                        if known != other && !existing.other_macs.contains(&other) =>
+                    {
+                        existing.other_macs.push(other);
+                    }
+                    _ => {}
+                }
+                if existing.hostname.is_none() {
+                    existing.hostname = device.hostname;
+                    existing.hostname_source = device.hostname_source;
+                }
+            }
+            None => {
+                by_ip.insert(device.ip, device);
+            }
+        }
+    }
+
+    by_ip.into_values().collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SortKey {
+    #[default]
+    Ip,
+    Mac,
+    Vendor,
+    Hostname,
+    Latency,
+    LastSeen,
+}
+
+/// Sorts `devices` by `key`, descending if `reverse`. Devices missing the
+/// key always go last; ties fall back to IP order.
+pub fn sort_devices(devices: &mut [Device], key: SortKey, reverse: bool) {
+    fn by<T: PartialOrd>(a: Option<T>, b: Option<T>, reverse: bool) -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                let order = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+                if reverse {
+                    order.reverse()
+                } else {
+                    order
+                }
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+
+    let lower = |s: &Option<String>| s.as_ref().map(|s| s.to_lowercase());
+    devices.sort_by(|a, b| {
+        match key {
+            SortKey::Ip => by(Some(a.ip), Some(b.ip), reverse),
+            SortKey::Mac => by(a.mac, b.mac, reverse),
+            SortKey::Vendor => by(lower(&a.vendor), lower(&b.vendor), reverse),
+            SortKey::Hostname => by(lower(&a.hostname), lower(&b.hostname), reverse),
+            SortKey::Latency => by(
+                a.latency.map(|l| l.avg_ms),
+                b.latency.map(|l| l.avg_ms),
+                reverse,
+            ),
+            SortKey::LastSeen => by(a.last_seen, b.last_seen, reverse),
+        }
+        .then(a.ip.cmp(&b.ip))
+    });
+}
+
+/// Renders one CSV record, quoting fields that contain commas, quotes or
+/// line breaks.
+pub fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Prints rows under `header` with every column padded to its widest cell.
+/// Rows for which `highlight(index)` is true are printed in bold green.
+pub fn print_table<const N: usize>(
+    header: [&str; N],
+    rows: &[[String; N]],
+    highlight: impl Fn(usize) -> bool,
+) {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let format_row = |cells: [&str; N]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell))
+            .collect();
+        line.join("  ").trim_end().to_string()
+    };
+
+    println!("{}", format_row(header));
+    for (index, row) in rows.iter().enumerate() {
+        let line = format_row(row.each_ref().map(String::as_str));
+        if highlight(index) {
+            println!("\x1b[1;32m{}\x1b[0m", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// A device's IPv6 addresses in one cell: a global one if it has any (the
+/// link-local address says little), and how many more there are.
+fn ipv6_cell(addresses: &[Ipv6Addr]) -> String {
+    let Some(shown) = addresses
+        .iter()
+        .find(|a| !a.is_unicast_link_local())
+        .or(addresses.first())
+    else {
+        return "-".to_string();
+    };
+    match addresses.len() {
+        1 => shown.to_string(),
+        n => format!("{} +{}", shown, n - 1),
+    }
+}
+
+/// Prints devices as an aligned table, highlighting the IPs in `changed`.
+pub fn print_device_table(devices: &[Device], changed: &HashSet<Ipv4Addr>) {
+    if devices.is_empty() {
+        println!("No devices found.");
+        return;
+    }
+
+    let dash = || "-".to_string();
+    let rows: Vec<[String; 11]> = devices
+        .iter()
+        .map(|d| {
+            [
+                match (d.role, d.excluded) {
+                    (Some(role), true) => format!("{} [{}, excluded]", d.ip, role.label()),
+                    (Some(role), false) => format!("{} [{}]", d.ip, role.label()),
+                    (None, true) => format!("{} [excluded]", d.ip),
+                    (None, false) => d.ip.to_string(),
+                },
+                ipv6_cell(&d.ipv6),
+                d.mac.map_or_else(dash, |mac| mac.to_string()),
+                d.vendor.clone().unwrap_or_else(dash),
+                match (&d.hostname, d.hostname_source) {
+                    (Some(name), Some(NameSource::Dhcp)) => format!("{} (dhcp)", name),
+                    (Some(name), _) => name.clone(),
+                    (None, _) => dash(),
+                },
+                match (&d.alias, &d.mdns_name) {
+                    (Some(alias), Some(name)) => format!("{} ({})", alias, name),
+                    (Some(name), None) | (None, Some(name)) => name.clone(),
+                    (None, None) => dash(),
+                },
+                // The product name says more than the guessed class
+                d.model.clone().unwrap_or_else(|| {
+                    d.class.map_or_else(dash, |class| class.label().to_string())
+                }),
+                d.os.clone().unwrap_or_else(dash),
+                d.latency.map_or_else(
+                    || ">timeout".to_string(),
+                    |l| format!("{:.1}/{:.1} ms", l.min_ms, l.avg_ms),
+                ),
+                match (d.is_stale(), d.source) {
+                    (false, _) => "up",
+                    (true, DeviceSource::Snmp) => "via router",
+                    (true, _) => "stale",
+                }
+                .to_string(),
+                d.rules.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let highlight = |index: usize| changed.contains(&devices[index].ip);
+    if devices.iter().any(|d| d.os.is_some()) {
+        print_table(
+            [
+                "IP", "IPV6", "MAC", "VENDOR", "HOSTNAME", "NAME", "TYPE", "OS", "LATENCY",
+                "STATUS", "RULES",
+            ],
+            &rows,
+            highlight,
+        );
+        return;
+    }
+    // The OS column only appears after --os-detect
+    let rows: Vec<[String; 10]> = rows
+        .into_iter()
+        .map(
+            |[ip, ipv6, mac, vendor, hostname, name, class, _, latency, status, rules]| {
+                [
+                    ip, ipv6, mac, vendor, hostname, name, class, latency, status, rules,
+                ]
+            },
+        )
+        .collect();
+    print_table(
+        [
+            "IP", "IPV6", "MAC", "VENDOR", "HOSTNAME", "NAME", "TYPE", "LATENCY", "STATUS", "RULES",
+        ],
+        &rows,
+        highlight,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `nmap -sn -v -oG -` against a /24 with two hosts up.
+    const GREPPABLE: &str = "\
+# Nmap 7.94 scan initiated Tue Oct 14 10:00:00 2026 as: nmap -sn -v -oG - 192.168.1.0/24
+Host: 192.168.1.1 (router.lan)\tStatus: Up
+Host: 192.168.1.2 ()\tStatus: Down
+Host: 192.168.1.23 ()\tStatus: Up
+# Nmap done at Tue Oct 14 10:00:03 2026 -- 256 IP addresses (2 hosts up) scanned in 3.05 seconds
+";
+
+    #[test]
+    fn greppable_keeps_the_hosts_that_are_up() {
+        let devices = parse_nmap_greppable(GREPPABLE);
+        let ips: Vec<Ipv4Addr> = devices.iter().map(|d| d.ip).collect();
+        assert_eq!(
+            ips,
+            [
+                Ipv4Addr::new(192, 168, 1, 1),
+                Ipv4Addr::new(192, 168, 1, 23)
+            ]
+        );
+        assert!(devices.iter().all(|d| d.source == DeviceSource::Nmap));
+    }
+
+    #[test]
+    fn greppable_reads_the_reverse_dns_name() {
+        let devices = parse_nmap_greppable(GREPPABLE);
+        assert_eq!(devices[0].hostname.as_deref(), Some("router.lan"));
+        assert_eq!(devices[0].hostname_source, Some(NameSource::Dns));
+        assert_eq!(devices[1].hostname, None);
+        assert_eq!(devices[1].hostname_source, None);
+    }
+
+    #[test]
+    fn greppable_without_hosts() {
+        assert!(parse_nmap_greppable("# Nmap done at Tue Oct 14 10:00:03 2026 -- 256 IP addresses (0 hosts up) scanned in 2.01 seconds\n").is_empty());
+        assert!(parse_nmap_greppable("").is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+//! Hostnames from DHCP: the leases macOS hands out when Internet Sharing is
+//! on (`/var/db/dhcpd_leases`), and our own lease from `ipconfig getpacket`.
+//! Devices send their name when asking for a lease, so these are often
+//! friendlier than reverse DNS.
+
+use crate::device::{Device, NameSource};
+use crate::mac::MacAddr;
+use log::{debug, warn};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::Ipv4Addr;
+use std::process::Command;
+
+const LEASES_FILE: &str = "/var/db/dhcpd_leases";
+
+/// A name a device registered with a DHCP server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub ip: Ipv4Addr,
+    pub mac: Option<MacAddr>,
+    pub name: String,
+}
+
+/// Strips the hardware type bootpd prefixes MACs with (`1,` for Ethernet).
+fn parse_hw_address(value: &str) -> Option<MacAddr> {
+    value.rsplit(',').next()?.parse().ok()
+}
+
+/// Parses bootpd's lease database, a list of brace-delimited blocks:
+///
+/// ```text
+/// {
+///     name=Johns-iPhone
+///     ip_address=192.168.2.3
+///     hw_address=1,a4:83:e7:12:34:56
+///     identifier=1,a4:83:e7:12:34:56
+///     lease=0x65a1b2c3
+/// }
+/// ```
+///
+/// Unknown keys are ignored, and blocks without a name or address skipped.
+pub fn parse_dhcpd_leases(s: &str) -> Vec<Lease> {
+    let mut leases = Vec::new();
+    let (mut ip, mut mac, mut name) = (None, None, None);
+    for line in s.lines().map(str::trim) {
+        match line {
+            "{" => (ip, mac, name) = (None, None, None),
+            "}" => {
+                if let (Some(ip), Some(name)) = (ip.take(), name.take()) {
+                    leases.push(Lease {
+                        ip,
+                        mac: mac.take(),
+                        name,
+                    });
+                }
+            }
+            _ => match line.split_once('=') {
+                Some(("name", value)) if !value.is_empty() => name = Some(value.to_string()),
+                Some(("ip_address", value)) => ip = value.parse().ok(),
+                Some(("hw_address", value)) => mac = parse_hw_address(value),
+                _ => {}
+            },
+        }
+    }
+    leases
+}
+
+/// Parses `ipconfig getpacket <interface>`, the server's reply to our own
+/// lease request; the name is only present when the server sent a
+/// `host_name` option.
+///
+/// ```text
+/// yiaddr = 192.168.1.23
+/// chaddr = a4:83:e7:12:34:56
+/// options:
+/// host_name (string): Johns-MacBook
+/// ```
+pub fn parse_getpacket(s: &str) -> Option<Lease> {
+    let (mut ip, mut mac, mut name) = (None, None, None);
+    for line in s.lines().map(str::trim) {
+        if let Some((key, value)) = line.split_once(" = ") {
+            match key {
+                "yiaddr" => ip = value.trim().parse().ok(),
+                "chaddr" => mac = value.trim().parse().ok(),
+                _ => {}
+            }
+        } else if let Some(value) = line.strip_prefix("host_name (string):") {
+            name = Some(value.trim().to_string()).filter(|n| !n.is_empty());
+        }
+    }
+    Some(Lease {
+        ip: ip?,
+        mac,
+        name: name?,
+    })
+}
+
+/// Every lease we can read. All sources are optional: the leases file only
+/// exists while Internet Sharing is (or was) on, and `ipconfig` is
+/// macOS-only.
+pub fn leases(interface: &str) -> Vec<Lease> {
+    let mut leases = match fs::read_to_string(LEASES_FILE) {
+        Ok(s) => parse_dhcpd_leases(&s),
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            warn!("Failed to read {}: {}", LEASES_FILE, e);
+            Vec::new()
+        }
+    };
+    if cfg!(target_os = "macos") {
+        match Command::new("ipconfig")
+            .args(["getpacket", interface])
+            .output()
+        {
+            Ok(output) => leases.extend(parse_getpacket(&String::from_utf8_lossy(&output.stdout))),
+            Err(e) => debug!("ipconfig getpacket failed: {}", e),
+        }
+    }
+    leases
+}
+
+/// Names devices that don't have a hostname yet from their lease, matched
+/// by MAC, or by address for leases without one.
+pub fn apply_lease_names(devices: &mut [Device], leases: &[Lease]) {
+    for device in devices.iter_mut().filter(|d| d.hostname.is_none()) {
+        let lease = leases
+            .iter()
+            .find(|l| l.mac.is_some() && l.mac == device.mac)
+            .or_else(|| leases.iter().find(|l| l.mac.is_none() && l.ip == device.ip));
+        if let Some(lease) = lease {
+            device.hostname = Some(lease.name.clone());
+            device.hostname_source = Some(NameSource::Dhcp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::DeviceSource;
+
+    /// bootpd's lease file with Internet Sharing on; bootpd drops the
+    /// leading zero of each MAC octet.
+    const LEASES: &str = include_str!("../testdata/dhcpd_leases");
+    /// `ipconfig getpacket en0` on macOS 14.
+    const GETPACKET: &str = include_str!("../testdata/ipconfig_getpacket_en0.txt");
+
+    #[test]
+    fn parses_the_lease_file() {
+        assert_eq!(
+            parse_dhcpd_leases(LEASES),
+            [
+                Lease {
+                    ip: Ipv4Addr::new(192, 168, 2, 3),
+                    mac: Some("a4:83:e7:02:34:56".parse().unwrap()),
+                    name: "Johns-iPhone".to_string(),
+                },
+                // Not an Ethernet MAC, so only the address matches it
+                Lease {
+                    ip: Ipv4Addr::new(192, 168, 2, 5),
+                    mac: None,
+                    name: "kitchen-speaker".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_our_own_lease() {
+        assert_eq!(
+            parse_getpacket(GETPACKET),
+            Some(Lease {
+                ip: Ipv4Addr::new(192, 168, 1, 23),
+                mac: Some("a4:83:e7:02:34:56".parse().unwrap()),
+                name: "Johns-MacBook".to_string(),
+            })
+        );
+        let unnamed: String = GETPACKET
+            .lines()
+            .filter(|l| !l.starts_with("host_name"))
+            .map(|l| format!("{}\n", l))
+            .collect();
+        assert_eq!(parse_getpacket(&unnamed), None);
+        assert_eq!(parse_getpacket(""), None);
+    }
+
+    #[test]
+    fn names_only_unnamed_devices() {
+        let leases = parse_dhcpd_leases(LEASES);
+        let mut phone = Device::new(Ipv4Addr::new(192, 168, 2, 30), DeviceSource::Nmap);
+        phone.mac = Some("a4:83:e7:02:34:56".parse().unwrap());
+        let speaker = Device::new(Ipv4Addr::new(192, 168, 2, 5), DeviceSource::Nmap);
+        let mut laptop = Device::new(Ipv4Addr::new(192, 168, 2, 3), DeviceSource::Nmap);
+        laptop.hostname = Some("laptop.lan".to_string());
+        laptop.hostname_source = Some(NameSource::Dns);
+        let mut devices = [phone, speaker, laptop];
+
+        apply_lease_names(&mut devices, &leases);
+
+        assert_eq!(devices[0].hostname.as_deref(), Some("Johns-iPhone"));
+        assert_eq!(devices[0].hostname_source, Some(NameSource::Dhcp));
+        assert_eq!(devices[1].hostname.as_deref(), Some("kitchen-speaker"));
+        assert_eq!(devices[2].hostname.as_deref(), Some("laptop.lan"));
+        assert_eq!(devices[2].hostname_source, Some(NameSource::Dns));
+    }
+}
@@ -0,0 +1,82 @@
+//! Changes between two scans of the same interface.
+
+use crate::device::Device;
+use crate::mac::MacAddr;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+
+/// Devices are matched by MAC when known, since IPs change with DHCP.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Mac(MacAddr),
+    Ip(Ipv4Addr),
+}
+
+fn key(device: &Device) -> Key {
+    device.mac.map_or(Key::Ip(device.ip), Key::Mac)
+}
+
+#[derive(Debug, Default)]
+pub struct ScanDiff {
+    pub joined: Vec<Device>,
+    pub departed: Vec<Device>,
+    /// Known MACs now at a different address: (device, previous IP)
+    pub moved: Vec<(Device, Ipv4Addr)>,
+}
+
+impl ScanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.departed.is_empty() && self.moved.is_empty()
+    }
+}
+
+pub fn diff(previous: &[Device], current: &[Device]) -> ScanDiff {
+    let before: HashMap<Key, &Device> = previous.iter().map(|d| (key(d), d)).collect();
+    let after: HashMap<Key, &Device> = current.iter().map(|d| (key(d), d)).collect();
+
+    let mut changes = ScanDiff::default();
+    for device in current {
+        match before.get(&key(device)) {
+            None => changes.joined.push(device.clone()),
+            Some(old) if old.ip != device.ip => changes.moved.push((device.clone(), old.ip)),
+            Some(_) => {}
+        }
+    }
+    changes.departed = previous
+        .iter()
+        .filter(|d| !after.contains_key(&key(d)))
+        .cloned()
+        .collect();
+    changes
+}
+
+/// One line identifying `device`: IP, MAC, name and vendor, as known.
+pub fn describe(device: &Device) -> String {
+    let mut parts = vec![device.ip.to_string()];
+    if let Some(mac) = device.mac {
+        parts.push(mac.to_string());
+    }
+    if let Some(name) = device.mdns_name.as_ref().or(device.hostname.as_ref()) {
+        parts.push(name.clone());
+    }
+    if let Some(vendor) = &device.vendor {
+        parts.push(format!("[{}]", vendor));
+    }
+    parts.join("  ")
+}
+
+pub fn print_diff(changes: &ScanDiff) {
+    if changes.is_empty() {
+        println!("No changes since the last scan.");
+        return;
+    }
+    for device in &changes.joined {
+        println!("+ new       {}", describe(device));
+    }
+    for device in &changes.departed {
+        println!("- gone      {}", describe(device));
+    }
+    for (device, old_ip) in &changes.moved {
+        println!("~ moved     {} (was {})", describe(device), old_ip);
+    }
+}
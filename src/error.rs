@@ -0,0 +1,110 @@
+//! Failures callers may want to tell apart. They travel inside `anyhow`
+//! errors like everything else, so match on them with
+//! `err.downcast_ref::<KickerError>()`.
+
+use crate::device::HostRole;
+use ipnetwork::Ipv4Network;
+use std::net::IpAddr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum KickerError {
+    #[error("This command requires root privileges (current euid: {euid})")]
+    NotRoot { euid: u32 },
+    #[error("Interface {0} not found")]
+    InterfaceNotFound(String),
+    #[error("Interface {0} has no IPv4 address")]
+    NoIpv4Address(String),
+    /// A required external program isn't installed
+    #[error("{0} not found in PATH")]
+    ToolMissing(&'static str),
+    /// Several required programs aren't installed, see `check_dependencies`
+    #[error("{}", describe_missing(.0))]
+    ToolsMissing(Vec<&'static str>),
+    /// A privileged command (pfctl, dnctl, nft, ...) exited unsuccessfully
+    #[error("Command failed: {cmd}\nError: {stderr}")]
+    CommandFailed { cmd: String, stderr: String },
+    /// An external program ran past its time limit and was killed
+    #[error("{cmd} timed out after {secs}s and was stopped")]
+    TimedOut { cmd: String, secs: u64 },
+    /// `monitor` or `limit` was pointed at the gateway or this machine
+    #[error("Refusing to add rules for {ip}: {}", describe_role(*.role))]
+    HostTarget { ip: String, role: HostRole },
+    /// A `--cidr` target covers the gateway or this machine
+    #[error("Refusing to add rules for {network}: it contains {ip}, {}", describe_member(*.role))]
+    RangeContainsHost {
+        network: String,
+        ip: IpAddr,
+        role: HostRole,
+    },
+    #[error("Refusing to scan {network} ({} addresses); pass --force to scan more than a /{min_prefix}", network.size())]
+    RangeTooLarge {
+        network: Ipv4Network,
+        min_prefix: u8,
+    },
+}
+
+/// How to get `tool`, for the tools wifi-kicker runs.
+fn install_hint(tool: &str) -> &'static str {
+    match tool {
+        "nmap" => "install with `brew install nmap` (or your package manager)",
+        "nft" => "install nftables, e.g. `sudo apt install nftables`",
+        "ip" => "install iproute2, e.g. `sudo apt install iproute2`",
+        "ifconfig" | "arp" if cfg!(target_os = "linux") => {
+            "install net-tools, e.g. `sudo apt install net-tools`"
+        }
+        "sudo" => "install sudo; privileged commands run through it",
+        _ => "it ships with macOS; check that /sbin and /usr/sbin are in PATH",
+    }
+}
+
+fn describe_role(role: HostRole) -> &'static str {
+    match role {
+        HostRole::Gateway => {
+            "it is the default gateway, so rules on it would cut this machine off the network"
+        }
+        HostRole::ThisHost => "it is this machine's own address",
+    }
+}
+
+fn describe_member(role: HostRole) -> &'static str {
+    match role {
+        HostRole::Gateway => {
+            "the default gateway, so the rules would cut this machine off the network"
+        }
+        HostRole::ThisHost => "this machine's own address",
+    }
+}
+
+fn describe_missing(tools: &[&str]) -> String {
+    let lines: Vec<String> = tools
+        .iter()
+        .map(|tool| format!("  {} not found \u{2014} {}", tool, install_hint(tool)))
+        .collect();
+    format!("Missing required tools:\n{}", lines.join("\n"))
+}
+
+impl KickerError {
+    /// What the user can do about it, shown under the error message.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            KickerError::NotRoot { .. } => Some("Please run with sudo."),
+            KickerError::InterfaceNotFound(_) => {
+                Some("Run `rust-wifi-kicker interfaces` to see the available ones.")
+            }
+            KickerError::ToolMissing("nmap") => {
+                Some("Install it (brew install nmap), or drop the options that need it (--method nmap, --require-nmap, --os-detect).")
+            }
+            KickerError::TimedOut { cmd, .. } if cmd == "nmap" => {
+                Some("Allow it longer with `scan --nmap-timeout <secs>`, or scan a smaller range.")
+            }
+            KickerError::HostTarget { .. } | KickerError::RangeContainsHost { .. } => {
+                Some("Pass --force if that is really what you want.")
+            }
+            KickerError::TimedOut { .. } => {
+                Some("Allow privileged commands longer with `--timeout <secs>`.")
+            }
+            _ => None,
+        }
+    }
+}
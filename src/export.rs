@@ -0,0 +1,199 @@
+//! `export` and `import`: the aliases, groups, schedules and managed
+//! targets in one JSON document, to move a setup to another machine or keep
+//! it with dotfiles.
+
+use crate::alias::{self, AliasTarget};
+use crate::interface;
+use crate::schedule::{self, Schedule};
+use crate::state::{Family, ManagedTarget, RuleKind, State};
+use crate::RunContext;
+use anyhow::{anyhow, Context, Result};
+use ipnetwork::IpNetwork;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Bumped whenever a change to the document would make older builds
+/// misread it.
+pub const EXPORT_VERSION: u64 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub version: u64,
+    pub aliases: BTreeMap<String, AliasTarget>,
+    pub groups: BTreeMap<String, Vec<AliasTarget>>,
+    pub schedules: Vec<Schedule>,
+    pub targets: Vec<ManagedTarget>,
+}
+
+/// Collects everything `import` restores.
+pub fn export() -> Result<Export> {
+    let state = State::load()?;
+    Ok(Export {
+        version: EXPORT_VERSION,
+        aliases: alias::load()?,
+        groups: alias::load_groups()?,
+        schedules: state.schedules,
+        targets: state.targets,
+    })
+}
+
+/// Reads an export, refusing files of another schema version.
+pub fn read(path: &Path) -> Result<Export> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let document: serde_json::Value = serde_json::from_str(&contents)
+        .with_context(|| format!("{} is not JSON", path.display()))?;
+    match document.get("version").and_then(serde_json::Value::as_u64) {
+        Some(EXPORT_VERSION) => {}
+        Some(version) => {
+            return Err(anyhow!(
+                "{} has schema version {}, but this build only reads version {}",
+                path.display(),
+                version,
+                EXPORT_VERSION
+            ))
+        }
+        None => {
+            return Err(anyhow!(
+                "{} has no schema version; is it a `wifi-kicker export`?",
+                path.display()
+            ))
+        }
+    }
+    serde_json::from_value(document).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Reads an imported target as an address or a network with its host bits
+/// clear, and returns it the way `monitor` would have stored it. The file
+/// may have been edited by hand, and its addresses end up in the firewall
+/// rules verbatim.
+fn parse_target_ip(ip: &str) -> Result<String> {
+    if !ip.contains('/') {
+        return ip
+            .parse::<IpAddr>()
+            .map(|addr| addr.to_string())
+            .map_err(|_| anyhow!("{:?} is not an IP address or network", ip));
+    }
+    let network: IpNetwork = ip
+        .parse()
+        .map_err(|_| anyhow!("{:?} is not an IP address or network", ip))?;
+    if network.ip() != network.network() {
+        return Err(anyhow!("{} has host bits set", ip));
+    }
+    Ok(network.to_string())
+}
+
+/// Applies [`parse_target_ip`] and the checks `monitor` and `limit` make
+/// on their targets: no gateway or own address unless `force`, and no
+/// overly wide range.
+fn check_target_ip(ip: &str, force: bool) -> Result<String> {
+    let ip = parse_target_ip(ip)?;
+    interface::check_host_role(&ip, force)?;
+    Ok(ip)
+}
+
+/// Validates every address in `export` before anything is changed,
+/// re-deriving each target's family from its address.
+fn validate(export: &mut Export, force: bool) -> Result<()> {
+    for target in &mut export.targets {
+        target.ip = check_target_ip(&target.ip, force).context("The file was not imported")?;
+        target.family = Family::of(&target.ip);
+    }
+    for schedule in &mut export.schedules {
+        schedule.ip = check_target_ip(&schedule.ip, force).context("The file was not imported")?;
+    }
+    Ok(())
+}
+
+/// Merges `export` into this machine's setup and reloads the rules.
+/// Entries of the same name, or targets of the same IP and mode, are
+/// replaced, so importing a file twice changes nothing the second time.
+/// Bandwidth limits get pipes free on this machine.
+///
+/// Refuses the whole file if any address is malformed or one `monitor`
+/// would refuse; `force` is `monitor --force`. Needs root, except with
+/// `ctx.dry_run`.
+pub fn import(ctx: &RunContext, mut export: Export, force: bool) -> Result<()> {
+    ctx.check_root()?;
+    validate(&mut export, force)?;
+    if !ctx.dry_run {
+        for (name, target) in &export.aliases {
+            alias::set(name, *target)?;
+        }
+        for (name, members) in &export.groups {
+            alias::add_to_group(name, members)?;
+        }
+    }
+
+    let mut state = State::load()?;
+    let previous = state.targets.clone();
+    let replaced: Vec<ManagedTarget> = export
+        .targets
+        .iter()
+        .filter(|t| t.kind == RuleKind::Limit)
+        .flat_map(|t| state.take(&t.ip, Some(RuleKind::Limit)))
+        .collect();
+    ctx.firewall.remove(ctx, &replaced)?;
+    for mut target in export.targets.iter().cloned() {
+        if target.upload_pipe.is_some() {
+            target.upload_pipe = Some(state.free_pipe(&[]));
+        }
+        if target.download_pipe.is_some() {
+            target.download_pipe = Some(state.free_pipe(&target.pipes().collect::<Vec<_>>()));
+        }
+        state.upsert(target);
+    }
+    for imported in &export.schedules {
+        state.schedules.retain(|s| s.ip != imported.ip);
+        state.schedules.push(imported.clone());
+    }
+    // Imported windows that are open right now block straight away
+    if !export.schedules.is_empty() {
+        schedule::enforce(&mut state)?;
+    }
+
+    crate::apply_or_roll_back(ctx, &state, &previous)?;
+    ctx.save(&state)?;
+    if !export.schedules.is_empty() {
+        schedule::install(ctx, &state.schedules)?;
+    }
+
+    info!(
+        "Imported {} alias(es), {} group(s), {} schedule(s) and {} managed target(s)",
+        export.aliases.len(),
+        export.groups.len(),
+        export.schedules.len(),
+        export.targets.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_addresses_and_networks() {
+        assert_eq!(parse_target_ip("192.168.1.42").unwrap(), "192.168.1.42");
+        assert_eq!(parse_target_ip("fd00:0::1").unwrap(), "fd00::1");
+        assert_eq!(parse_target_ip("10.0.4.0/24").unwrap(), "10.0.4.0/24");
+    }
+
+    #[test]
+    fn refuses_rule_text_and_host_bits() {
+        assert!(parse_target_ip("1.2.3.4 to any\npass quick all #").is_err());
+        assert!(parse_target_ip("any").is_err());
+        assert!(parse_target_ip("10.0.4.1/24").is_err());
+        assert!(parse_target_ip("").is_err());
+    }
+
+    #[test]
+    fn refuses_ranges_wider_than_monitor_accepts() {
+        assert!(check_target_ip("0.0.0.0/0", true).is_err());
+        assert!(check_target_ip("::/0", true).is_err());
+    }
+}
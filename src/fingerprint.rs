@@ -0,0 +1,296 @@
+//! Best-effort guess at what kind of device is behind an address, from its
+//! vendor, advertised Bonjour services, ping TTL and a few quick port probes.
+//! Each piece of evidence adds points to one or more classes; the best
+//! scoring class wins if it has enough support.
+
+use crate::device::Device;
+use crate::oui::PRIVATE_MAC_LABEL;
+use crate::probe;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Ports whose state says the most about the device class.
+const FINGERPRINT_PORTS: [u16; 12] = [
+    22, 53, 445, 548, 631, 1883, 3389, 8008, 8009, 8883, 9100, 62078,
+];
+const PORT_TIMEOUT: Duration = Duration::from_millis(300);
+/// Devices fingerprinted at once; each probes all of `FINGERPRINT_PORTS`.
+const MAX_DEVICES_IN_FLIGHT: usize = 8;
+/// A class needs at least this many points to be reported.
+const MIN_SCORE: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceClass {
+    Phone,
+    Laptop,
+    Tv,
+    Printer,
+    Iot,
+    Router,
+    Unknown,
+}
+
+impl DeviceClass {
+    pub fn label(self) -> &'static str {
+        match self {
+            DeviceClass::Phone => "phone",
+            DeviceClass::Laptop => "laptop",
+            DeviceClass::Tv => "TV",
+            DeviceClass::Printer => "printer",
+            DeviceClass::Iot => "IoT",
+            DeviceClass::Router => "router",
+            DeviceClass::Unknown => "unknown",
+        }
+    }
+}
+
+/// Everything known about one device that bears on its class.
+#[derive(Debug)]
+pub struct Evidence<'a> {
+    pub vendor: Option<&'a str>,
+    /// Bonjour service types, e.g. "_ipp._tcp"
+    pub services: &'a BTreeSet<String>,
+    /// TTL of an echo reply
+    pub ttl: Option<u8>,
+    pub open_ports: &'a [u16],
+}
+
+/// (service type, class, points)
+const SERVICE_HINTS: &[(&str, DeviceClass, u32)] = &[
+    ("_ipp._tcp", DeviceClass::Printer, 3),
+    ("_ipps._tcp", DeviceClass::Printer, 3),
+    ("_printer._tcp", DeviceClass::Printer, 3),
+    ("_pdl-datastream._tcp", DeviceClass::Printer, 3),
+    ("_uscan._tcp", DeviceClass::Printer, 2),
+    ("_googlecast._tcp", DeviceClass::Tv, 3),
+    ("_mediaremotetv._tcp", DeviceClass::Tv, 3),
+    ("_amzn-wplay._tcp", DeviceClass::Tv, 3),
+    ("_roku-rcp._tcp", DeviceClass::Tv, 3),
+    ("_airplay._tcp", DeviceClass::Tv, 1),
+    ("_apple-mobdev2._tcp", DeviceClass::Phone, 3),
+    ("_smb._tcp", DeviceClass::Laptop, 2),
+    ("_afpovertcp._tcp", DeviceClass::Laptop, 2),
+    ("_rfb._tcp", DeviceClass::Laptop, 2),
+    ("_ssh._tcp", DeviceClass::Laptop, 1),
+    ("_hap._tcp", DeviceClass::Iot, 3),
+    ("_matter._tcp", DeviceClass::Iot, 3),
+    ("_hue._tcp", DeviceClass::Iot, 3),
+    ("_sonos._tcp", DeviceClass::Iot, 2),
+];
+
+/// (port, class, points)
+const PORT_HINTS: &[(u16, DeviceClass, u32)] = &[
+    (62078, DeviceClass::Phone, 3),
+    (9100, DeviceClass::Printer, 2),
+    (631, DeviceClass::Printer, 2),
+    (8008, DeviceClass::Tv, 2),
+    (8009, DeviceClass::Tv, 2),
+    (53, DeviceClass::Router, 3),
+    (1883, DeviceClass::Iot, 2),
+    (8883, DeviceClass::Iot, 2),
+    (3389, DeviceClass::Laptop, 2),
+    (445, DeviceClass::Laptop, 1),
+    (548, DeviceClass::Laptop, 1),
+    (22, DeviceClass::Laptop, 1),
+];
+
+/// (vendor substring, class, points), matched case-insensitively against
+/// the OUI vendor.
+const VENDOR_HINTS: &[(&str, DeviceClass, u32)] = &[
+    ("espressif", DeviceClass::Iot, 2),
+    ("signify", DeviceClass::Iot, 2),
+    ("nest labs", DeviceClass::Iot, 2),
+    ("ecobee", DeviceClass::Iot, 2),
+    ("belkin", DeviceClass::Iot, 1),
+    ("raspberry pi", DeviceClass::Iot, 1),
+    ("amazon", DeviceClass::Iot, 1),
+    ("sonos", DeviceClass::Iot, 2),
+    ("roku", DeviceClass::Tv, 3),
+    ("lg electronics", DeviceClass::Tv, 1),
+    ("sony", DeviceClass::Tv, 1),
+    ("hewlett packard", DeviceClass::Printer, 1),
+    ("ubiquiti", DeviceClass::Router, 2),
+    ("netgear", DeviceClass::Router, 2),
+    ("tp-link", DeviceClass::Router, 1),
+    ("asustek", DeviceClass::Router, 1),
+    ("cisco", DeviceClass::Router, 2),
+    ("intel", DeviceClass::Laptop, 2),
+    ("dell", DeviceClass::Laptop, 2),
+    ("microsoft", DeviceClass::Laptop, 1),
+    ("xiaomi", DeviceClass::Phone, 1),
+    ("huawei", DeviceClass::Phone, 1),
+    ("samsung", DeviceClass::Phone, 1),
+];
+
+/// Classifies a device from `evidence`. Ties and weak evidence give
+/// `Unknown`.
+pub fn classify(evidence: &Evidence) -> DeviceClass {
+    let mut scores: HashMap<DeviceClass, u32> = HashMap::new();
+    let mut add = |class, points| *scores.entry(class).or_default() += points;
+
+    for service in evidence.services {
+        for (hint, class, points) in SERVICE_HINTS {
+            if service.eq_ignore_ascii_case(hint) {
+                add(*class, *points);
+            }
+        }
+    }
+    for port in evidence.open_ports {
+        for (hint, class, points) in PORT_HINTS {
+            if port == hint {
+                add(*class, *points);
+            }
+        }
+    }
+    if let Some(vendor) = evidence.vendor {
+        if vendor == PRIVATE_MAC_LABEL {
+            // Phones randomize their Wi-Fi MAC per network by default
+            add(DeviceClass::Phone, 1);
+        }
+        let vendor = vendor.to_lowercase();
+        for (hint, class, points) in VENDOR_HINTS {
+            if vendor.contains(hint) {
+                add(*class, *points);
+            }
+        }
+    }
+    match evidence.ttl {
+        // Windows starts at 128, network gear usually at 255
+        Some(65..=128) => add(DeviceClass::Laptop, 1),
+        Some(129..) => add(DeviceClass::Router, 1),
+        _ => {}
+    }
+
+    let best = scores.values().copied().max().unwrap_or(0);
+    let mut leaders = scores.iter().filter(|(_, score)| **score == best);
+    match (leaders.next(), leaders.next()) {
+        (Some((class, _)), None) if best >= MIN_SCORE => *class,
+        _ => DeviceClass::Unknown,
+    }
+}
+
+/// Fills in `class` for every device not already in `cache`, probing them
+/// concurrently. Uses the TTL found by the ping pass.
+pub async fn fingerprint_devices(
+    devices: &mut [Device],
+    cache: &mut HashMap<Ipv4Addr, DeviceClass>,
+) {
+    let semaphore = Arc::new(Semaphore::new(MAX_DEVICES_IN_FLIGHT));
+    let mut tasks = JoinSet::new();
+    for device in devices
+        .iter()
+        .filter(|d| d.up && !cache.contains_key(&d.ip))
+    {
+        let semaphore = Arc::clone(&semaphore);
+        let (ip, vendor, services, ttl) = (
+            device.ip,
+            device.vendor.clone(),
+            device.services.clone(),
+            device.ttl,
+        );
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let open_ports = probe::scan_ports(ip, &FINGERPRINT_PORTS, PORT_TIMEOUT).await;
+            let class = classify(&Evidence {
+                vendor: vendor.as_deref(),
+                services: &services,
+                ttl,
+                open_ports: &open_ports,
+            });
+            (ip, class)
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Ok((ip, class)) = result {
+            cache.insert(ip, class);
+        }
+    }
+
+    for device in devices.iter_mut() {
+        device.class = cache.get(&device.ip).copied();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class_of(
+        vendor: Option<&str>,
+        services: &[&str],
+        ttl: Option<u8>,
+        ports: &[u16],
+    ) -> DeviceClass {
+        classify(&Evidence {
+            vendor,
+            services: &services.iter().map(|s| s.to_string()).collect(),
+            ttl,
+            open_ports: ports,
+        })
+    }
+
+    #[test]
+    fn classifies_typical_devices() {
+        let printer = class_of(
+            Some("Hewlett Packard"),
+            &["_ipp._tcp", "_uscan._tcp"],
+            Some(64),
+            &[631, 9100],
+        );
+        assert_eq!(printer, DeviceClass::Printer);
+
+        let apple_tv = class_of(
+            Some("Apple, Inc."),
+            &["_airplay._tcp", "_mediaremotetv._tcp"],
+            Some(64),
+            &[],
+        );
+        assert_eq!(apple_tv, DeviceClass::Tv);
+
+        let iphone = class_of(Some(PRIVATE_MAC_LABEL), &[], Some(64), &[62078]);
+        assert_eq!(iphone, DeviceClass::Phone);
+
+        let router = class_of(Some("NETGEAR"), &[], Some(255), &[22, 53]);
+        assert_eq!(router, DeviceClass::Router);
+
+        let windows = class_of(Some("Intel Corporate"), &[], Some(128), &[445, 3389]);
+        assert_eq!(windows, DeviceClass::Laptop);
+
+        let plug = class_of(Some("Espressif Inc."), &[], Some(64), &[1883]);
+        assert_eq!(plug, DeviceClass::Iot);
+    }
+
+    #[test]
+    fn service_types_match_case_insensitively() {
+        assert_eq!(
+            class_of(None, &["_IPP._tcp"], None, &[]),
+            DeviceClass::Printer
+        );
+    }
+
+    #[test]
+    fn weak_evidence_is_unknown() {
+        assert_eq!(class_of(None, &[], None, &[]), DeviceClass::Unknown);
+        assert_eq!(
+            class_of(Some("Apple, Inc."), &[], Some(64), &[]),
+            DeviceClass::Unknown
+        );
+        // One open SSH port is a single point
+        assert_eq!(class_of(None, &[], Some(64), &[22]), DeviceClass::Unknown);
+    }
+
+    #[test]
+    fn ties_are_unknown() {
+        // 631 says printer and 8008 says TV, two points each
+        assert_eq!(
+            class_of(None, &[], None, &[631, 8008]),
+            DeviceClass::Unknown
+        );
+    }
+}
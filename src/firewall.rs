@@ -0,0 +1,1008 @@
+//! Platform firewall backends: PF with dummynet pipes on macOS, nftables on
+//! Linux. Both regenerate the complete wifi-kicker ruleset from the managed
+//! targets on every change, into a PF anchor or an nftables table of its
+//! own, so other rules are never touched.
+
+use crate::runner::{self, CommandRunner};
+use crate::state::{ManagedTarget, RuleKind, State};
+use crate::{backup, rules, store, RunContext};
+use anyhow::{anyhow, Context, Result};
+use ipnetwork::IpNetwork;
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Staged in the data directory, see `store::work_file`.
+pub(crate) const PF_RULES_FILE: &str = "pf.rules";
+/// The PF anchor our rules are loaded into, leaving the main ruleset and
+/// other anchors alone.
+const PF_ANCHOR: &str = "com.wifi-kicker";
+const PF_ANCHOR_FILE: &str = "/etc/pf.anchors/com.wifi-kicker";
+const NFT_RULES_FILE: &str = "wifi-kicker.nft";
+/// Ruleset of persistent targets, for inclusion from /etc/nftables.conf.
+const NFT_PERSISTENT_FILE: &str = "/etc/wifi-kicker.nft";
+const PF_CONF: &str = "/etc/pf.conf";
+const NFT_CONF: &str = "/etc/nftables.conf";
+
+pub trait Firewall {
+    /// External programs the backend runs.
+    fn tools(&self) -> &'static [&'static str];
+
+    /// Installs the rules for exactly `targets`, replacing whatever
+    /// wifi-kicker installed before.
+    fn apply(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()>;
+
+    /// Releases what `removed` held besides its rules, such as dummynet
+    /// pipes. The remaining targets still need to be applied afterwards.
+    fn remove(&self, ctx: &RunContext, removed: &[ManagedTarget]) -> Result<()>;
+
+    /// Prints the rules currently loaded.
+    fn show(&self, runner: &dyn CommandRunner) -> Result<()>;
+
+    /// Live connection states tracked by the firewall.
+    fn states(&self, runner: &dyn CommandRunner) -> Result<Vec<PfState>>;
+
+    /// The live ruleset as listed by the firewall, empty when none of our
+    /// rules are loaded.
+    fn loaded_rules(&self, runner: &dyn CommandRunner) -> Result<String>;
+
+    /// Whether the rules generated for `target` appear in `loaded`, as
+    /// returned by `loaded_rules`.
+    fn is_loaded(&self, target: &ManagedTarget, loaded: &str) -> bool;
+
+    /// Whether the loaded ruleset still covers every one of `targets`, i.e.
+    /// nothing flushed it since it was applied.
+    fn is_applied(&self, runner: &dyn CommandRunner, targets: &[ManagedTarget]) -> Result<bool>;
+
+    /// Traffic counted by the loaded rules, keyed by target IP.
+    fn usage(&self, runner: &dyn CommandRunner) -> Result<BTreeMap<String, Usage>>;
+
+    /// Zeroes the counters behind `usage`; `targets` are the managed
+    /// devices, for backends that reload the rules to do so.
+    fn reset_usage(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()>;
+
+    /// Unloads the rules of `targets` and deletes every file and config
+    /// line wifi-kicker added, leaving other rules as they were.
+    fn uninstall(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()>;
+}
+
+/// True if every rule in `expected` has a counterpart in `loaded`: a line
+/// containing all of its words, so `10.0.0.1` never matches `10.0.0.12`.
+/// `{tcp udp}` lists are skipped since pfctl expands them into one rule per
+/// protocol, and so are comments.
+fn rules_loaded<'a>(expected: impl IntoIterator<Item = &'a str>, loaded: &str) -> bool {
+    let loaded: Vec<Vec<&str>> = loaded
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    expected
+        .into_iter()
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty() && !rule.starts_with('#'))
+        .all(|rule| {
+            let mut words = Vec::new();
+            let mut in_list = false;
+            for word in rule.split_whitespace() {
+                in_list |= word.starts_with('{');
+                if !in_list {
+                    words.push(word);
+                }
+                in_list &= !word.ends_with('}');
+            }
+            loaded
+                .iter()
+                .any(|line| words.iter().all(|word| line.contains(word)))
+        })
+}
+
+/// Whether the PF rules generated for `target` appear in `loaded`, the
+/// output of `pfctl -a com.wifi-kicker -sr` and `-s dummynet`.
+pub fn rule_is_loaded(target: &ManagedTarget, loaded: &str) -> bool {
+    rules_loaded(rules::target_rules(target).lines(), loaded)
+}
+
+/// Picks the backend for the platform we were built for.
+pub fn detect() -> Box<dyn Firewall> {
+    if cfg!(target_os = "linux") {
+        Box::new(NftFirewall)
+    } else {
+        Box::new(PfFirewall::default())
+    }
+}
+
+/// One connection state from `pfctl -ss -v`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PfState {
+    pub proto: String,
+    /// Source as `address:port`
+    pub src: String,
+    /// Destination as `address:port`
+    pub dst: String,
+    /// Packets in both directions
+    pub packets: u64,
+    /// Bytes in both directions
+    pub bytes: u64,
+}
+
+impl PfState {
+    /// The address part of `src` or `dst`; IPv6 endpoints are written
+    /// `addr[port]`, IPv4 ones `addr:port`.
+    fn address(endpoint: &str) -> &str {
+        match endpoint.split_once('[') {
+            Some((addr, _)) => addr,
+            None => endpoint.rsplit_once(':').map_or(endpoint, |(addr, _)| addr),
+        }
+    }
+
+    /// Whether `ip`, an address or a network, is either end of the
+    /// connection.
+    pub fn involves(&self, ip: &str) -> bool {
+        let Ok(network) = ip.parse::<IpNetwork>() else {
+            return false;
+        };
+        [&self.src, &self.dst].into_iter().any(|endpoint| {
+            Self::address(endpoint)
+                .parse()
+                .is_ok_and(|addr| network.contains(addr))
+        })
+    }
+}
+
+/// Traffic through the rules of one device since they were loaded or the
+/// counters were reset. `in` is what the device received, `out` what it
+/// sent; for blocked devices these are the dropped attempts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub in_bytes: u64,
+    pub out_bytes: u64,
+    pub in_packets: u64,
+    pub out_packets: u64,
+}
+
+impl Usage {
+    fn add(&mut self, sent: bool, packets: u64, bytes: u64) {
+        if sent {
+            self.out_packets += packets;
+            self.out_bytes += bytes;
+        } else {
+            self.in_packets += packets;
+            self.in_bytes += bytes;
+        }
+    }
+}
+
+/// The IP in a `wifi-kicker:<ip>` label or comment on `line`.
+fn labelled_ip(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("\"wifi-kicker:")?;
+    rest.split('"').next()
+}
+
+/// The number following `key` in a whitespace-separated `line`.
+fn counter_after(line: &str, key: &str) -> Option<u64> {
+    let mut words = line.split_whitespace();
+    words.find(|word| *word == key)?;
+    words.next()?.parse().ok()
+}
+
+/// Parses verbose rule listings (`pfctl -sr -v`), where each of our rules
+/// is followed by its counters:
+///
+/// ```text
+/// block drop in inet proto tcp from 10.0.0.9 to any label "wifi-kicker:10.0.0.9"
+///   [ Evaluations: 1523      Packets: 12        Bytes: 720         States: 0     ]
+/// ```
+///
+/// Rules matching `from <ip>` count what the device sent, `to <ip>` what it
+/// received.
+pub fn parse_pf_usage(output: &str) -> BTreeMap<String, Usage> {
+    let mut usage: BTreeMap<String, Usage> = BTreeMap::new();
+    let mut current: Option<(String, bool)> = None;
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            current = labelled_ip(line).map(|ip| {
+                let from = line.split_whitespace().skip_while(|w| *w != "from").nth(1);
+                (ip.to_string(), from == Some(ip))
+            });
+            continue;
+        }
+        let Some((ip, sent)) = &current else {
+            continue;
+        };
+        if let (Some(packets), Some(bytes)) = (
+            counter_after(line, "Packets:"),
+            counter_after(line, "Bytes:"),
+        ) {
+            usage
+                .entry(ip.clone())
+                .or_default()
+                .add(*sent, packets, bytes);
+        }
+    }
+    usage
+}
+
+/// Parses `nft list table` output, where counters are inline:
+///
+/// ```text
+/// ip saddr 10.0.0.9 counter packets 12 bytes 720 drop comment "wifi-kicker:10.0.0.9"
+/// ```
+pub fn parse_nft_usage(output: &str) -> BTreeMap<String, Usage> {
+    let mut usage: BTreeMap<String, Usage> = BTreeMap::new();
+    for line in output.lines() {
+        let Some(ip) = labelled_ip(line) else {
+            continue;
+        };
+        if let (Some(packets), Some(bytes)) =
+            (counter_after(line, "packets"), counter_after(line, "bytes"))
+        {
+            usage
+                .entry(ip.to_string())
+                .or_default()
+                .add(line.contains(" saddr "), packets, bytes);
+        }
+    }
+    usage
+}
+
+/// Sums an `in:out` counter pair such as `10:12`.
+fn parse_pair(field: &str) -> Option<u64> {
+    let (a, b) = field.trim_end_matches(',').split_once(':')?;
+    Some(a.parse::<u64>().ok()? + b.parse::<u64>().ok()?)
+}
+
+/// Parses verbose state listings:
+///
+/// ```text
+/// all tcp 192.168.1.23:52344 -> 17.253.144.10:443       ESTABLISHED:ESTABLISHED
+///    age 00:01:02, expires in 23:59:58, 10:12 pkts, 1234:5678 bytes, rule 3
+/// ```
+///
+/// Direction arrows point either way (`<-` for inbound) and NAT states show
+/// an extra `(translated)` endpoint, which is skipped.
+pub fn parse_pf_states(output: &str) -> Vec<PfState> {
+    let mut states: Vec<PfState> = Vec::new();
+    for line in output.lines() {
+        if line.starts_with(char::is_whitespace) {
+            let Some(state) = states.last_mut() else {
+                continue;
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            for pair in fields.windows(2) {
+                match pair[1].trim_end_matches(',') {
+                    "pkts" => state.packets = parse_pair(pair[0]).unwrap_or(0),
+                    "bytes" => state.bytes = parse_pair(pair[0]).unwrap_or(0),
+                    _ => {}
+                }
+            }
+            continue;
+        }
+
+        let fields: Vec<&str> = line
+            .split_whitespace()
+            .filter(|f| !f.starts_with('('))
+            .collect();
+        let [_, proto, a, arrow, b, ..] = fields.as_slice() else {
+            continue;
+        };
+        let (src, dst) = match *arrow {
+            "->" => (a, b),
+            "<-" => (b, a),
+            _ => continue,
+        };
+        states.push(PfState {
+            proto: proto.to_string(),
+            src: src.to_string(),
+            dst: dst.to_string(),
+            packets: 0,
+            bytes: 0,
+        });
+    }
+    states
+}
+
+/// Parses the `Status: Enabled` / `Status: Disabled` line of `pfctl -s info`.
+fn parse_pf_status(info: &str) -> Option<bool> {
+    let status = info
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Status:"))?;
+    match status.split_whitespace().next()? {
+        "Enabled" => Some(true),
+        "Disabled" => Some(false),
+        _ => None,
+    }
+}
+
+/// Whether PF is turned on; rules are inert while it's off.
+pub fn pf_enabled(runner: &dyn CommandRunner) -> Result<bool> {
+    let info = runner.run("pfctl", &["-s", "info"])?;
+    let info = String::from_utf8_lossy(&info.stdout);
+    parse_pf_status(&info).ok_or_else(|| anyhow!("No status line in pfctl -s info output"))
+}
+
+/// Turns PF on unless it already is. `pfctl -e` fails when PF is enabled,
+/// so its status is checked first rather than ignoring every failure.
+fn enable_pf(ctx: &RunContext) -> Result<()> {
+    // Dry runs don't read anything back
+    if !ctx.dry_run && pf_enabled(ctx.runner)? {
+        return Ok(());
+    }
+    match runner::run_retry(ctx.runner, "pfctl", &["-e"], runner::DEFAULT_ATTEMPTS) {
+        // Something else enabled it in the meantime
+        Err(e) if format!("{:#}", e).contains("already enabled") => Ok(()),
+        result => result.map(drop),
+    }
+}
+
+/// The pf.conf lines that hook our anchor into the main ruleset and fill
+/// it with the persistent rules at boot.
+const PF_CONF_ANCHORS: [&str; 3] = [
+    "dummynet-anchor \"com.wifi-kicker\"",
+    "anchor \"com.wifi-kicker\"",
+    "load anchor \"com.wifi-kicker\" from \"/etc/pf.anchors/com.wifi-kicker\"",
+];
+
+/// Statements that pf requires ahead of filter rules (options,
+/// normalization, queueing, translation), plus the other anchors, which
+/// ours should follow.
+const PF_CONF_PRELUDE: [&str; 13] = [
+    "set",
+    "scrub",
+    "scrub-anchor",
+    "altq",
+    "queue",
+    "nat",
+    "nat-anchor",
+    "rdr",
+    "rdr-anchor",
+    "binat",
+    "binat-anchor",
+    "dummynet-anchor",
+    "anchor",
+];
+
+/// A pf.conf line without its comment and with whitespace collapsed, so
+/// `anchor   "x" # note` compares equal to `anchor "x"`.
+fn pf_conf_statement(line: &str) -> String {
+    let code = line.split('#').next().unwrap_or("");
+    code.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Returns `pf_conf` with whichever of our anchor lines are missing, or
+/// `None` if all are active already. Commented-out copies don't count. The
+/// lines go after the last prelude statement (see `PF_CONF_PRELUDE`), or at
+/// the end when there is none, keeping pf's required statement order.
+fn add_anchors(pf_conf: &str) -> Option<String> {
+    let statements: Vec<String> = pf_conf.lines().map(pf_conf_statement).collect();
+    let missing: Vec<&str> = PF_CONF_ANCHORS
+        .into_iter()
+        .filter(|anchor| !statements.iter().any(|s| s == anchor))
+        .collect();
+    if missing.is_empty() {
+        return None;
+    }
+
+    let mut lines: Vec<&str> = pf_conf.lines().collect();
+    let position = statements
+        .iter()
+        .rposition(|s| {
+            let keyword = s.split(' ').next().unwrap_or("");
+            PF_CONF_PRELUDE.contains(&keyword)
+        })
+        .map_or(lines.len(), |last| last + 1);
+    lines.splice(position..position, missing);
+
+    let mut conf = lines.join("\n");
+    conf.push('\n');
+    Some(conf)
+}
+
+/// Returns `conf` without the lines whose statement is one of `ours`, or
+/// `None` if it has none of them. Every other line is kept as it is.
+fn strip_lines(conf: &str, ours: &[&str]) -> Option<String> {
+    let kept: Vec<&str> = conf
+        .lines()
+        .filter(|line| !ours.contains(&pf_conf_statement(line).as_str()))
+        .collect();
+    if kept.len() == conf.lines().count() {
+        return None;
+    }
+    let mut conf = kept.join("\n");
+    conf.push('\n');
+    Some(conf)
+}
+
+pub struct PfFirewall {
+    /// The main configuration, where the anchor is hooked in
+    pub pf_conf: String,
+    /// The anchor file pf.conf loads the persistent rules from at boot
+    pub anchor_file: String,
+}
+
+impl Default for PfFirewall {
+    fn default() -> Self {
+        PfFirewall {
+            pf_conf: PF_CONF.to_string(),
+            anchor_file: PF_ANCHOR_FILE.to_string(),
+        }
+    }
+}
+
+impl PfFirewall {
+    /// Creates (or reconfigures) dummynet pipe `pipe` with a bandwidth cap.
+    fn configure_pipe(ctx: &RunContext, pipe: u32, kbytes_per_sec: u32) -> Result<()> {
+        // dnctl takes bits, the CLI takes bytes
+        let bandwidth = format!("{}Kbit/s", u64::from(kbytes_per_sec) * 8);
+        ctx.runner.run(
+            "dnctl",
+            &["pipe", &pipe.to_string(), "config", "bw", &bandwidth],
+        )?;
+        Ok(())
+    }
+
+    /// Writes the ruleset for all managed targets and has pfctl parse it
+    /// as our anchor. Rules of persistent targets are also installed as the
+    /// anchor file pf.conf loads at boot. It is kept, empty, once the last
+    /// of them is gone, since pf.conf fails to load without it.
+    fn save_rules(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()> {
+        let rules_file = store::work_file(PF_RULES_FILE)?;
+        ctx.write(&rules_file, &rules::render(targets))?;
+        // Parse without loading, so bad rules never reach the live firewall
+        // or /etc
+        ctx.runner
+            .run("pfctl", &["-n", "-a", PF_ANCHOR, "-f", &rules_file])
+            .context("pfctl rejected the generated rules; nothing was loaded")?;
+
+        let persistent: Vec<&ManagedTarget> = targets.iter().filter(|t| t.persistent).collect();
+        // Written before `hook_anchor` adds the line loading it
+        let exists = Path::new(&self.anchor_file).exists();
+        if targets.is_empty() && !exists {
+            return Ok(());
+        }
+        if !exists {
+            // Keep the configuration from before we first touch /etc
+            backup::ensure_initial_backup(ctx)?;
+        }
+
+        // Save to a permanent location for persistence
+        ctx.install(
+            "pf.anchor.rules",
+            &rules::render(persistent),
+            &self.anchor_file,
+        )?;
+        Ok(())
+    }
+
+    /// Makes the main ruleset evaluate our anchor: adds the hooks to
+    /// pf.conf if they are missing, and reloads pf.conf when they aren't
+    /// loaded yet. That replaces the main ruleset with the user's own
+    /// configuration, and leaves the rules in every anchor as they are.
+    fn hook_anchor(&self, ctx: &RunContext) -> Result<()> {
+        let pf_conf = fs::read_to_string(&self.pf_conf)
+            .with_context(|| format!("Failed to read {}", self.pf_conf))?;
+        let edited = match add_anchors(&pf_conf) {
+            Some(new_conf) => {
+                // Keep the configuration from before we first touch /etc
+                backup::ensure_initial_backup(ctx)?;
+                ctx.install("pf.conf", &new_conf, &self.pf_conf)?;
+                true
+            }
+            None => false,
+        };
+        // Dry runs don't read anything back
+        if edited || (!ctx.dry_run && !anchor_hooked(ctx.runner)?) {
+            runner::run_retry(
+                ctx.runner,
+                "pfctl",
+                &["-f", &self.pf_conf],
+                runner::DEFAULT_ATTEMPTS,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the loaded main ruleset has our filter anchor hook.
+fn anchor_hooked(runner: &dyn CommandRunner) -> Result<bool> {
+    let main = runner.run("pfctl", &["-sr"])?;
+    let hook = format!("anchor \"{}\"", PF_ANCHOR);
+    Ok(String::from_utf8_lossy(&main.stdout)
+        .lines()
+        .any(|line| line.trim() == hook || line.trim().starts_with(&format!("{} ", hook))))
+}
+
+impl Firewall for PfFirewall {
+    fn tools(&self) -> &'static [&'static str] {
+        &["pfctl", "dnctl"]
+    }
+
+    fn apply(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()> {
+        // Pipes are reconfigured every time, which also recreates them if
+        // they were lost while the rules survived
+        for target in targets.iter().filter(|t| t.kind == RuleKind::Limit) {
+            for (pipe, rate) in [
+                (target.upload_pipe, target.upload),
+                (target.download_pipe, target.download),
+            ] {
+                if let (Some(pipe), Some(rate)) = (pipe, rate) {
+                    Self::configure_pipe(ctx, pipe, rate)?;
+                }
+            }
+        }
+        self.save_rules(ctx, targets)?;
+
+        enable_pf(ctx)?;
+        // With no targets left the anchor is only emptied, so there is no
+        // need to hook it in
+        if !targets.is_empty() {
+            self.hook_anchor(ctx)?;
+        }
+        runner::run_retry(
+            ctx.runner,
+            "pfctl",
+            &["-a", PF_ANCHOR, "-f", &store::work_file(PF_RULES_FILE)?],
+            runner::DEFAULT_ATTEMPTS,
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, ctx: &RunContext, removed: &[ManagedTarget]) -> Result<()> {
+        for pipe in removed.iter().flat_map(|t| t.pipes()) {
+            ctx.runner
+                .run("dnctl", &["pipe", &pipe.to_string(), "delete"])?;
+        }
+        Ok(())
+    }
+
+    fn show(&self, runner: &dyn CommandRunner) -> Result<()> {
+        let enabled = pf_enabled(runner)?;
+        println!(
+            "PF status: {}",
+            if enabled { "Enabled" } else { "Disabled" }
+        );
+        if !enabled && !State::load()?.targets.is_empty() {
+            println!("Warning: PF is disabled, so the rules for managed devices have no effect. Enable it with `sudo pfctl -e`.");
+        }
+
+        println!("\nCurrent PF rules:");
+        let rules_output = runner.run("pfctl", &["-sr"])?;
+        println!("{}", String::from_utf8_lossy(&rules_output.stdout));
+
+        println!("\nwifi-kicker rules (anchor {}):", PF_ANCHOR);
+        print!("{}", self.loaded_rules(runner)?);
+
+        println!("\nCurrent states:");
+        let states_output = runner.run("pfctl", &["-ss"])?;
+        println!("{}", String::from_utf8_lossy(&states_output.stdout));
+        Ok(())
+    }
+
+    fn states(&self, runner: &dyn CommandRunner) -> Result<Vec<PfState>> {
+        let output = runner.run("pfctl", &["-ss", "-v"])?;
+        Ok(parse_pf_states(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn loaded_rules(&self, runner: &dyn CommandRunner) -> Result<String> {
+        // Block rules are listed with -sr, dummynet rules separately
+        let mut loaded =
+            String::from_utf8_lossy(&runner.run("pfctl", &["-a", PF_ANCHOR, "-sr"])?.stdout)
+                .into_owned();
+        loaded.push_str(&String::from_utf8_lossy(
+            &runner
+                .run("pfctl", &["-a", PF_ANCHOR, "-s", "dummynet"])?
+                .stdout,
+        ));
+        Ok(loaded)
+    }
+
+    fn is_loaded(&self, target: &ManagedTarget, loaded: &str) -> bool {
+        rule_is_loaded(target, loaded)
+    }
+
+    fn is_applied(&self, runner: &dyn CommandRunner, targets: &[ManagedTarget]) -> Result<bool> {
+        if targets.is_empty() {
+            return Ok(true);
+        }
+        // Unhooked, the anchor's rules are loaded but never evaluated
+        if !pf_enabled(runner)? || !anchor_hooked(runner)? {
+            return Ok(false);
+        }
+        let loaded = self.loaded_rules(runner)?;
+        Ok(targets.iter().all(|t| self.is_loaded(t, &loaded)))
+    }
+
+    fn usage(&self, runner: &dyn CommandRunner) -> Result<BTreeMap<String, Usage>> {
+        let mut rules =
+            String::from_utf8_lossy(&runner.run("pfctl", &["-a", PF_ANCHOR, "-sr", "-v"])?.stdout)
+                .into_owned();
+        rules.push_str(&String::from_utf8_lossy(
+            &runner
+                .run("pfctl", &["-a", PF_ANCHOR, "-s", "dummynet", "-v"])?
+                .stdout,
+        ));
+        Ok(parse_pf_usage(&rules))
+    }
+
+    fn reset_usage(&self, ctx: &RunContext, _targets: &[ManagedTarget]) -> Result<()> {
+        // Zeroes the counters of every loaded rule, not just ours
+        ctx.runner.run("pfctl", &["-z"])?;
+        Ok(())
+    }
+
+    fn uninstall(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()> {
+        self.remove(ctx, targets)?;
+        runner::run_retry(
+            ctx.runner,
+            "pfctl",
+            &["-a", PF_ANCHOR, "-F", "all"],
+            runner::DEFAULT_ATTEMPTS,
+        )?;
+        if Path::new(&self.pf_conf).exists() {
+            let pf_conf = fs::read_to_string(&self.pf_conf)?;
+            if let Some(new_conf) = strip_lines(&pf_conf, &PF_CONF_ANCHORS) {
+                ctx.install("pf.conf", &new_conf, &self.pf_conf)?;
+                // Drops the hooks from the loaded main ruleset too
+                runner::run_retry(
+                    ctx.runner,
+                    "pfctl",
+                    &["-f", &self.pf_conf],
+                    runner::DEFAULT_ATTEMPTS,
+                )?;
+            }
+        }
+        // Only once pf.conf no longer loads it
+        if Path::new(&self.anchor_file).exists() {
+            ctx.runner.run("rm", &[&self.anchor_file])?;
+        }
+        Ok(())
+    }
+}
+
+pub struct NftFirewall;
+
+impl Firewall for NftFirewall {
+    fn tools(&self) -> &'static [&'static str] {
+        &["nft"]
+    }
+
+    fn apply(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()> {
+        let rules_file = store::work_file(NFT_RULES_FILE)?;
+        ctx.write(&rules_file, &rules::render_nft(targets))?;
+        ctx.runner.run("nft", &["-f", &rules_file])?;
+
+        let persistent: Vec<&ManagedTarget> = targets.iter().filter(|t| t.persistent).collect();
+        if persistent.is_empty() {
+            if Path::new(NFT_PERSISTENT_FILE).exists() {
+                ctx.runner.run("rm", &[NFT_PERSISTENT_FILE])?;
+            }
+            return Ok(());
+        }
+        ctx.install(
+            "wifi-kicker.persistent.nft",
+            &rules::render_nft(persistent),
+            NFT_PERSISTENT_FILE,
+        )?;
+        if !fs::read_to_string(NFT_CONF).is_ok_and(|conf| conf.contains(NFT_PERSISTENT_FILE)) {
+            info!(
+                "Add `include \"{}\"` to {} to load persistent rules at boot",
+                NFT_PERSISTENT_FILE, NFT_CONF
+            );
+        }
+        Ok(())
+    }
+
+    fn remove(&self, _ctx: &RunContext, _removed: &[ManagedTarget]) -> Result<()> {
+        // Limits are policers inside the table, so nothing outlives the rules
+        Ok(())
+    }
+
+    fn show(&self, runner: &dyn CommandRunner) -> Result<()> {
+        println!("Current nftables rules:");
+        match runner.run("nft", &["list", "table", "inet", rules::NFT_TABLE]) {
+            Ok(output) => println!("{}", String::from_utf8_lossy(&output.stdout)),
+            Err(_) => println!("No wifi-kicker rules are loaded."),
+        }
+        Ok(())
+    }
+
+    fn states(&self, _runner: &dyn CommandRunner) -> Result<Vec<PfState>> {
+        // Connection tracking lives in conntrack, outside nftables
+        Ok(Vec::new())
+    }
+
+    fn loaded_rules(&self, runner: &dyn CommandRunner) -> Result<String> {
+        // Listing fails when the table is gone
+        Ok(runner
+            .run("nft", &["list", "table", "inet", rules::NFT_TABLE])
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+            .unwrap_or_default())
+    }
+
+    fn is_loaded(&self, target: &ManagedTarget, loaded: &str) -> bool {
+        let statements = rules::nft_target_rules(target);
+        rules_loaded(statements.iter().map(String::as_str), loaded)
+    }
+
+    fn is_applied(&self, runner: &dyn CommandRunner, targets: &[ManagedTarget]) -> Result<bool> {
+        if targets.is_empty() {
+            return Ok(true);
+        }
+        let loaded = self.loaded_rules(runner)?;
+        Ok(targets.iter().all(|t| self.is_loaded(t, &loaded)))
+    }
+
+    fn usage(&self, runner: &dyn CommandRunner) -> Result<BTreeMap<String, Usage>> {
+        Ok(parse_nft_usage(&self.loaded_rules(runner)?))
+    }
+
+    fn reset_usage(&self, ctx: &RunContext, targets: &[ManagedTarget]) -> Result<()> {
+        // Recreating the table starts its counters from zero
+        self.apply(ctx, targets)
+    }
+
+    fn uninstall(&self, ctx: &RunContext, _targets: &[ManagedTarget]) -> Result<()> {
+        match ctx
+            .runner
+            .run("nft", &["delete", "table", "inet", rules::NFT_TABLE])
+        {
+            // Nothing loaded
+            Err(e) if format!("{:#}", e).contains("No such file or directory") => {}
+            result => {
+                result?;
+            }
+        }
+        if Path::new(NFT_PERSISTENT_FILE).exists() {
+            ctx.runner.run("rm", &[NFT_PERSISTENT_FILE])?;
+        }
+        // The include `apply` suggested would fail at boot without the file
+        let include = format!("include \"{}\"", NFT_PERSISTENT_FILE);
+        let conf = fs::read_to_string(NFT_CONF).unwrap_or_default();
+        if let Some(new_conf) = strip_lines(&conf, &[&include]) {
+            ctx.install("nftables.conf", &new_conf, NFT_CONF)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+    use crate::MockRunner;
+    use std::process::Output;
+
+    /// The stock macOS pf.conf.
+    const APPLE_PF_CONF: &str = "\
+#
+# com.apple anchor point
+#
+scrub-anchor \"com.apple/*\"
+nat-anchor \"com.apple/*\"
+rdr-anchor \"com.apple/*\"
+dummynet-anchor \"com.apple/*\"
+anchor \"com.apple/*\"
+load anchor \"com.apple\" from \"/etc/pf.anchors/com.apple\"
+";
+
+    #[test]
+    fn anchors_follow_the_prelude() {
+        let conf = add_anchors(APPLE_PF_CONF).unwrap();
+        let lines: Vec<&str> = conf.lines().collect();
+        assert_eq!(
+            lines[8..12],
+            [
+                "dummynet-anchor \"com.wifi-kicker\"",
+                "anchor \"com.wifi-kicker\"",
+                "load anchor \"com.wifi-kicker\" from \"/etc/pf.anchors/com.wifi-kicker\"",
+                "load anchor \"com.apple\" from \"/etc/pf.anchors/com.apple\"",
+            ]
+        );
+        assert_eq!(add_anchors(&conf), None);
+    }
+
+    #[test]
+    fn anchors_go_after_options_and_translation_but_before_filter_rules() {
+        let conf = "set skip on lo0\nnat on en0 from 10.0.0.0/24 to any -> (en0)\npass in all\n";
+        assert_eq!(
+            add_anchors(conf).unwrap(),
+            "set skip on lo0\n\
+             nat on en0 from 10.0.0.0/24 to any -> (en0)\n\
+             dummynet-anchor \"com.wifi-kicker\"\n\
+             anchor \"com.wifi-kicker\"\n\
+             load anchor \"com.wifi-kicker\" from \"/etc/pf.anchors/com.wifi-kicker\"\n\
+             pass in all\n"
+        );
+        // Without a prelude they go at the end
+        assert!(add_anchors("pass all\n")
+            .unwrap()
+            .starts_with("pass all\ndummynet-anchor"));
+    }
+
+    #[test]
+    fn commented_copies_dont_count() {
+        let conf = "#anchor \"com.wifi-kicker\"\n# dummynet-anchor \"com.wifi-kicker\"\n";
+        let added = add_anchors(conf).unwrap();
+        assert!(added.starts_with(conf));
+        assert_eq!(added.lines().count(), 5);
+    }
+
+    #[test]
+    fn odd_whitespace_and_trailing_comments_still_count() {
+        let conf = "dummynet-anchor   \"com.wifi-kicker\"\n\
+                    \tanchor \"com.wifi-kicker\"   # ours\n\
+                    load  anchor \"com.wifi-kicker\"  from \"/etc/pf.anchors/com.wifi-kicker\"\n";
+        assert_eq!(add_anchors(conf), None);
+    }
+
+    #[test]
+    fn only_the_missing_lines_are_added() {
+        let conf = "anchor \"com.apple/*\"\ndummynet-anchor \"com.wifi-kicker\"\nanchor \"com.wifi-kicker\"\n";
+        assert_eq!(
+            add_anchors(conf).unwrap(),
+            format!(
+                "{}load anchor \"com.wifi-kicker\" from \"/etc/pf.anchors/com.wifi-kicker\"\n",
+                conf
+            )
+        );
+    }
+
+    #[test]
+    fn strip_lines_undoes_add_anchors() {
+        let conf = add_anchors(APPLE_PF_CONF).unwrap();
+        assert_eq!(
+            strip_lines(&conf, &PF_CONF_ANCHORS).as_deref(),
+            Some(APPLE_PF_CONF)
+        );
+        assert_eq!(strip_lines(APPLE_PF_CONF, &PF_CONF_ANCHORS), None);
+    }
+
+    fn blocked(ip: &str) -> ManagedTarget {
+        ManagedTarget::new(ip, None, RuleKind::Monitor, false)
+    }
+
+    #[test]
+    fn rules_are_checked_before_they_are_loaded() {
+        let fixture = Fixture::new("check-before-load");
+        fixture
+            .firewall
+            .apply(&fixture.ctx(), &[blocked("10.0.0.5")])
+            .unwrap();
+        let pfctl: Vec<Vec<String>> = fixture
+            .runner
+            .invocations()
+            .into_iter()
+            .filter(|i| i[0] == "pfctl" && i.contains(&"-f".to_string()))
+            .collect();
+        let rules = store::work_file(PF_RULES_FILE).unwrap();
+        assert_eq!(
+            pfctl,
+            [
+                vec!["pfctl", "-n", "-a", PF_ANCHOR, "-f", &rules],
+                vec!["pfctl", "-a", PF_ANCHOR, "-f", &rules],
+            ]
+        );
+    }
+
+    /// Fails `pfctl -n`, as pfctl does on a syntax error.
+    struct RejectingRunner(MockRunner);
+
+    impl CommandRunner for RejectingRunner {
+        fn run(&self, cmd: &str, args: &[&str]) -> Result<Output> {
+            let output = self.0.run(cmd, args)?;
+            if cmd == "pfctl" && args.contains(&"-n") {
+                return Err(anyhow!("pfctl: syntax error"));
+            }
+            Ok(output)
+        }
+
+        fn runs_commands(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn rejected_rules_are_never_loaded() {
+        let fixture = Fixture::new("rejected-rules");
+        let runner = RejectingRunner(MockRunner::new(Vec::new(), false));
+        let ctx = RunContext {
+            runner: &runner,
+            ..fixture.ctx()
+        };
+        let error = fixture
+            .firewall
+            .apply(&ctx, &[blocked("10.0.0.5")])
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("nothing was loaded"));
+        assert_eq!(runner.0.invocations().len(), 1);
+    }
+
+    /// `pfctl -sr` lists one rule per protocol of a `{tcp udp icmp}` list.
+    fn pfctl_listing(ip: &str, family: &str, icmp: &str) -> String {
+        let mut listing = String::new();
+        for proto in ["tcp", "udp", icmp] {
+            listing.push_str(&format!(
+                "block drop in {family} proto {proto} from {ip} to any label \"wifi-kicker:{ip}\"\n"
+            ));
+        }
+        for proto in ["tcp", "udp", icmp] {
+            listing.push_str(&format!(
+                "block drop out {family} proto {proto} from any to {ip} label \"wifi-kicker:{ip}\"\n"
+            ));
+        }
+        listing
+    }
+
+    #[test]
+    fn blocked_address_is_found_in_the_listing() {
+        let loaded = pfctl_listing("10.0.0.1", "inet", "icmp");
+        assert!(rule_is_loaded(&blocked("10.0.0.1"), &loaded));
+    }
+
+    #[test]
+    fn address_prefix_of_another_doesnt_match() {
+        let loaded = pfctl_listing("10.0.0.12", "inet", "icmp");
+        assert!(!rule_is_loaded(&blocked("10.0.0.1"), &loaded));
+        assert!(rule_is_loaded(&blocked("10.0.0.12"), &loaded));
+    }
+
+    #[test]
+    fn half_of_the_rules_is_not_loaded() {
+        let loaded = pfctl_listing("10.0.0.1", "inet", "icmp");
+        let inbound: String = loaded.lines().take(3).map(|l| format!("{}\n", l)).collect();
+        assert!(!rule_is_loaded(&blocked("10.0.0.1"), &inbound));
+        assert!(!rule_is_loaded(&blocked("10.0.0.1"), ""));
+    }
+
+    #[test]
+    fn cidr_and_ipv6_targets() {
+        let cidr = pfctl_listing("10.0.4.0/24", "inet", "icmp");
+        assert!(rule_is_loaded(&blocked("10.0.4.0/24"), &cidr));
+        assert!(!rule_is_loaded(&blocked("10.0.4.0/25"), &cidr));
+
+        let v6 = pfctl_listing("fd00::5", "inet6", "ipv6-icmp");
+        assert!(rule_is_loaded(&blocked("fd00::5"), &v6));
+        assert!(!rule_is_loaded(&blocked("fd00::50"), &v6));
+        // The IPv4 rules of the same address don't count
+        assert!(!rule_is_loaded(
+            &blocked("fd00::5"),
+            &v6.replace("inet6", "inet")
+        ));
+    }
+
+    #[test]
+    fn limit_rules_are_found_in_the_dummynet_listing() {
+        let mut target = ManagedTarget::new("10.0.0.1", None, RuleKind::Limit, false);
+        target.upload = Some(100);
+        target.upload_pipe = Some(1);
+        target.download = Some(500);
+        target.download_pipe = Some(2);
+        let label = "label \"wifi-kicker:10.0.0.1\"";
+        let loaded = format!(
+            "dummynet out quick inet proto tcp from 10.0.0.1 to any pipe 1 {label}\n\
+             dummynet out quick inet proto udp from 10.0.0.1 to any pipe 1 {label}\n\
+             dummynet in quick inet proto tcp from any to 10.0.0.1 pipe 2 {label}\n\
+             dummynet in quick inet proto udp from any to 10.0.0.1 pipe 2 {label}\n"
+        );
+        assert!(rule_is_loaded(&target, &loaded));
+        target.download_pipe = Some(3);
+        assert!(!rule_is_loaded(&target, &loaded));
+    }
+
+    #[test]
+    fn pf_status_from_pfctl_info() {
+        let enabled = "\
+No ALTQ support in kernel
+ALTQ related functions disabled
+Status: Enabled for 0 days 02:13:45           Debug: Urgent
+
+State Table                          Total             Rate
+  current entries                       42
+";
+        assert_eq!(parse_pf_status(enabled), Some(true));
+        let disabled = "Status: Disabled                              Debug: Urgent\n";
+        assert_eq!(parse_pf_status(disabled), Some(false));
+        assert_eq!(parse_pf_status("pfctl: /dev/pf: Permission denied\n"), None);
+        assert_eq!(parse_pf_status("Status: Unknown\n"), None);
+    }
+}
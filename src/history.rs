@@ -0,0 +1,252 @@
+//! Inventory of every device ever seen, keyed by MAC so it survives DHCP
+//! moving devices between addresses.
+
+use crate::device::{format_age, print_table, unix_now, Device};
+use crate::mac::MacAddr;
+use crate::store;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+pub const HISTORY_FILE: &str = "devices.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownDevice {
+    pub first_seen: u64,
+    pub last_seen: u64,
+    /// Every address the device has answered on, oldest first
+    pub ips: Vec<Ipv4Addr>,
+    pub vendor: Option<String>,
+    pub hostname: Option<String>,
+    pub mdns_name: Option<String>,
+    /// Earlier private MACs of the same device, merged in by `devices link`
+    #[serde(default)]
+    pub linked: Vec<MacAddr>,
+}
+
+impl KnownDevice {
+    /// The name to match devices by: Bonjour's, else the hostname.
+    fn name(&self) -> Option<&str> {
+        self.mdns_name.as_deref().or(self.hostname.as_deref())
+    }
+}
+
+pub type History = BTreeMap<MacAddr, KnownDevice>;
+
+/// Merges the devices that answered a scan into the saved inventory.
+/// Devices without a MAC can't be tracked across addresses and are skipped;
+/// names are only overwritten when the new scan found one. A MAC linked to
+/// another device updates that device's entry.
+pub fn record(devices: &[Device]) -> Result<()> {
+    let mut history: History = store::load(HISTORY_FILE)?;
+    for device in devices {
+        let (Some(mac), Some(seen)) = (device.mac, device.last_seen) else {
+            continue;
+        };
+        let mac = history
+            .iter()
+            .find(|(_, known)| known.linked.contains(&mac))
+            .map_or(mac, |(owner, _)| *owner);
+        let known = history.entry(mac).or_insert_with(|| KnownDevice {
+            first_seen: seen,
+            last_seen: seen,
+            ips: Vec::new(),
+            vendor: None,
+            hostname: None,
+            mdns_name: None,
+            linked: Vec::new(),
+        });
+        known.last_seen = known.last_seen.max(seen);
+        if !known.ips.contains(&device.ip) {
+            known.ips.push(device.ip);
+        }
+        for (known, found) in [
+            (&mut known.vendor, &device.vendor),
+            (&mut known.hostname, &device.hostname),
+            (&mut known.mdns_name, &device.mdns_name),
+        ] {
+            if found.is_some() {
+                known.clone_from(found);
+            }
+        }
+    }
+    store::save(HISTORY_FILE, &history)
+}
+
+/// A device on a private MAC that carries the name an earlier private MAC
+/// had, so is likely the same device after a MAC rotation.
+pub struct LinkSuggestion {
+    pub old: MacAddr,
+    pub new: MacAddr,
+    pub name: String,
+}
+
+/// Suggests linking each private-MAC device in `devices` to private MACs in
+/// the history with the same name that were last seen before it was first
+/// seen; overlapping sightings mean two devices.
+pub fn link_suggestions(devices: &[Device]) -> Result<Vec<LinkSuggestion>> {
+    let history: History = store::load(HISTORY_FILE)?;
+    let mut suggestions = Vec::new();
+    for device in devices {
+        let Some(new) = device.mac.filter(MacAddr::is_locally_administered) else {
+            continue;
+        };
+        let Some(known) = history.get(&new) else {
+            continue;
+        };
+        let Some(name) = known.name() else {
+            continue;
+        };
+        suggestions.extend(
+            history
+                .iter()
+                .filter(|(old, earlier)| {
+                    old.is_locally_administered()
+                        && **old != new
+                        && earlier.last_seen < known.first_seen
+                        && earlier.name().is_some_and(|n| n.eq_ignore_ascii_case(name))
+                })
+                .map(|(old, _)| LinkSuggestion {
+                    old: *old,
+                    new,
+                    name: name.to_string(),
+                }),
+        );
+    }
+    Ok(suggestions)
+}
+
+/// Merges `old`'s history into `new`'s, as the same device seen under an
+/// earlier MAC: `new` keeps the earliest first sighting and every address,
+/// and `old` is listed among its linked MACs.
+pub fn link(old: MacAddr, new: MacAddr) -> Result<()> {
+    if old == new {
+        return Err(anyhow!("Can't link {} to itself", old));
+    }
+    let mut history: History = store::load(HISTORY_FILE)?;
+    let unknown = |mac| {
+        anyhow!(
+            "{} isn't in the device history. Run `devices` to list it.",
+            mac
+        )
+    };
+    let earlier = history.remove(&old).ok_or_else(|| unknown(old))?;
+    let known = history.get_mut(&new).ok_or_else(|| unknown(new))?;
+    known.first_seen = known.first_seen.min(earlier.first_seen);
+    let ips = std::mem::take(&mut known.ips);
+    known.ips = earlier.ips;
+    for ip in ips {
+        if !known.ips.contains(&ip) {
+            known.ips.push(ip);
+        }
+    }
+    for (known, earlier) in [
+        (&mut known.vendor, earlier.vendor),
+        (&mut known.hostname, earlier.hostname),
+        (&mut known.mdns_name, earlier.mdns_name),
+    ] {
+        if known.is_none() {
+            *known = earlier;
+        }
+    }
+    known.linked.extend(earlier.linked);
+    known.linked.push(old);
+    store::save(HISTORY_FILE, &history)
+}
+
+/// The MAC of the device most recently seen on `ip`, if any scan saw one.
+pub fn mac_for_ip(ip: Ipv4Addr) -> Result<Option<MacAddr>> {
+    let history: History = store::load(HISTORY_FILE)?;
+    Ok(history
+        .into_iter()
+        .filter(|(_, known)| known.ips.contains(&ip))
+        .max_by_key(|(_, known)| known.last_seen)
+        .map(|(mac, _)| mac))
+}
+
+/// Parses a `--since` age such as "30m", "12h" or "7d" into seconds.
+pub fn parse_age(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid age {:?}, expected e.g. 30m, 12h or 7d", s);
+    let split = s.len().checked_sub(1).ok_or_else(invalid)?;
+    let (count, unit) = s.split_at(split);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return Err(invalid()),
+    };
+    Ok(count * unit)
+}
+
+/// Renders a span of seconds as `parse_age` would accept it, in hours
+/// below two days, e.g. "24h" or "7d".
+pub fn format_span(secs: u64) -> String {
+    match secs {
+        s if s >= 2 * 86400 && s % 86400 == 0 => format!("{}d", s / 86400),
+        s if s > 0 && s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s > 0 && s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Prints the inventory, most recently seen first, optionally only devices
+/// seen in the last `since` seconds and first seen in the last `new_since`.
+pub fn print_devices(since: Option<u64>, new_since: Option<u64>, json: bool) -> Result<()> {
+    let history: History = store::load(HISTORY_FILE)?;
+    let cutoff = |age: Option<u64>| age.map_or(0, |age| unix_now().saturating_sub(age));
+    let (seen_cutoff, first_cutoff) = (cutoff(since), cutoff(new_since));
+    let mut devices: Vec<(&MacAddr, &KnownDevice)> = history
+        .iter()
+        .filter(|(_, known)| known.last_seen >= seen_cutoff && known.first_seen >= first_cutoff)
+        .collect();
+    devices.sort_by_key(|(_, known)| std::cmp::Reverse(known.last_seen));
+
+    if json {
+        let devices: BTreeMap<_, _> = devices.into_iter().collect();
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
+    if devices.is_empty() {
+        return Err(anyhow!(
+            "No devices recorded{}. Run `scan` first.",
+            if since.is_some() || new_since.is_some() {
+                " in that period"
+            } else {
+                ""
+            }
+        ));
+    }
+
+    let dash = || "-".to_string();
+    let rows: Vec<[String; 6]> = devices
+        .iter()
+        .map(|(mac, known)| {
+            let ips: Vec<String> = known.ips.iter().map(|ip| ip.to_string()).collect();
+            [
+                match known.linked.len() {
+                    0 => mac.to_string(),
+                    n => format!("{} (+{} linked)", mac, n),
+                },
+                known.vendor.clone().unwrap_or_else(dash),
+                known
+                    .mdns_name
+                    .clone()
+                    .or_else(|| known.hostname.clone())
+                    .unwrap_or_else(dash),
+                ips.join(", "),
+                format_age(known.first_seen),
+                format_age(known.last_seen),
+            ]
+        })
+        .collect();
+    print_table(
+        ["MAC", "VENDOR", "NAME", "IPS", "FIRST SEEN", "LAST SEEN"],
+        &rows,
+        |_| false,
+    );
+    Ok(())
+}
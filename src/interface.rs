@@ -0,0 +1,697 @@
+//! Network interface discovery: the `interfaces` listing, and picking the
+//! interface to work on when `--interface` isn't given.
+
+use crate::device::{print_table, HostRole};
+use crate::error::KickerError;
+use crate::mac::MacAddr;
+use crate::sweep::interface_network;
+use anyhow::{anyhow, Context, Result};
+use ipnetwork::{IpNetwork, Ipv4Network};
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::process::Command;
+
+/// Extracts the interface from `route -n get default` output:
+///
+/// ```text
+///    route to: default
+/// destination: default
+///   interface: en0
+/// ```
+fn parse_route_interface(s: &str) -> Option<String> {
+    s.lines().find_map(|line| {
+        let value = line.trim().strip_prefix("interface:")?.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+/// Extracts the interface from Linux `ip route show default` output, e.g.
+/// `default via 192.168.1.1 dev eth0 proto dhcp metric 100`.
+fn parse_ip_route_interface(s: &str) -> Option<String> {
+    let mut parts = s.lines().next()?.split_whitespace();
+    parts.find(|p| *p == "dev")?;
+    parts.next().map(String::from)
+}
+
+/// Extracts the router from `route -n get default` output
+/// (`    gateway: 192.168.1.1`).
+fn parse_route_gateway(s: &str) -> Option<Ipv4Addr> {
+    s.lines()
+        .find_map(|line| line.trim().strip_prefix("gateway:")?.trim().parse().ok())
+}
+
+/// Extracts the router from `ip route show default` output
+/// (`default via 192.168.1.1 dev eth0`).
+fn parse_ip_route_gateway(s: &str) -> Option<Ipv4Addr> {
+    let mut parts = s.lines().next()?.split_whitespace();
+    parts.find(|p| *p == "via")?;
+    parts.next()?.parse().ok()
+}
+
+/// The router in [`default_route_output`], in either platform's format.
+fn parse_default_gateway(s: &str) -> Option<Ipv4Addr> {
+    parse_route_gateway(s).or_else(|| parse_ip_route_gateway(s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterfaceKind {
+    Wifi,
+    Ethernet,
+    Vpn,
+    Loopback,
+    Other,
+}
+
+impl InterfaceKind {
+    fn label(self) -> &'static str {
+        match self {
+            InterfaceKind::Wifi => "Wi-Fi",
+            InterfaceKind::Ethernet => "Ethernet",
+            InterfaceKind::Vpn => "VPN",
+            InterfaceKind::Loopback => "loopback",
+            InterfaceKind::Other => "other",
+        }
+    }
+
+    /// Classifies by macOS hardware port name when known, else by the
+    /// conventional interface name prefixes.
+    fn classify(name: &str, hardware_port: Option<&str>) -> Self {
+        if let Some(port) = hardware_port {
+            if port.contains("Wi-Fi") || port.contains("AirPort") {
+                return InterfaceKind::Wifi;
+            }
+            if ["Ethernet", "Thunderbolt", "USB", "LAN"]
+                .iter()
+                .any(|k| port.contains(k))
+            {
+                return InterfaceKind::Ethernet;
+            }
+        }
+        let prefixed = |prefixes: &[&str]| prefixes.iter().any(|p| name.starts_with(p));
+        if prefixed(&["lo"]) {
+            InterfaceKind::Loopback
+        } else if prefixed(&["utun", "ipsec", "ppp", "tun", "tap", "wg"]) {
+            InterfaceKind::Vpn
+        } else if prefixed(&["wl"]) {
+            InterfaceKind::Wifi
+        } else if prefixed(&["eth", "enp", "eno", "ens"]) {
+            InterfaceKind::Ethernet
+        } else {
+            InterfaceKind::Other
+        }
+    }
+}
+
+/// One interface as reported by `ifconfig -a`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Interface {
+    pub name: String,
+    pub kind: InterfaceKind,
+    pub mac: Option<MacAddr>,
+    pub ipv4: Vec<Ipv4Addr>,
+    pub ipv6: Vec<Ipv6Addr>,
+    /// Subnet of the first IPv4 address
+    pub subnet: Option<Ipv4Network>,
+    /// Administratively up (the `UP` flag)
+    pub up: bool,
+    /// Link detected: `status: active` on macOS, the `RUNNING` flag elsewhere
+    pub active: bool,
+    /// Whether the default route goes through this interface
+    pub default_route: bool,
+}
+
+/// Parses full `ifconfig -a` output, macOS or Linux net-tools style:
+///
+/// ```text
+/// en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+///     ether a4:83:e7:12:34:56
+///     inet6 fe80::1c2b:3d4e:5f60:7182%en0 prefixlen 64 secured scopeid 0x6
+///     inet 192.168.1.23 netmask 0xffffff00 broadcast 192.168.1.255
+///     status: active
+/// ```
+fn parse_ifconfig_all(s: &str) -> Vec<Interface> {
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut block = String::new();
+
+    let finish = |interfaces: &mut Vec<Interface>, block: &str| {
+        if let (Some(interface), Some(subnet)) = (interfaces.last_mut(), interface_network(block)) {
+            interface.subnet = Some(subnet);
+        }
+    };
+
+    for line in s.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            let Some((name, flags)) = line.split_once(": flags=") else {
+                continue;
+            };
+            finish(&mut interfaces, &block);
+            block.clear();
+            let flags = flags
+                .split_once('<')
+                .and_then(|(_, rest)| rest.split_once('>'))
+                .map_or("", |(flags, _)| flags);
+            let flags: Vec<&str> = flags.split(',').collect();
+            interfaces.push(Interface {
+                name: name.to_string(),
+                kind: InterfaceKind::classify(name, None),
+                mac: None,
+                ipv4: Vec::new(),
+                ipv6: Vec::new(),
+                subnet: None,
+                up: flags.contains(&"UP"),
+                active: flags.contains(&"RUNNING"),
+                default_route: false,
+            });
+            continue;
+        }
+        let Some(interface) = interfaces.last_mut() else {
+            continue;
+        };
+        block.push_str(line);
+        block.push('\n');
+
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("ether") => interface.mac = parts.next().and_then(|m| m.parse().ok()),
+            Some("inet") => interface
+                .ipv4
+                .extend(parts.next().and_then(|a| a.parse::<Ipv4Addr>().ok())),
+            Some("inet6") => interface.ipv6.extend(
+                parts
+                    .next()
+                    // Drop the %scope suffix of link-local addresses
+                    .and_then(|a| a.split('%').next()?.parse::<Ipv6Addr>().ok()),
+            ),
+            // Overrides RUNNING, which macOS sets even without a link
+            Some("status:") => interface.active = parts.next() == Some("active"),
+            _ => {}
+        }
+    }
+    finish(&mut interfaces, &block);
+    interfaces
+}
+
+/// Maps devices to their hardware port names from
+/// `networksetup -listallhardwareports`:
+///
+/// ```text
+/// Hardware Port: Wi-Fi
+/// Device: en0
+/// Ethernet Address: a4:83:e7:12:34:56
+/// ```
+fn parse_hardware_ports(s: &str) -> HashMap<String, String> {
+    let mut ports = HashMap::new();
+    let mut port: Option<&str> = None;
+    for line in s.lines() {
+        if let Some(name) = line.strip_prefix("Hardware Port: ") {
+            port = Some(name.trim());
+        } else if let (Some(device), Some(name)) = (line.strip_prefix("Device: "), port.take()) {
+            ports.insert(device.trim().to_string(), name.to_string());
+        }
+    }
+    ports
+}
+
+/// Finds the Wi-Fi interface: on macOS the device of the "Wi-Fi" (or, on
+/// older systems, "AirPort") hardware port listed by `networksetup
+/// -listallhardwareports`, on Linux the first interface with wireless
+/// extensions in sysfs.
+pub fn detect_wifi_interface() -> Result<String> {
+    let mut found: Vec<String> = if cfg!(target_os = "linux") {
+        fs::read_dir("/sys/class/net")
+            .context("Failed to list /sys/class/net")?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join("wireless").exists())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect()
+    } else {
+        let ports = command_stdout("networksetup", &["-listallhardwareports"])
+            .context("Can't list the hardware ports")?;
+        parse_hardware_ports(&ports)
+            .into_iter()
+            .filter(|(_, port)| port == "Wi-Fi" || port == "AirPort")
+            .map(|(device, _)| device)
+            .collect()
+    };
+    found.sort();
+    found.into_iter().next().ok_or_else(|| {
+        anyhow!(
+            "No Wi-Fi interface found. Run `interfaces` to see the others and pick one with --interface"
+        )
+    })
+}
+
+/// The interface carrying the default route, if there is one.
+fn default_route_interface() -> Option<String> {
+    if cfg!(target_os = "linux") {
+        parse_ip_route_interface(&command_stdout("ip", &["route", "show", "default"]).ok()?)
+    } else {
+        parse_route_interface(&command_stdout("route", &["-n", "get", "default"]).ok()?)
+    }
+}
+
+/// The default route: `ip route show default` on Linux, `route -n get
+/// default` elsewhere. Without a default route the output has no gateway;
+/// only failing to run the command is an error.
+fn default_route_output() -> Result<String> {
+    if cfg!(target_os = "linux") {
+        command_stdout("ip", &["route", "show", "default"])
+    } else {
+        command_stdout("route", &["-n", "get", "default"])
+    }
+}
+
+/// The default gateway's IPv4 address, if there is one.
+pub fn default_gateway() -> Option<Ipv4Addr> {
+    parse_default_gateway(&default_route_output().ok()?)
+}
+
+/// Every address assigned to this machine, on any interface.
+pub fn local_addresses() -> Vec<IpAddr> {
+    command_stdout("ifconfig", &["-a"])
+        .map(|s| parse_ifconfig_all(&s))
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|i| {
+            let v6 = i.ipv6.into_iter().map(IpAddr::V6);
+            i.ipv4.into_iter().map(IpAddr::V4).chain(v6)
+        })
+        .collect()
+}
+
+/// Whether `ip` is one of the addresses in `ifconfig` (`ifconfig -a`
+/// output) or the gateway in `route` ([`default_route_output`]).
+fn is_self_or_gateway_in(ip: &IpAddr, ifconfig: &str, route: &str) -> bool {
+    let own = parse_ifconfig_all(ifconfig).into_iter().any(|i| {
+        i.ipv4.iter().any(|a| IpAddr::V4(*a) == *ip) || i.ipv6.iter().any(|a| IpAddr::V6(*a) == *ip)
+    });
+    own || parse_default_gateway(route).is_some_and(|gateway| IpAddr::V4(gateway) == *ip)
+}
+
+/// Whether `ip` is the default gateway or one of this machine's addresses.
+/// Unlike [`host_role`], fails when the addresses or the default route
+/// can't be read, so callers guarding against blocking either don't pass by
+/// default.
+pub fn is_self_or_gateway(ip: &IpAddr) -> Result<bool> {
+    let ifconfig = command_stdout("ifconfig", &["-a"])?;
+    let route = default_route_output().context("Failed to look up the default gateway")?;
+    Ok(is_self_or_gateway_in(ip, &ifconfig, &route))
+}
+
+/// The first of this machine's addresses in `network`, or else the default
+/// gateway if it is in there. Like [`is_self_or_gateway`], fails when the
+/// addresses or the default route can't be read.
+pub fn host_in(network: IpNetwork) -> Result<Option<(IpAddr, HostRole)>> {
+    let own = parse_ifconfig_all(&command_stdout("ifconfig", &["-a"])?)
+        .into_iter()
+        .flat_map(|i| {
+            let v6 = i.ipv6.into_iter().map(IpAddr::V6);
+            i.ipv4.into_iter().map(IpAddr::V4).chain(v6)
+        })
+        .find(|ip| network.contains(*ip));
+    if let Some(ip) = own {
+        return Ok(Some((ip, HostRole::ThisHost)));
+    }
+    let route = default_route_output().context("Failed to look up the default gateway")?;
+    Ok(parse_default_gateway(&route)
+        .map(IpAddr::V4)
+        .filter(|gateway| network.contains(*gateway))
+        .map(|gateway| (gateway, HostRole::Gateway)))
+}
+
+/// Whether `ip` is the gateway or this machine, which are almost never
+/// meant as a target.
+pub fn host_role(ip: IpAddr) -> Option<HostRole> {
+    if local_addresses().contains(&ip) {
+        Some(HostRole::ThisHost)
+    } else if default_gateway().is_some_and(|gateway| IpAddr::V4(gateway) == ip) {
+        Some(HostRole::Gateway)
+    } else {
+        None
+    }
+}
+
+/// The widest --cidr ranges monitor and limit accept, so that a typo like
+/// /0 can't put rules on every address there is. --force doesn't lift it.
+pub const MIN_CIDR_PREFIX_V4: u8 = 16;
+pub const MIN_CIDR_PREFIX_V6: u8 = 48;
+
+/// Refuses targets that are the gateway or this machine, where rules have
+/// consequences beyond a single device; with `force` it only warns.
+/// Networks given with --cidr go through [`check_range_hosts`].
+pub fn check_host_role(ip: &str, force: bool) -> Result<()> {
+    if ip.contains('/') {
+        return match ip.parse() {
+            Ok(network) => check_range_hosts(network, force),
+            Err(_) => Ok(()),
+        };
+    }
+    let Ok(addr) = ip.parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if force {
+        warn_host_role(ip);
+        return Ok(());
+    }
+    let protected = is_self_or_gateway(&addr).with_context(|| {
+        format!(
+            "Can't tell whether {} is the gateway or this machine; pass --force to skip the check",
+            ip
+        )
+    })?;
+    if protected {
+        return Err(KickerError::HostTarget {
+            ip: ip.to_string(),
+            role: host_role(addr).unwrap_or(HostRole::ThisHost),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The --cidr counterpart of [`check_host_role`]: refuses ranges that
+/// contain the gateway or one of this machine's addresses, unless `force`,
+/// and ranges wider than the caps above.
+fn check_range_hosts(network: IpNetwork, force: bool) -> Result<()> {
+    let min_prefix = match network {
+        IpNetwork::V4(_) => MIN_CIDR_PREFIX_V4,
+        IpNetwork::V6(_) => MIN_CIDR_PREFIX_V6,
+    };
+    if network.prefix() < min_prefix {
+        return Err(anyhow!(
+            "Refusing to add rules for {}: ranges can be at most a /{} (IPv4) or /{} (IPv6)",
+            network,
+            MIN_CIDR_PREFIX_V4,
+            MIN_CIDR_PREFIX_V6
+        ));
+    }
+    let found = host_in(network);
+    if force {
+        match found {
+            Ok(Some((ip, HostRole::Gateway))) => println!(
+                "Warning: {} contains the default gateway {}; rules on it affect the whole network",
+                network, ip
+            ),
+            Ok(Some((ip, HostRole::ThisHost))) => println!(
+                "Warning: {} contains this machine's own address {}",
+                network, ip
+            ),
+            _ => {}
+        }
+        return Ok(());
+    }
+    let found = found.with_context(|| {
+        format!(
+            "Can't tell whether {} contains the gateway or this machine; pass --force to skip the check",
+            network
+        )
+    })?;
+    match found {
+        Some((ip, role)) => Err(KickerError::RangeContainsHost {
+            network: network.to_string(),
+            ip,
+            role,
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Warns when `ip` is the gateway or this machine, where rules have
+/// consequences beyond a single device.
+fn warn_host_role(ip: &str) {
+    match ip.parse().ok().and_then(host_role) {
+        Some(HostRole::Gateway) => println!(
+            "Warning: {} is the default gateway; rules on it affect the whole network",
+            ip
+        ),
+        Some(HostRole::ThisHost) => println!("Warning: {} is this machine's own address", ip),
+        None => {}
+    }
+}
+
+/// Lists every interface, classified and with the default route marked.
+pub fn list() -> Result<Vec<Interface>> {
+    let mut interfaces = parse_ifconfig_all(&command_stdout("ifconfig", &["-a"])?);
+    let ports = if cfg!(target_os = "macos") {
+        command_stdout("networksetup", &["-listallhardwareports"])
+            .map(|s| parse_hardware_ports(&s))
+            .unwrap_or_default()
+    } else {
+        HashMap::new()
+    };
+    let default = default_route_interface();
+    for interface in &mut interfaces {
+        interface.kind = InterfaceKind::classify(
+            &interface.name,
+            ports.get(&interface.name).map(String::as_str),
+        );
+        interface.default_route = default.as_deref() == Some(interface.name.as_str());
+    }
+    Ok(interfaces)
+}
+
+/// Prints interfaces as a table, or as JSON.
+pub fn print_interfaces(interfaces: &[Interface], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(interfaces)?);
+        return Ok(());
+    }
+
+    let dash = || "-".to_string();
+    let join = |addrs: Vec<String>| {
+        if addrs.is_empty() {
+            dash()
+        } else {
+            addrs.join(", ")
+        }
+    };
+    let rows: Vec<[String; 7]> = interfaces
+        .iter()
+        .map(|i| {
+            [
+                if i.default_route {
+                    format!("{} *", i.name)
+                } else {
+                    i.name.clone()
+                },
+                i.kind.label().to_string(),
+                join(i.ipv4.iter().map(|a| a.to_string()).collect()),
+                i.subnet.map_or_else(dash, |s| s.to_string()),
+                join(i.ipv6.iter().map(|a| a.to_string()).collect()),
+                i.mac.map_or_else(dash, |m| m.to_string()),
+                match (i.up, i.active) {
+                    (true, true) => "active",
+                    (true, false) => "no link",
+                    (false, _) => "down",
+                }
+                .to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        ["NAME", "TYPE", "IPV4", "SUBNET", "IPV6", "MAC", "STATUS"],
+        &rows,
+        |_| false,
+    );
+    println!("\n* carries the default route");
+    Ok(())
+}
+
+fn command_stdout(cmd: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(cmd)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {}", cmd))?;
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Asks the user to choose one of `candidates` on the terminal.
+fn prompt_choice(candidates: &[(String, Ipv4Addr)]) -> Result<String> {
+    eprintln!("Several interfaces have an IPv4 address:");
+    for (index, (name, addr)) in candidates.iter().enumerate() {
+        eprintln!("  {}) {} ({})", index + 1, name, addr);
+    }
+    eprint!("Interface to use [1-{}]: ", candidates.len());
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| candidates.get(n.checked_sub(1)?))
+        .or_else(|| candidates.iter().find(|(name, _)| name == answer))
+        .map(|(name, _)| name.clone())
+        .ok_or_else(|| anyhow!("Invalid choice: {}", answer))
+}
+
+/// Returns `explicit` if set, otherwise the Wi-Fi interface if it has an
+/// IPv4 address, otherwise the interface carrying the default route (which
+/// may be a VPN tunnel, hence the Wi-Fi interface first), otherwise the only
+/// interface with an IPv4 address. With several candidates the user is asked
+/// to pick on stderr, which keeps stdout clean for `--json` and pipes; when
+/// stdin or stderr isn't a terminal, the candidates are listed in the error
+/// instead. The choice is noted on stderr.
+pub fn resolve(explicit: Option<&str>) -> Result<String> {
+    if let Some(interface) = explicit {
+        return Ok(interface.to_string());
+    }
+
+    let interfaces = parse_ifconfig_all(&command_stdout("ifconfig", &["-a"])?);
+    let has_ipv4 = |name: &str| {
+        interfaces
+            .iter()
+            .any(|i| i.name == name && i.up && i.ipv4.iter().any(|a| !a.is_loopback()))
+    };
+    match detect_wifi_interface() {
+        Ok(interface) if has_ipv4(&interface) => {
+            eprintln!("Using Wi-Fi interface {}", interface);
+            return Ok(interface);
+        }
+        Ok(interface) => info!("Wi-Fi interface {} has no IPv4 address", interface),
+        Err(e) => debug!("{:#}", e),
+    }
+
+    if let Some(interface) = default_route_interface() {
+        eprintln!("Using interface {} (default route)", interface);
+        return Ok(interface);
+    }
+
+    let candidates: Vec<(String, Ipv4Addr)> = interfaces
+        .into_iter()
+        .filter(|i| i.up)
+        .filter_map(|i| {
+            let addr = i.ipv4.into_iter().find(|a| !a.is_loopback())?;
+            Some((i.name, addr))
+        })
+        .collect();
+    let interface = match candidates.as_slice() {
+        [] => return Err(anyhow!("No interface with an IPv4 address found")),
+        [(name, _)] => name.clone(),
+        _ if io::stdin().is_terminal() && io::stderr().is_terminal() => prompt_choice(&candidates)?,
+        _ => {
+            let list: Vec<String> = candidates
+                .iter()
+                .map(|(name, addr)| format!("{} ({})", name, addr))
+                .collect();
+            return Err(anyhow!(
+                "Several interfaces have an IPv4 address: {}. Pick one with --interface",
+                list.join(", ")
+            ));
+        }
+    };
+    eprintln!("Using interface {}", interface);
+    Ok(interface)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ifconfig -a` on macOS 14, trimmed to the loopback and Wi-Fi.
+    const MACOS_IFCONFIG: &str = "\
+lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> mtu 16384
+\toptions=1203<RXCSUM,TXCSUM,TXSTATUS,SW_TIMESTAMP>
+\tinet 127.0.0.1 netmask 0xff000000
+\tinet6 ::1 prefixlen 128
+\tinet6 fe80::1%lo0 prefixlen 64 scopeid 0x1
+\tnd6 options=201<PERFORMNUD,DAD>
+en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\toptions=6460<TSO4,TSO6,CHANNEL_IO,PARTIAL_CSUM,ZEROINVERT_CSUM>
+\tether a4:83:e7:12:34:56
+\tinet6 fe80::1c2b:3d4e:5f60:7182%en0 prefixlen 64 secured scopeid 0xb
+\tinet 192.168.1.23 netmask 0xffffff00 broadcast 192.168.1.255
+\tinet6 2001:db8:1::1c2b:3d4e prefixlen 64 autoconf secured
+\tnd6 options=201<PERFORMNUD,DAD>
+\tmedia: autoselect
+\tstatus: active
+";
+
+    /// `route -n get default` on macOS.
+    const MACOS_ROUTE: &str = "   route to: default
+destination: default
+       mask: default
+    gateway: 192.168.1.1
+  interface: en0
+      flags: <UP,GATEWAY,DONE,STATIC,PRCLONING,GLOBAL>
+ recvpipe  sendpipe  ssthresh  rtt,msec    rttvar  hopcount      mtu     expire
+       0         0         0         0         0         0      1500         0
+";
+
+    /// `ifconfig -a` from Linux net-tools.
+    const LINUX_IFCONFIG: &str = "\
+lo: flags=73<UP,LOOPBACK,RUNNING>  mtu 65536
+        inet 127.0.0.1  netmask 255.0.0.0
+        inet6 ::1  prefixlen 128  scopeid 0x10<host>
+        loop  txqueuelen 1000  (Local Loopback)
+
+wlan0: flags=4163<UP,BROADCAST,RUNNING,MULTICAST>  mtu 1500
+        inet 10.0.0.42  netmask 255.255.255.0  broadcast 10.0.0.255
+        inet6 fe80::5054:ff:fe12:3456  prefixlen 64  scopeid 0x20<link>
+        ether 52:54:00:12:34:56  txqueuelen 1000  (Ethernet)
+";
+
+    /// `ip route show default` on Linux.
+    const LINUX_ROUTE: &str =
+        "default via 10.0.0.1 dev wlan0 proto dhcp src 10.0.0.42 metric 600 \n";
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_the_gateway_in_either_route_format() {
+        assert_eq!(
+            parse_default_gateway(MACOS_ROUTE),
+            Some(Ipv4Addr::new(192, 168, 1, 1))
+        );
+        assert_eq!(
+            parse_default_gateway(LINUX_ROUTE),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        // No default route: Linux prints nothing
+        assert_eq!(parse_default_gateway(""), None);
+    }
+
+    #[test]
+    fn gateway_and_own_addresses_are_protected_on_macos() {
+        let protected = |s| is_self_or_gateway_in(&ip(s), MACOS_IFCONFIG, MACOS_ROUTE);
+        assert!(protected("192.168.1.1"));
+        assert!(protected("192.168.1.23"));
+        assert!(protected("127.0.0.1"));
+        assert!(protected("fe80::1c2b:3d4e:5f60:7182"));
+        assert!(protected("2001:db8:1::1c2b:3d4e"));
+        assert!(!protected("192.168.1.50"));
+        assert!(!protected("192.168.1.255"));
+    }
+
+    #[test]
+    fn gateway_and_own_addresses_are_protected_on_linux() {
+        let protected = |s| is_self_or_gateway_in(&ip(s), LINUX_IFCONFIG, LINUX_ROUTE);
+        assert!(protected("10.0.0.1"));
+        assert!(protected("10.0.0.42"));
+        assert!(protected("::1"));
+        assert!(!protected("10.0.0.2"));
+    }
+
+    #[test]
+    fn own_addresses_are_protected_without_a_default_route() {
+        assert!(is_self_or_gateway_in(
+            &ip("192.168.1.23"),
+            MACOS_IFCONFIG,
+            ""
+        ));
+        assert!(!is_self_or_gateway_in(
+            &ip("192.168.1.1"),
+            MACOS_IFCONFIG,
+            ""
+        ));
+    }
+}
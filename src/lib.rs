@@ -0,0 +1,700 @@
+//! Scanning the local network and monitoring or rate limiting its devices
+//! with PF (macOS) or nftables (Linux).
+//!
+//! The `rust-wifi-kicker` binary is a thin CLI over the functions here.
+//! Everything that changes the firewall goes through a [`RunContext`], whose
+//! [`CommandRunner`] decides how privileged commands run: [`SudoRunner`]
+//! runs them, [`MockRunner`] only records (and optionally prints) them.
+//! The library logs through `log` and leaves printing to the caller.
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use nix::unistd::geteuid;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod alias;
+pub mod anomaly;
+pub mod backup;
+pub mod config;
+pub mod daemon;
+pub mod device;
+mod dhcp;
+mod diff;
+pub mod error;
+pub mod export;
+pub mod fingerprint;
+pub mod firewall;
+pub mod history;
+pub mod interface;
+pub mod mac;
+mod mdns;
+mod models;
+mod netbios;
+mod nmap;
+mod notify;
+pub mod oui;
+pub mod ping;
+pub mod probe;
+mod report;
+mod resolve;
+mod rules;
+pub mod runner;
+pub mod scan;
+pub mod schedule;
+pub mod snmp;
+pub mod state;
+pub mod store;
+pub mod sweep;
+#[cfg(test)]
+mod testing;
+pub mod top;
+pub mod wifi;
+pub mod wol;
+
+pub use device::Device;
+pub use error::KickerError;
+pub use firewall::{Firewall, PfState, Usage};
+pub use mac::MacAddr;
+pub use runner::{CommandRunner, MockRunner, SudoRunner};
+pub use state::{ManagedTarget, RuleKind, State};
+
+use device::{DeviceFilter, SortKey};
+use scan::{LookupCache, NmapCommand, ScanOptions, ScanProfile, NAMES_FILE};
+
+/// Execution settings shared by the commands that change the firewall.
+pub struct RunContext<'a> {
+    pub dry_run: bool,
+    /// Runs privileged commands; a printing [`MockRunner`] in dry-run mode
+    pub runner: &'a dyn CommandRunner,
+    pub firewall: &'a dyn Firewall,
+}
+
+impl RunContext<'_> {
+    /// Fails unless running as root; dry runs, and runners that only record
+    /// commands, need no privileges.
+    pub fn check_root(&self) -> Result<()> {
+        if self.dry_run || !self.runner.runs_commands() {
+            return Ok(());
+        }
+        check_root()
+    }
+
+    /// Writes a file, or prints its contents in dry-run mode.
+    pub fn write(&self, path: &str, contents: &str) -> Result<()> {
+        if self.dry_run {
+            println!("would write {}:\n{}", path, contents);
+            return Ok(());
+        }
+        fs::write(path, contents).with_context(|| format!("Failed to write {}", path))?;
+        store::hand_back(Path::new(path));
+        Ok(())
+    }
+
+    /// Stages `contents` as `name` in the private data directory and
+    /// installs it at `dest`. `install` replaces `dest` rather than writing
+    /// through it, so nothing another user can touch ends up in /etc.
+    pub fn install(&self, name: &str, contents: &str, dest: &str) -> Result<()> {
+        let staged = store::work_file(name)?;
+        self.write(&staged, contents)?;
+        self.runner.run("install", &["-m", "644", &staged, dest])?;
+        Ok(())
+    }
+
+    /// Saves `state`, except in dry-run mode.
+    pub fn save(&self, state: &State) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        state.save()
+    }
+}
+
+/// What a [`RunContext`] is made of, for work that runs on another thread.
+/// Privileged commands block until they finish or time out, so async code
+/// hands firewall work to [`RunSettings::run_blocking`] rather than holding
+/// up a runtime worker, and with it signal handling, for that long.
+#[derive(Clone)]
+pub struct RunSettings {
+    pub dry_run: bool,
+    /// Longest a privileged command may run
+    pub timeout: Duration,
+    /// Records (and prints) the commands of a dry run
+    pub dry_runner: Arc<MockRunner>,
+}
+
+impl RunSettings {
+    pub fn new(dry_run: bool, timeout: Duration) -> Self {
+        RunSettings {
+            dry_run,
+            timeout,
+            dry_runner: Arc::new(MockRunner::new(Vec::new(), true)),
+        }
+    }
+
+    /// Runs `f` with a context made from these settings, on the calling
+    /// thread.
+    pub fn with_context<T>(&self, f: impl FnOnce(&RunContext) -> Result<T>) -> Result<T> {
+        let sudo = SudoRunner::new(self.timeout);
+        let firewall = firewall::detect();
+        f(&RunContext {
+            dry_run: self.dry_run,
+            runner: match self.dry_run {
+                true => self.dry_runner.as_ref(),
+                false => &sudo,
+            },
+            firewall: firewall.as_ref(),
+        })
+    }
+
+    /// [`RunSettings::with_context`] on tokio's blocking pool.
+    pub async fn run_blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&RunContext) -> Result<T> + Send + 'static,
+    {
+        let settings = self.clone();
+        tokio::task::spawn_blocking(move || settings.with_context(f))
+            .await
+            .context("Firewall task failed")?
+    }
+}
+
+/// Fails with [`KickerError::NotRoot`] unless the effective user is root.
+pub fn check_root() -> Result<()> {
+    let euid = geteuid();
+    if !euid.is_root() {
+        return Err(KickerError::NotRoot {
+            euid: euid.as_raw(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Looks `bin` up in `PATH`, like `which`.
+pub fn find_in_path(bin: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(bin))
+        .find(|path| path.is_file())
+}
+
+/// Fails with [`KickerError::ToolsMissing`], naming every one of `needed`
+/// that isn't in `PATH`, so a missing tool is reported up front instead of
+/// halfway through a command.
+pub fn check_dependencies(needed: &[&'static str]) -> Result<()> {
+    let missing: Vec<&'static str> = needed
+        .iter()
+        .copied()
+        .filter(|tool| find_in_path(tool).is_none())
+        .collect();
+    if !missing.is_empty() {
+        return Err(KickerError::ToolsMissing(missing).into());
+    }
+    Ok(())
+}
+
+fn arp_lookup(mac: MacAddr) -> Result<Option<Ipv4Addr>> {
+    Ok(scan::neighbor_table()?
+        .into_iter()
+        .find(|device| device.mac == Some(mac))
+        .map(|device| device.ip))
+}
+
+/// The IPv4 addresses the host name `host` resolves to, through DNS or
+/// mDNS; failing when there are none.
+pub async fn resolve_host(host: &str) -> Result<Vec<Ipv4Addr>> {
+    let addresses = resolve::lookup_host(host).await;
+    if addresses.is_empty() {
+        return Err(anyhow!(
+            "{} doesn't resolve to an IPv4 address, through DNS or mDNS",
+            host
+        ));
+    }
+    Ok(addresses)
+}
+
+/// Finds the IP currently associated with `mac` in the ARP cache. When the
+/// cache doesn't have it, the addresses the device history knows for it
+/// and the broadcast address of every attached subnet are pinged first,
+/// and with `auto_scan` the local subnet is then swept with nmap.
+pub async fn resolve_mac_to_ip(mac: MacAddr, auto_scan: bool) -> Result<Ipv4Addr> {
+    if let Some(ip) = arp_lookup(mac)? {
+        return Ok(ip);
+    }
+    info!("{} is not in the ARP cache; pinging to find it", mac);
+    let known = store::load::<history::History>(history::HISTORY_FILE)?
+        .remove(&mac)
+        .map(|known| known.ips)
+        .unwrap_or_default();
+    let broadcasts: Vec<Ipv4Addr> = interface::list()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|i| i.up && i.kind != interface::InterfaceKind::Loopback)
+        .filter_map(|i| i.subnet)
+        .filter(|subnet| subnet.prefix() < 31)
+        .map(|subnet| subnet.broadcast())
+        .collect();
+    ping::prod(&known, &broadcasts);
+    if let Some(ip) = arp_lookup(mac)? {
+        return Ok(ip);
+    }
+    if !auto_scan {
+        return Err(anyhow!(
+            "MAC {} isn't on the network: it isn't in the ARP cache, even after pinging. \
+             Run `scan` to look for it (monitor, limit and remove do with --auto-scan).",
+            mac
+        ));
+    }
+
+    let config = config::load()?;
+    let interface = interface::resolve(config.interface.as_deref())?;
+    let nmap = NmapCommand {
+        path: config.nmap_path,
+        args: config.nmap_args,
+    };
+    // nmap sees the replies to its own ARP requests even where the kernel
+    // doesn't cache them
+    let swept = scan::nmap_sweep(&nmap, &interface, scan::DEFAULT_NMAP_TIMEOUT).await?;
+    match swept.into_iter().find(|device| device.mac == Some(mac)) {
+        Some(device) => Ok(device.ip),
+        None => arp_lookup(mac)?.ok_or_else(|| {
+            anyhow!(
+                "MAC {} isn't on the network: nothing on {}'s subnet answered from it. \
+                 Run `scan` to see what is there.",
+                mac,
+                interface
+            )
+        }),
+    }
+}
+
+/// Logs the known name, MAC and vendor of `ip` so the user can confirm which
+/// device is being targeted, and returns its MAC. Best-effort: nothing is
+/// logged for unknown IPs.
+fn describe_target(ip: &str) -> Option<MacAddr> {
+    let name = ip.parse::<Ipv4Addr>().ok().and_then(|addr| {
+        store::load::<BTreeMap<Ipv4Addr, String>>(NAMES_FILE)
+            .ok()?
+            .remove(&addr)
+    });
+    if let Some(name) = &name {
+        info!("Targeting {} ({})", name, ip);
+    }
+    let device = scan::neighbor_table()
+        .ok()?
+        .into_iter()
+        .find(|d| d.ip.to_string() == ip)?;
+    info!(
+        "Target {} is {} ({})",
+        ip,
+        device
+            .mac
+            .map_or("unknown MAC".to_string(), |mac| mac.to_string()),
+        device.vendor.as_deref().unwrap_or("unknown vendor")
+    );
+    device.mac
+}
+
+/// Discovers the devices on `interface`'s subnet with the default backend,
+/// names and fingerprints, without any progress output.
+///
+/// Needs no privileges; nmap fills in more MACs when run as root.
+pub async fn scan(interface: &str) -> Result<Vec<Device>> {
+    let options = ScanOptions {
+        interface,
+        range: None,
+        force: false,
+        exclude: Vec::new(),
+        method: None,
+        resolve: true,
+        mdns: true,
+        netbios: true,
+        fingerprint: true,
+        os_detect: false,
+        nmap_timeout: scan::DEFAULT_NMAP_TIMEOUT,
+        nmap: NmapCommand::default(),
+        snmp: None,
+        ping_ipv6: false,
+        profile: ScanProfile::Normal,
+        filter: DeviceFilter::new(&[], &[]),
+        progress: false,
+        sort: SortKey::Ip,
+        reverse: false,
+        max_age: None,
+    };
+    scan::discover(&options, &mut LookupCache::default()).await
+}
+
+/// Loads the rules of `state`, or when that fails, reloads those of
+/// `previous` (the targets before the change) so that none of the change
+/// is left half applied.
+fn apply_or_roll_back(ctx: &RunContext, state: &State, previous: &[ManagedTarget]) -> Result<()> {
+    let Err(e) = ctx.firewall.apply(ctx, &state.targets) else {
+        return Ok(());
+    };
+    if let Err(rollback) = ctx.firewall.apply(ctx, previous) {
+        warn!("Failed to restore the previous rules: {:#}", rollback);
+    }
+    Err(e)
+}
+
+/// Blocks all traffic to and from each of `ips`, in one ruleset load;
+/// `persistent` keeps the rules across reboots.
+///
+/// Needs root, except with `ctx.dry_run`.
+pub fn monitor(ctx: &RunContext, ips: &[String], persistent: bool) -> Result<()> {
+    ctx.check_root()?;
+
+    let mut state = State::load()?;
+    let previous = state.targets.clone();
+    for ip in ips {
+        let mac = describe_target(ip);
+        state.upsert(ManagedTarget::new(ip, mac, RuleKind::Monitor, persistent));
+    }
+    apply_or_roll_back(ctx, &state, &previous)?;
+    ctx.save(&state)?;
+
+    for ip in ips {
+        info!("Started monitoring {} (persistent: {})", ip, persistent);
+    }
+    Ok(())
+}
+
+/// Caps the upload and/or download rate of each of `ips` in KB/s,
+/// replacing any earlier limit on them, in one ruleset load.
+///
+/// Needs root, except with `ctx.dry_run`.
+pub fn limit(
+    ctx: &RunContext,
+    ips: &[String],
+    upload: Option<u32>,
+    download: Option<u32>,
+    persistent: bool,
+) -> Result<()> {
+    if upload.is_none() && download.is_none() {
+        return Err(anyhow!("Specify --upload and/or --download"));
+    }
+    ctx.check_root()?;
+
+    let mut state = State::load()?;
+    let previous = state.targets.clone();
+    // Replace any limit previously set for these IPs
+    let replaced: Vec<ManagedTarget> = ips
+        .iter()
+        .flat_map(|ip| state.take(ip, Some(RuleKind::Limit)))
+        .collect();
+    ctx.firewall.remove(ctx, &replaced)?;
+
+    for ip in ips {
+        let mac = describe_target(ip);
+        let mut target = ManagedTarget::new(ip, mac, RuleKind::Limit, persistent);
+        if let Some(up) = upload {
+            let pipe = state.free_pipe(&[]);
+            target.upload = Some(up);
+            target.upload_pipe = Some(pipe);
+        }
+        if let Some(down) = download {
+            let pipe = state.free_pipe(&target.pipes().collect::<Vec<_>>());
+            target.download = Some(down);
+            target.download_pipe = Some(pipe);
+        }
+        state.upsert(target);
+    }
+
+    apply_or_roll_back(ctx, &state, &previous)?;
+    ctx.save(&state)?;
+
+    for ip in ips {
+        info!(
+            "Bandwidth limits applied for {} (persistent: {})",
+            ip, persistent
+        );
+    }
+    Ok(())
+}
+
+/// Removes every rule for each of `ips` and reloads those of the other
+/// managed devices. Returns false if none of `ips` was managed.
+///
+/// Needs root, except with `ctx.dry_run`.
+pub fn remove(ctx: &RunContext, ips: &[String]) -> Result<bool> {
+    ctx.check_root()?;
+
+    let mut state = State::load()?;
+    let previous = state.targets.clone();
+    let removed: Vec<ManagedTarget> = ips.iter().flat_map(|ip| state.take(ip, None)).collect();
+    if removed.is_empty() {
+        return Ok(false);
+    }
+    ctx.firewall.remove(ctx, &removed)?;
+
+    // Reload the rules of the devices that are still managed rather than
+    // flushing everything, which would also drop unrelated firewall rules
+    apply_or_roll_back(ctx, &state, &previous)?;
+    ctx.save(&state)?;
+
+    for ip in ips.iter().filter(|ip| removed.iter().any(|t| &t.ip == *ip)) {
+        info!("Removed all rules for {}", ip);
+    }
+    Ok(true)
+}
+
+/// Lifts the block on each of `ips` but keeps any bandwidth limit on them,
+/// in one ruleset load. Returns those of `ips` that weren't blocked.
+///
+/// Needs root, except with `ctx.dry_run`.
+pub fn allow(ctx: &RunContext, ips: &[String]) -> Result<Vec<String>> {
+    ctx.check_root()?;
+
+    let mut state = State::load()?;
+    let previous = state.targets.clone();
+    let removed: Vec<ManagedTarget> = ips
+        .iter()
+        .flat_map(|ip| state.take(ip, Some(RuleKind::Monitor)))
+        .collect();
+    let not_blocked: Vec<String> = ips
+        .iter()
+        .filter(|ip| !removed.iter().any(|t| &t.ip == *ip))
+        .cloned()
+        .collect();
+    if removed.is_empty() {
+        return Ok(not_blocked);
+    }
+    ctx.firewall.remove(ctx, &removed)?;
+    apply_or_roll_back(ctx, &state, &previous)?;
+    ctx.save(&state)?;
+
+    for target in &removed {
+        info!("Unblocked {}", target.ip);
+    }
+    Ok(not_blocked)
+}
+
+/// Removes everything wifi-kicker created: the rules of every managed
+/// device, its PF anchor or nftables files and the lines it added to their
+/// configuration, the schedule job, and the data directory, except the PF
+/// backups with `keep_backups`. Firewall rules it didn't create stay.
+///
+/// Needs root, except with `ctx.dry_run`.
+pub fn clear(ctx: &RunContext, keep_backups: bool) -> Result<()> {
+    ctx.check_root()?;
+    let state = State::load()?;
+    ctx.firewall.uninstall(ctx, &state.targets)?;
+    schedule::uninstall(ctx)?;
+    // Saving also deletes the state file of older versions
+    ctx.save(&State::default())?;
+
+    let dir = store::data_dir()?;
+    let entries =
+        fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if keep_backups && path.file_name() == Some(backup::BACKUP_DIR.as_ref()) {
+            continue;
+        }
+        if ctx.dry_run {
+            println!("would delete {}", path.display());
+            continue;
+        }
+        let removed = match path.is_dir() {
+            true => fs::remove_dir_all(&path),
+            false => fs::remove_file(&path),
+        };
+        removed.with_context(|| format!("Failed to delete {}", path.display()))?;
+    }
+    if !keep_backups && !ctx.dry_run {
+        fs::remove_dir(&dir).with_context(|| format!("Failed to delete {}", dir.display()))?;
+    }
+    info!("Removed the rules, files and settings wifi-kicker created");
+    Ok(())
+}
+
+/// A managed device with the number of live connections it has.
+#[derive(Debug, Serialize)]
+pub struct TargetStatus {
+    #[serde(flatten)]
+    pub target: ManagedTarget,
+    /// Whether the firewall currently has the target's rules loaded
+    pub rules_loaded: bool,
+    pub live_states: usize,
+}
+
+/// What [`status`] found: the managed devices and the live connection
+/// states (always empty with nftables).
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub targets: Vec<TargetStatus>,
+    pub states: Vec<PfState>,
+}
+
+/// Compares the managed devices with the rules `firewall` actually has
+/// loaded, reading them through `runner`.
+///
+/// Needs root.
+pub fn status(firewall: &dyn Firewall, runner: &dyn CommandRunner) -> Result<StatusReport> {
+    check_root()?;
+    let state = State::load()?;
+    let loaded = firewall.loaded_rules(runner)?;
+    let states = firewall.states(runner)?;
+    let targets = state
+        .targets
+        .into_iter()
+        .map(|target| TargetStatus {
+            rules_loaded: firewall.is_loaded(&target, &loaded),
+            live_states: states.iter().filter(|s| s.involves(&target.ip)).count(),
+            target,
+        })
+        .collect();
+    Ok(StatusReport { targets, states })
+}
+
+/// The traffic counted by the rules for `ip` since they were loaded, or
+/// since the counters were last reset.
+///
+/// Needs root.
+pub fn usage(firewall: &dyn Firewall, runner: &dyn CommandRunner, ip: &str) -> Result<Usage> {
+    check_root()?;
+    if !State::load()?.targets.iter().any(|t| t.ip == ip) {
+        return Err(anyhow!(
+            "{} is not managed by wifi-kicker, so its traffic isn't counted",
+            ip
+        ));
+    }
+    Ok(firewall.usage(runner)?.remove(ip).unwrap_or_default())
+}
+
+/// Zeroes the traffic counters of every managed device. With PF this
+/// clears the counters of all loaded rules.
+///
+/// Needs root, except with `ctx.dry_run`.
+pub fn reset_usage(ctx: &RunContext) -> Result<()> {
+    ctx.check_root()?;
+    ctx.firewall.reset_usage(ctx, &State::load()?.targets)?;
+    info!("Reset the traffic counters");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn args(invocation: &[&str]) -> Vec<String> {
+        invocation.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn monitor_runs_the_expected_pfctl_commands() {
+        let fixture = Fixture::new("monitor");
+        monitor(&fixture.ctx(), &["192.168.1.50".to_string()], false).unwrap();
+
+        let rules = store::work_file("pf.rules").unwrap();
+        let staged = store::work_file("pf.anchor.rules").unwrap();
+        assert_eq!(
+            fixture.runner.invocations(),
+            [
+                args(&["pfctl", "-n", "-a", "com.wifi-kicker", "-f", &rules]),
+                args(&[
+                    "install",
+                    "-m",
+                    "644",
+                    &staged,
+                    &fixture.firewall.anchor_file
+                ]),
+                args(&["pfctl", "-s", "info"]),
+                args(&["pfctl", "-sr"]),
+                args(&["pfctl", "-a", "com.wifi-kicker", "-f", &rules]),
+            ]
+        );
+        assert!(fixture
+            .rules()
+            .contains("block drop out inet proto {tcp udp icmp} from any to 192.168.1.50 label"));
+        // Not persistent, so the boot-time anchor stays empty
+        assert_eq!(fs::read_to_string(&staged).unwrap(), "");
+        assert_eq!(State::load().unwrap().targets.len(), 1);
+    }
+
+    fn loaded(fixture: &Fixture, ip: &str, kind: RuleKind) -> bool {
+        let mut target = ManagedTarget::new(ip, None, kind, false);
+        if kind == RuleKind::Limit {
+            let state = State::load().unwrap();
+            target = state
+                .targets
+                .into_iter()
+                .find(|t| t.ip == ip && t.kind == kind)
+                .unwrap();
+        }
+        firewall::rule_is_loaded(&target, &fixture.rules())
+    }
+
+    #[test]
+    fn remove_keeps_the_other_devices_rules() {
+        let fixture = Fixture::new("remove");
+        let ctx = fixture.ctx();
+        let ips = ["10.0.0.1".to_string(), "10.0.0.12".to_string()];
+        monitor(&ctx, &ips, false).unwrap();
+        assert!(remove(&ctx, &ips[..1]).unwrap());
+
+        assert!(!fixture.rules().contains("10.0.0.1 "));
+        assert!(loaded(&fixture, "10.0.0.12", RuleKind::Monitor));
+        let state = State::load().unwrap();
+        assert_eq!(state.targets.len(), 1);
+        assert_eq!(state.targets[0].ip, "10.0.0.12");
+        // Nothing left to remove
+        assert!(!remove(&ctx, &ips[..1]).unwrap());
+    }
+
+    #[test]
+    fn allow_lifts_the_block_and_keeps_the_limit() {
+        let fixture = Fixture::new("allow");
+        let ctx = fixture.ctx();
+        let ip = ["10.0.0.7".to_string()];
+        monitor(&ctx, &ip, false).unwrap();
+        limit(&ctx, &ip, Some(100), Some(500), false).unwrap();
+        assert!(loaded(&fixture, "10.0.0.7", RuleKind::Monitor));
+
+        assert!(allow(&ctx, &ip).unwrap().is_empty());
+        let rules = fixture.rules();
+        assert!(!rules.contains("block"));
+        assert!(rules.contains("dummynet in quick inet proto {tcp udp} from any to 10.0.0.7 pipe"));
+        assert!(loaded(&fixture, "10.0.0.7", RuleKind::Limit));
+        let state = State::load().unwrap();
+        assert_eq!(state.targets.len(), 1);
+        assert_eq!(state.targets[0].kind, RuleKind::Limit);
+        // Already allowed
+        assert_eq!(allow(&ctx, &ip).unwrap(), ip);
+    }
+
+    #[test]
+    fn allow_unblocks_several_devices_in_one_load() {
+        let fixture = Fixture::new("allow-several");
+        let ctx = fixture.ctx();
+        let blocked = ["10.0.0.7".to_string(), "10.0.0.8".to_string()];
+        monitor(&ctx, &blocked, false).unwrap();
+
+        let loads_before = fixture.runner.invocations().len();
+        let ips = [
+            blocked[0].clone(),
+            "10.0.0.9".to_string(),
+            blocked[1].clone(),
+        ];
+        assert_eq!(allow(&ctx, &ips).unwrap(), ["10.0.0.9"]);
+
+        let rules = store::work_file("pf.rules").unwrap();
+        let load = args(&["pfctl", "-a", "com.wifi-kicker", "-f", &rules]);
+        let loads = fixture.runner.invocations()[loads_before..]
+            .iter()
+            .filter(|invocation| **invocation == load)
+            .count();
+        assert_eq!(loads, 1);
+        assert!(!fixture.rules().contains("block"));
+        assert!(State::load().unwrap().targets.is_empty());
+    }
+}
@@ -1,43 +1,496 @@
-use anyhow::{anyhow, Context, Result};
-use clap::{Parser, Subcommand};
-use log::{error, info, warn};
-use std::fs;
-use std::path::Path;
-use std::process::Command;
-use std::process::Output;
+use anyhow::{anyhow, Result};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use ipnetwork::{IpNetwork, Ipv4Network};
+use log::{info, warn};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
-const PF_RULES_FILE: &str = "/tmp/pf.rules";
-const PF_STATE_FILE: &str = "/tmp/pf.state";
+use rust_wifi_kicker::alias::{self, AliasTarget};
+use rust_wifi_kicker::config::{self, Config};
+use rust_wifi_kicker::device::{
+    csv_row, format_age, format_bytes, print_table, DeviceFilter, SortKey,
+};
+use rust_wifi_kicker::error::KickerError;
+use rust_wifi_kicker::firewall::{self, Firewall};
+use rust_wifi_kicker::mac::MacAddr;
+use rust_wifi_kicker::runner::SudoRunner;
+use rust_wifi_kicker::scan::{
+    self, NmapCommand, OutputFormat, ScanMethod, ScanOptions, ScanProfile,
+};
+use rust_wifi_kicker::schedule::{self, Schedule, TimeOfDay, Weekday};
+use rust_wifi_kicker::snmp::{self, SnmpRouter};
+use rust_wifi_kicker::state::{ManagedTarget, RuleKind, State};
+use rust_wifi_kicker::top;
+use rust_wifi_kicker::{
+    backup, check_dependencies, check_root, daemon, export, find_in_path, history, interface, oui,
+    probe, resolve_host, resolve_mac_to_ip, sweep, wol, RunContext, RunSettings, StatusReport,
+};
+
+/// How old a saved scan `scan --cached` accepts, in seconds.
+const DEFAULT_CACHE_AGE: u64 = 300;
+
+/// Exit status of `scan --diff-only` and `scan --new-only` when new devices
+/// showed up.
+const EXIT_NEW_DEVICES: u8 = 2;
+/// Exit status of `scan --fail-on-conflict` when an IP had several MACs.
+const EXIT_CONFLICT: u8 = 3;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Print the PF rules and commands that would run, without applying them
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Seconds a privileged command (pfctl, dnctl, nft, ...) may run before
+    /// it is stopped
+    #[arg(long, global = true, value_name = "SECS", default_value_t = SudoRunner::DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+    /// Log more: -v for debug messages, -vv for trace
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Log only errors, and hide progress spinners
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Selects the device, or devices, a command applies to.
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct Target {
+    /// Target IP address (IPv4 or IPv6); monitor, limit, allow and remove
+    /// take several, repeated or comma-separated
+    #[arg(short, long, value_parser = parse_ip, value_delimiter = ',')]
+    ip: Vec<IpAddr>,
+    /// Target MAC address, resolved to its current IP via the ARP cache
+    #[arg(short, long)]
+    mac: Option<MacAddr>,
+    /// Target alias, as defined with `alias add`
+    #[arg(short, long)]
+    name: Option<String>,
+    /// Target host name, resolved through DNS, or mDNS for .local names
+    #[arg(long)]
+    host: Option<String>,
+    /// Target network in CIDR notation (e.g., 192.168.4.0/24); the rules
+    /// match every address in it
+    #[arg(long, value_parser = parse_cidr)]
+    cidr: Option<IpNetwork>,
+    /// Target group, as defined with `group add`
+    #[arg(long)]
+    group: Option<String>,
+    /// Target the Nth device the last `scan` listed (within 10 minutes)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    device: Option<u64>,
+}
+
+fn parse_ip(s: &str) -> Result<IpAddr, String> {
+    s.parse()
+        .map_err(|_| format!("{} is not a valid IPv4 or IPv6 address", s))
+}
+
+/// Parses a network such as `192.168.4.0/24`. Host bits must be clear, so
+/// the rules match exactly the range the user meant.
+fn parse_cidr(s: &str) -> Result<IpNetwork, String> {
+    if !s.contains('/') {
+        return Err(format!(
+            "{} has no prefix length; use --ip for a single address",
+            s
+        ));
+    }
+    let network: IpNetwork = s
+        .parse()
+        .map_err(|_| format!("{} is not a valid network in CIDR notation", s))?;
+    if network.ip() != network.network() {
+        return Err(format!(
+            "{} has host bits set; did you mean {}/{}?",
+            s,
+            network.network(),
+            network.prefix()
+        ));
+    }
+    Ok(network)
+}
+
+/// Parses an IPv4 address, with a clearer error for IPv6 literals.
+fn parse_ipv4(s: &str) -> Result<Ipv4Addr, String> {
+    s.parse().map_err(|_| {
+        if s.parse::<std::net::Ipv6Addr>().is_ok() {
+            format!("{} is an IPv6 address; only IPv4 is supported here", s)
+        } else {
+            format!("{} is not a valid IPv4 address", s)
+        }
+    })
+}
+
+/// The IP of an alias or group member, looking MACs up in the ARP cache
+/// (and with `auto_scan`, on the local subnet).
+async fn resolve_member(member: AliasTarget, auto_scan: bool) -> Result<String> {
+    match member {
+        AliasTarget::Ip(ip) => Ok(ip.to_string()),
+        AliasTarget::Mac(mac) => {
+            let ip = resolve_mac_to_ip(mac, auto_scan).await?;
+            info!("Resolved {} to {}", mac, ip);
+            Ok(ip.to_string())
+        }
+    }
+}
+
+/// The devices `--except` names: IPs and MACs as given, aliases and groups
+/// looked up.
+fn resolve_excepts(names: &[String]) -> Result<Vec<AliasTarget>> {
+    let mut targets = Vec::new();
+    for name in names {
+        if let Ok(target) = name.parse() {
+            targets.push(target);
+        } else if alias::load()?.contains_key(name) {
+            targets.push(alias::lookup(name)?);
+        } else if alias::load_groups()?.contains_key(name) {
+            targets.extend(alias::group_members(name)?);
+        } else {
+            return Err(anyhow!("--except {}: not an IP, MAC, alias or group", name));
+        }
+    }
+    Ok(targets)
+}
+
+impl Target {
+    /// The --ip given, if any, refusing several.
+    fn single_ip(&self) -> Result<Option<IpAddr>> {
+        match self.ip.as_slice() {
+            [] => Ok(None),
+            [ip] => Ok(Some(*ip)),
+            _ => Err(anyhow!(
+                "This command takes a single device; several --ip only work with monitor, limit, allow and remove"
+            )),
+        }
+    }
+
+    /// Returns the target's IP, or its network for --cidr, looking up MAC
+    /// targets in the ARP cache. clap allows only one selector; should
+    /// several be set anyway, they win in the order --ip, --mac, --name,
+    /// --cidr, --device. --host and --group need [`Target::resolve_all`].
+    async fn resolve(&self) -> Result<String> {
+        self.resolve_with(false).await
+    }
+
+    /// [`Target::resolve`], sweeping the subnet for MACs not in the ARP
+    /// cache when `auto_scan` is set.
+    async fn resolve_with(&self, auto_scan: bool) -> Result<String> {
+        if let Some(ip) = self.single_ip()? {
+            return Ok(ip.to_string());
+        }
+        if let Some(mac) = self.mac {
+            return resolve_member(AliasTarget::Mac(mac), auto_scan).await;
+        }
+        if let Some(name) = &self.name {
+            return resolve_member(alias::lookup(name)?, auto_scan).await;
+        }
+        if let Some(cidr) = self.cidr {
+            return Ok(cidr.to_string());
+        }
+        if let Some(n) = self.device {
+            let device = scan::listed_device(n as usize)?;
+            info!("Device {} of the last scan is {}", n, device.ip);
+            return Ok(device.ip.to_string());
+        }
+        if self.group.is_some() || self.host.is_some() {
+            return Err(anyhow!(
+                "This command takes a single device; --group and --host only work with monitor, limit, allow and remove"
+            ));
+        }
+        Err(anyhow!(
+            "One of --ip, --mac, --name, --host, --cidr, --group or --device is required"
+        ))
+    }
+
+    /// Like [`Target::resolve`], but takes every --ip given and expands
+    /// --group into the IPs of its members. Members whose MAC isn't in the
+    /// ARP cache are skipped with a note, as long as at least one member
+    /// resolves. A --host name that resolves to several addresses is an
+    /// error unless `all` is set.
+    async fn resolve_all(&self, auto_scan: bool, all: bool) -> Result<Vec<String>> {
+        if let Some(host) = &self.host {
+            let addresses = resolve_host(host).await?;
+            let list = addresses
+                .iter()
+                .map(Ipv4Addr::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            if addresses.len() > 1 && !all {
+                return Err(anyhow!(
+                    "{} resolves to {} addresses ({}); pass --all to cover every one",
+                    host,
+                    addresses.len(),
+                    list
+                ));
+            }
+            println!("Resolved {} to {}", host, list);
+            return Ok(addresses.iter().map(Ipv4Addr::to_string).collect());
+        }
+        // clap's `requires` can't see through the target group
+        if all {
+            return Err(anyhow!("--all only applies to --host"));
+        }
+        if self.ip.len() > 1 {
+            let mut ips: Vec<String> = Vec::new();
+            for ip in self.ip.iter().map(IpAddr::to_string) {
+                if !ips.contains(&ip) {
+                    ips.push(ip);
+                }
+            }
+            return Ok(ips);
+        }
+        let Some(group) = &self.group else {
+            return Ok(vec![self.resolve_with(auto_scan).await?]);
+        };
+        let mut ips = Vec::new();
+        for member in alias::group_members(group)? {
+            match resolve_member(member, auto_scan).await {
+                Ok(ip) if !ips.contains(&ip) => ips.push(ip),
+                Ok(_) => {}
+                Err(e) => println!("Skipping {} in group {}: {}", member, group, e),
+            }
+        }
+        if ips.is_empty() {
+            return Err(anyhow!("No member of group {} could be resolved", group));
+        }
+        Ok(ips)
+    }
+
+    /// Returns the target's MAC. IPs, given directly or through an alias,
+    /// are looked up in the device history that `scan` keeps.
+    fn resolve_mac(&self) -> Result<MacAddr> {
+        let ip = match (self.single_ip()?, self.mac, &self.name) {
+            (Some(ip), _, _) => ip,
+            (None, Some(mac), _) => return Ok(mac),
+            (None, None, Some(name)) => match alias::lookup(name)? {
+                AliasTarget::Mac(mac) => return Ok(mac),
+                AliasTarget::Ip(ip) => ip,
+            },
+            (None, None, None) => {
+                return Err(anyhow!(
+                    "This command takes a single device; pass --ip, --mac or --name"
+                ))
+            }
+        };
+        let IpAddr::V4(v4) = ip else {
+            return Err(anyhow!(
+                "No MAC is recorded for IPv6 address {}; pass --mac",
+                ip
+            ));
+        };
+        history::mac_for_ip(v4)?.ok_or_else(|| {
+            anyhow!(
+                "No MAC known for {}; run `scan` while it is awake, or pass --mac",
+                ip
+            )
+        })
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Scan for devices on the network
     Scan {
-        /// Network interface (e.g., en0)
-        #[arg(short, long, default_value = "en0")]
-        interface: String,
+        /// Network interface (e.g., en0; detected from the default route if omitted)
+        #[arg(short, long)]
+        interface: Option<String>,
+        /// Subnet to scan instead of the interface's, e.g. 192.168.50.0/24
+        #[arg(short, long, value_parser = scan::parse_range)]
+        range: Option<Ipv4Network>,
+        /// Allow scanning ranges larger than a /16
+        #[arg(long)]
+        force: bool,
+        /// Print discovered devices as a JSON array
+        #[arg(long, group = "format")]
+        json: bool,
+        /// Print discovered devices as CSV, with a header row
+        #[arg(long, group = "format")]
+        csv: bool,
+        /// Write the --json or --csv output to this file instead of stdout
+        #[arg(short, long, value_name = "PATH", requires = "format")]
+        output: Option<PathBuf>,
+        /// Also save the devices to this file, as JSON or CSV by its extension
+        /// (.json or .csv)
+        #[arg(long, value_name = "PATH", value_parser = scan::OutputFile::parse,
+              conflicts_with = "output")]
+        output_file: Option<scan::OutputFile>,
+        /// Save the devices as a self-contained HTML page, e.g. to share
+        #[arg(long, value_name = "PATH", group = "format", conflicts_with = "output")]
+        html: Option<PathBuf>,
+        /// Discovery backend (defaults to nmap when installed, native otherwise)
+        #[arg(short, long, value_enum)]
+        method: Option<ScanMethod>,
+        /// Fail instead of falling back to the native sweep when nmap isn't
+        /// installed
+        #[arg(long, conflicts_with = "method")]
+        require_nmap: bool,
+        /// Skip reverse DNS lookups of discovered devices
+        #[arg(long)]
+        no_resolve: bool,
+        /// Skip Bonjour discovery of device names
+        #[arg(long)]
+        no_mdns: bool,
+        /// Skip NetBIOS name queries (UDP 137) to devices without another name
+        #[arg(long)]
+        no_netbios: bool,
+        /// Skip guessing device types, which pings and probes a few ports on
+        /// each device
+        #[arg(long)]
+        no_fingerprint: bool,
+        /// How thorough to be: fast skips nmap and all name and latency
+        /// lookups, thorough uses longer timeouts and also probes common ports
+        #[arg(long, value_enum, default_value_t = ScanProfile::Normal)]
+        profile: ScanProfile,
+        /// Have nmap guess each responding device's operating system (needs
+        /// root and nmap; slow, up to 30s per device)
+        #[arg(long)]
+        os_detect: bool,
+        /// Seconds each nmap run may take before it is stopped
+        #[arg(long, value_name = "SECS", default_value_t = scan::DEFAULT_NMAP_TIMEOUT.as_secs())]
+        nmap_timeout: u64,
+        /// nmap binary to run instead of the one in PATH
+        #[arg(long, value_name = "PATH")]
+        nmap_path: Option<PathBuf>,
+        /// Extra argument for every nmap run, e.g. --nmap-arg=--min-rate
+        /// --nmap-arg=500; repeatable. Output options are refused, since
+        /// wifi-kicker parses nmap's output itself
+        #[arg(long, value_name = "ARG", allow_hyphen_values = true,
+              value_parser = NmapCommand::check_arg)]
+        nmap_arg: Vec<String>,
+        /// Also read the ARP table of this router over SNMP v2c, for
+        /// devices this machine never talks to directly
+        #[arg(long, value_name = "ROUTER", value_parser = parse_ipv4)]
+        snmp: Option<Ipv4Addr>,
+        /// SNMP community string for --snmp
+        #[arg(long, default_value = snmp::DEFAULT_COMMUNITY, requires = "snmp")]
+        community: String,
+        /// Ping every IPv6 node on the link (ff02::1) first, so the IPv6
+        /// column covers devices this machine hasn't talked to
+        #[arg(long)]
+        ping6: bool,
+        /// Only show devices whose vendor, hostname, name or MAC contains
+        /// this (case-insensitive); repeatable, matching any
+        #[arg(long, value_name = "TEXT")]
+        filter: Vec<String>,
+        /// An IP or CIDR range never to probe (its devices are only shown
+        /// if in the ARP cache, tagged [excluded]); any other text hides
+        /// devices whose vendor, hostname, name or MAC contains it.
+        /// Repeatable
+        #[arg(long, value_name = "RANGE|TEXT")]
+        exclude: Vec<String>,
+        /// Column to order the table by
+        #[arg(long, value_enum, default_value_t = SortKey::Ip)]
+        sort: SortKey,
+        /// Sort in descending order (missing values still go last)
+        #[arg(long)]
+        reverse: bool,
+        /// Show the last scan of this interface instead of rescanning if it
+        /// is under 5 minutes old
+        #[arg(long, conflicts_with_all = ["watch", "diff_only"])]
+        cached: bool,
+        /// Like --cached, with a custom maximum age
+        #[arg(long, value_name = "SECS", conflicts_with_all = ["watch", "diff_only"])]
+        max_age: Option<u64>,
+        /// Always rescan, updating the saved scan (the default without
+        /// --cached or --max-age)
+        #[arg(long, conflicts_with_all = ["cached", "max_age"])]
+        refresh: bool,
+        /// Keep scanning every N seconds (minimum 5), highlighting changes
+        #[arg(short, long, value_name = "SECONDS", conflicts_with = "format",
+              value_parser = clap::value_parser!(u64).range(5..), conflicts_with = "output_file")]
+        watch: Option<u64>,
+        /// Only print devices that joined, left or changed IP since the last
+        /// scan; exits with status 2 when new devices were found
+        #[arg(long, conflicts_with_all = ["format", "watch"])]
+        diff_only: bool,
+        /// Only list devices first seen within --window, including ones
+        /// never seen before; exits with status 2 when there are any
+        #[arg(long, conflicts_with = "watch")]
+        new_only: bool,
+        /// How recently a device must have first been seen to count as new
+        /// with --new-only, e.g. 30m, 24h or 7d
+        #[arg(long, value_name = "AGE", default_value = "24h",
+              value_parser = history::parse_age)]
+        window: u64,
+        /// Exit with status 3 when an IP answered from several MACs or the
+        /// gateway's MAC changed since the last scan
+        #[arg(long, conflicts_with = "watch")]
+        fail_on_conflict: bool,
+        /// With --watch, block devices missing from the device history as
+        /// they appear, as `monitor` would; never the gateway or this host
+        #[arg(long, requires = "watch")]
+        auto_block: bool,
+        /// Like --auto-block, but only report what would be blocked
+        #[arg(long, requires = "watch", conflicts_with = "auto_block")]
+        auto_block_dry_run: bool,
+        /// Never auto-block this device: an IP, MAC, alias or group name;
+        /// repeatable
+        #[arg(long, value_name = "DEVICE")]
+        except: Vec<String>,
+    },
+    /// Wake a device with a Wake-on-LAN magic packet
+    Wake {
+        #[command(flatten)]
+        target: Target,
+        /// Interface whose subnet to broadcast on (detected from the default
+        /// route if omitted)
+        #[arg(long)]
+        interface: Option<String>,
+        /// UDP port to send to
+        #[arg(long, default_value_t = wol::DEFAULT_PORT)]
+        port: u16,
+        /// Number of packets to send
+        #[arg(long, default_value_t = 1,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+    },
+    /// Post a desktop notification whenever a device joins or leaves
+    Watch {
+        /// Network interface (detected from the default route if omitted)
+        #[arg(short, long)]
+        interface: Option<String>,
+        /// Seconds between scans (minimum 5)
+        #[arg(long, default_value_t = 60,
+              value_parser = clap::value_parser!(u64).range(5..))]
+        interval: u64,
+        /// Only notify about devices joining, not leaving
+        #[arg(long)]
+        join_only: bool,
+        /// Discovery backend (defaults to nmap when installed, native otherwise)
+        #[arg(short, long, value_enum)]
+        method: Option<ScanMethod>,
     },
     /// Monitor a specific device
     Monitor {
-        /// Target IP address
-        #[arg(short, long)]
-        ip: String,
+        #[command(flatten)]
+        target: Target,
         /// Enable persistent monitoring (survives reboots)
         #[arg(short, long)]
         persistent: bool,
+        /// Don't make the rule persistent, even if the config file says to
+        #[arg(long, conflicts_with = "persistent")]
+        no_persistent: bool,
+        /// Block even the gateway or this machine's own address
+        #[arg(long)]
+        force: bool,
+        /// Sweep the local subnet with nmap when a --mac target can't be
+        /// found otherwise
+        #[arg(long)]
+        auto_scan: bool,
+        /// Cover every address a --host name resolves to
+        #[arg(long)]
+        all: bool,
     },
     /// Limit bandwidth for a device
     Limit {
-        /// Target IP address
-        #[arg(short, long)]
-        ip: String,
+        #[command(flatten)]
+        target: Target,
         /// Upload speed limit in KB/s
         #[arg(short, long)]
         upload: Option<u32>,
@@ -47,244 +500,1377 @@ enum Commands {
         /// Enable persistent limiting (survives reboots)
         #[arg(short, long)]
         persistent: bool,
+        /// Don't make the limit persistent, even if the config file says to
+        #[arg(long, conflicts_with = "persistent")]
+        no_persistent: bool,
+        /// Limit even the gateway or this machine's own address
+        #[arg(long)]
+        force: bool,
+        /// Sweep the local subnet with nmap when a --mac target can't be
+        /// found otherwise
+        #[arg(long)]
+        auto_scan: bool,
+        /// Cover every address a --host name resolves to
+        #[arg(long)]
+        all: bool,
+    },
+    /// Unblock a device, keeping any bandwidth limit on it
+    #[command(visible_alias = "unblock")]
+    Allow {
+        #[command(flatten)]
+        target: Target,
     },
     /// Remove all rules for a specific IP
     Remove {
-        /// Target IP address
-        #[arg(short, long)]
-        ip: String,
+        #[command(flatten)]
+        target: Target,
+        /// Accepted for older scripts; `remove` no longer asks anything
+        #[arg(short, long, hide = true)]
+        yes: bool,
+        /// Sweep the local subnet with nmap when a --mac target can't be
+        /// found otherwise
+        #[arg(long)]
+        auto_scan: bool,
+        /// Cover every address a --host name resolves to
+        #[arg(long)]
+        all: bool,
+    },
+    /// Block a device during a recurring time window
+    Schedule {
+        #[command(flatten)]
+        target: Target,
+        /// Start of the block window, HH:MM
+        #[arg(long, required_unless_present = "clear")]
+        block_from: Option<TimeOfDay>,
+        /// End of the block window, HH:MM; earlier than --block-from means
+        /// the next day
+        #[arg(long, required_unless_present = "clear")]
+        block_until: Option<TimeOfDay>,
+        /// Days the window starts on, e.g. mon,tue (default: every day)
+        #[arg(long, value_enum, value_delimiter = ',')]
+        days: Vec<Weekday>,
+        /// Delete the device's schedule instead
+        #[arg(long, conflicts_with_all = ["block_from", "block_until", "days"])]
+        clear: bool,
+    },
+    /// Keep enforcing schedules and reapplying flushed rules until stopped
+    Daemon {
+        /// Seconds between checks
+        #[arg(long, default_value_t = 30,
+              value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Apply or lift scheduled blocks for the current time (run by launchd)
+    #[command(hide = true)]
+    ScheduleTick,
+    /// Print a shell completion script, e.g. `completions zsh >
+    /// ~/.zfunc/_rust-wifi-kicker`
+    #[command(hide = true)]
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
     },
     /// Show current rules and monitored IPs
-    Status,
+    Status {
+        /// Output format; json and csv report managed devices and their
+        /// live connection states instead of raw pfctl output
+        #[arg(long, value_enum, default_value_t = StatusFormat::Text)]
+        format: StatusFormat,
+    },
+    /// Show how much traffic a managed device sent and received
+    Usage {
+        #[command(flatten)]
+        target: Target,
+        /// Print the counters as JSON
+        #[arg(long)]
+        json: bool,
+        /// Zero the counters after reporting them (with PF, those of every
+        /// loaded rule)
+        #[arg(long)]
+        reset: bool,
+    },
+    /// Full-screen view of the managed devices' live traffic rates
+    Top,
+    /// List the devices wifi-kicker currently manages
+    List {
+        /// Print managed targets as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Manage friendly names usable with --name instead of --ip/--mac
+    Alias {
+        #[command(subcommand)]
+        action: AliasAction,
+    },
+    /// Manage named groups of devices, for --group
+    Group {
+        #[command(subcommand)]
+        action: GroupAction,
+    },
+    /// Inspect the config file of flag defaults
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// List every device seen by past scans
+    #[command(args_conflicts_with_subcommands = true)]
+    Devices {
+        #[command(subcommand)]
+        action: Option<DevicesAction>,
+        /// Only devices seen within this long, e.g. 30m, 12h, 7d
+        #[arg(long, value_name = "AGE", value_parser = history::parse_age)]
+        since: Option<u64>,
+        /// Only devices first seen within this long, to spot newcomers
+        #[arg(long, value_name = "AGE", value_parser = history::parse_age)]
+        new_since: Option<u64>,
+        /// Print the inventory as JSON, keyed by MAC
+        #[arg(long)]
+        json: bool,
+    },
+    /// Download the IEEE OUI registry to ~/.wifi-kicker/oui.txt, which
+    /// vendor lookups then prefer over the built-in table
+    UpdateOui {
+        /// CSV to download, in the IEEE's format
+        #[arg(long, default_value = oui::IEEE_OUI_URL)]
+        url: String,
+    },
+    /// Remove every rule, file and setting wifi-kicker created, leaving
+    /// other firewall rules alone
+    Clear {
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// Keep the PF configuration backups in ~/.wifi-kicker/backups/
+        #[arg(long)]
+        keep_backups: bool,
+    },
+    /// Print the aliases, groups, schedules and managed devices as JSON,
+    /// for `import` on another machine
+    Export,
+    /// Merge a file written by `export` into this machine's setup and
+    /// reload the rules; importing it again changes nothing
+    Import {
+        path: PathBuf,
+        /// Import rules on the gateway or this machine's own address too
+        #[arg(long)]
+        force: bool,
+    },
+    /// Save /etc/pf.conf and the PF anchors to ~/.wifi-kicker/backups/
+    Backup,
+    /// Put back a PF configuration saved by `backup` and reload PF
+    Restore {
+        /// Backup archive, e.g. ~/.wifi-kicker/backups/pf-20240101-120000.tar.gz
+        path: PathBuf,
+    },
+    /// List network interfaces with their addresses and link status
+    Interfaces {
+        /// Print interfaces as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Scan a device for open TCP ports
+    Probe {
+        #[command(flatten)]
+        target: Target,
+        /// Ports to scan, e.g. "22,80,8000-8100" (default: the most common ones)
+        #[arg(short, long)]
+        ports: Option<String>,
+        /// Number of common ports to scan when --ports isn't given
+        #[arg(long, default_value_t = 50)]
+        top: usize,
+        /// Per-port connect timeout in milliseconds
+        #[arg(long, default_value_t = 500)]
+        timeout_ms: u64,
+        /// Interface whose subnet the target must be on (detected if omitted)
+        #[arg(long)]
+        interface: Option<String>,
+        /// Allow probing addresses outside the local subnet
+        #[arg(long)]
+        allow_external: bool,
+    },
 }
 
-fn check_root() -> Result<()> {
-    if !Command::new("id")
-        .arg("-u")
-        .output()
-        .map(|o| String::from_utf8_lossy(&o.stdout).trim() == "0")
-        .unwrap_or(false)
-    {
-        return Err(anyhow!(
-            "This command requires root privileges. Please run with sudo."
-        ));
-    }
-    Ok(())
+#[derive(Subcommand)]
+enum AliasAction {
+    /// Name a device by its IP or MAC address, refusing a name in use
+    Add {
+        name: String,
+        #[arg(value_name = "IP|MAC")]
+        target: AliasTarget,
+    },
+    /// Name a device, or point an existing name at another device
+    Set {
+        name: String,
+        #[arg(value_name = "IP|MAC")]
+        target: AliasTarget,
+    },
+    /// List the defined aliases
+    List {
+        /// Print aliases as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete an alias; rules on its device stay in place
+    Rm { name: String },
 }
 
-fn run_sudo_command(cmd: &str, args: &[&str]) -> Result<Output> {
-    let output = Command::new("sudo")
-        .arg(cmd)
-        .args(args)
-        .output()
-        .with_context(|| format!("Failed to run sudo command: {} {:?}", cmd, args))?;
-
-    if !output.status.success() {
-        error!("Command failed: {} {:?}", cmd, args);
-        error!("Error: {}", String::from_utf8_lossy(&output.stderr));
-        return Err(anyhow!(
-            "Command failed: {}\nError: {}",
-            cmd,
-            String::from_utf8_lossy(&output.stderr)
-        ));
-    }
-
-    Ok(output)
+#[derive(Subcommand)]
+enum GroupAction {
+    /// Add devices to a group, creating it if needed
+    Add {
+        name: String,
+        #[arg(value_name = "IP|MAC", required = true)]
+        members: Vec<AliasTarget>,
+    },
+    /// List the defined groups
+    List {
+        /// Print groups as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete a group
+    Rm { name: String },
 }
 
-fn save_state(rules: &str, persistent: bool) -> Result<()> {
-    fs::write(PF_RULES_FILE, rules)?;
+#[derive(Subcommand)]
+enum DevicesAction {
+    /// Merge the history of an earlier private MAC into a newer one, as the
+    /// same device (see the suggestions `scan` prints)
+    Link {
+        /// The MAC the device used before
+        old: MacAddr,
+        /// The MAC it uses now
+        new: MacAddr,
+    },
+}
 
-    if persistent {
-        // Save to a permanent location for persistence
-        run_sudo_command("cp", &[PF_RULES_FILE, "/etc/pf.anchors/com.wifi-kicker"])?;
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print where the config file is read from
+    Path,
+}
 
-        // Add anchor to main pf.conf if not already present
-        let pf_conf = fs::read_to_string("/etc/pf.conf")?;
-        if !pf_conf.contains("com.wifi-kicker") {
-            let anchor_rule = "anchor \"com.wifi-kicker\"";
-            let new_conf = format!("{}\n{}\n", pf_conf, anchor_rule);
-            fs::write("/tmp/pf.conf", new_conf)?;
-            run_sudo_command("cp", &["/tmp/pf.conf", "/etc/pf.conf"])?;
+/// Splits `scan --exclude` values into address ranges and text filters.
+/// Dotted values made of digits, dots and slashes are meant as addresses,
+/// so an invalid one is an error rather than a filter that never matches.
+fn split_excludes(values: &[String]) -> Result<(Vec<Ipv4Network>, Vec<String>)> {
+    let mut ranges = Vec::new();
+    let mut text = Vec::new();
+    for value in values {
+        let address_like = value.contains('.')
+            && value
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == '.' || c == '/');
+        if !address_like {
+            text.push(value.clone());
+            continue;
         }
+        let network: Ipv4Network = value
+            .parse()
+            .map_err(|e| anyhow!("Invalid --exclude {}: {}", value, e))?;
+        // 192.168.1.70/27 means the /27 it is in
+        ranges.push(Ipv4Network::new(network.network(), network.prefix())?);
     }
-
-    Ok(())
+    Ok((ranges, text))
 }
 
-fn scan_network(interface: &str) -> Result<()> {
-    // Check if interface exists
-    let ifconfig_output = Command::new("ifconfig")
-        .arg(interface)
-        .output()
-        .context("Failed to check interface")?;
-
-    if !ifconfig_output.status.success() {
-        return Err(anyhow!("Interface {} not found", interface));
+/// Fills in the flags `command` was run without from the config file.
+fn apply_config(command: &mut Commands, config: &Config) {
+    if let Commands::Scan {
+        nmap_path,
+        nmap_arg,
+        ..
+    } = command
+    {
+        if nmap_path.is_none() {
+            nmap_path.clone_from(&config.nmap_path);
+        }
+        if nmap_arg.is_empty() {
+            nmap_arg.clone_from(&config.nmap_args);
+        }
     }
+    match command {
+        Commands::Scan { interface, .. }
+        | Commands::Wake { interface, .. }
+        | Commands::Watch { interface, .. }
+        | Commands::Probe { interface, .. }
+            if interface.is_none() =>
+        {
+            interface.clone_from(&config.interface);
+        }
+        Commands::Monitor {
+            persistent,
+            no_persistent,
+            ..
+        } => *persistent |= config.persistent && !*no_persistent,
+        Commands::Limit {
+            upload,
+            download,
+            persistent,
+            no_persistent,
+            ..
+        } => {
+            // The caps go together; a cap given on the command line means
+            // the file's aren't wanted
+            if upload.is_none() && download.is_none() {
+                *upload = config.upload;
+                *download = config.download;
+            }
+            *persistent |= config.persistent && !*no_persistent;
+        }
+        _ => {}
+    }
+}
 
-    // Get current WiFi network name
-    let output = Command::new("networksetup")
-        .args(["-getairportnetwork", interface])
-        .output()
-        .context("Failed to get current network")?;
-
+/// Broadcasts Wake-on-LAN packets for `mac` on the subnet of `interface`.
+fn wake_device(
+    ctx: &RunContext,
+    mac: MacAddr,
+    interface: Option<&str>,
+    port: u16,
+    count: u32,
+) -> Result<()> {
+    let interface = interface::resolve(interface)?;
+    let ifconfig = scan::interface_config(&interface)?;
+    let no_address = || KickerError::NoIpv4Address(interface.clone());
+    let (local, _) = sweep::parse_ifconfig_inet(&ifconfig).ok_or_else(no_address)?;
+    let broadcast = sweep::interface_network(&ifconfig)
+        .ok_or_else(no_address)?
+        .broadcast();
+    if ctx.dry_run {
+        println!(
+            "would send {} magic packet(s) for {} to {}:{} on {}",
+            count, mac, broadcast, port, interface
+        );
+        return Ok(());
+    }
+    wol::wake(mac, local, broadcast, port, count)?;
     println!(
-        "Current network: {}",
-        String::from_utf8_lossy(&output.stdout)
+        "Sent {} magic packet(s) for {} to {}:{} on {}",
+        count, mac, broadcast, port, interface
     );
-
-    // Get network details including subnet
-    let ifconfig_output = Command::new("ifconfig")
-        .arg(interface)
-        .output()
-        .context("Failed to get interface details")?;
-    let ifconfig_str = String::from_utf8_lossy(&ifconfig_output.stdout);
-
-    // Perform active network scan using nmap
-    println!("\nScanning network for active devices...");
-    let nmap_output = Command::new("nmap")
-        .args(["-sn", &format!("-e{}", interface), "-oG", "-"]) // -sn performs ping scan
-        .output()
-        .context("Failed to run nmap scan. Please ensure nmap is installed.")?;
-
-    println!("\nDiscovered devices:");
-    println!("{}", String::from_utf8_lossy(&nmap_output.stdout));
-
-    // Still include ARP cache for recently seen devices
-    let arp_output = Command::new("arp")
-        .arg("-a")
-        .output()
-        .context("Failed to run ARP scan")?;
-
-    println!("\nRecently active devices (ARP cache):");
-    println!("{}", String::from_utf8_lossy(&arp_output.stdout));
-
     Ok(())
 }
 
-fn setup_monitoring(ip: &str, persistent: bool) -> Result<()> {
-    check_root()?;
-
-    // Create PF rules for monitoring
-    let rules = format!(
-        "# Monitoring rules for {}\n\
-         block drop in proto {{tcp udp icmp}} from {} to any\n\
-         block drop out proto {{tcp udp icmp}} from any to {}\n",
-        ip, ip, ip
-    );
-
-    save_state(&rules, persistent)?;
-
-    // Enable PF if not already enabled (ignore if already enabled)
-    let _ = run_sudo_command("pfctl", &["-e"]);
-
-    // Load the rules
-    run_sudo_command("pfctl", &["-f", PF_RULES_FILE])?;
+/// Asks `question` on the terminal, defaulting to no. Without a terminal
+/// there is nobody to ask, so the caller must have passed `--yes`.
+fn confirm(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "{} Refusing to continue without a terminal to confirm on; pass --yes",
+            question
+        ));
+    }
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
 
-    info!("Started monitoring {} (persistent: {})", ip, persistent);
+fn remove_rules(ctx: &RunContext, ips: &[String]) -> Result<()> {
+    ctx.check_root()?;
+    let state = State::load()?;
+    let (managed, unmanaged): (Vec<&String>, Vec<&String>) = ips
+        .iter()
+        .partition(|ip| state.targets.iter().any(|t| &t.ip == *ip));
+    for ip in unmanaged {
+        warn!("{} is not managed by wifi-kicker, nothing to remove", ip);
+    }
+    if managed.is_empty() {
+        return Ok(());
+    }
+    let managed: Vec<String> = managed.into_iter().cloned().collect();
+    rust_wifi_kicker::remove(ctx, &managed)?;
     Ok(())
 }
 
-fn setup_bandwidth_limit(
-    ip: &str,
-    upload: Option<u32>,
-    download: Option<u32>,
-    persistent: bool,
+/// The IPv4 address of the single device `probe` scans, with aliases and
+/// MACs looked up.
+async fn probe_address(target: &Target) -> Result<Ipv4Addr> {
+    if target.cidr.is_some() {
+        return Err(anyhow!(
+            "probe scans a single device; --cidr only works with monitor, limit, allow and remove"
+        ));
+    }
+    parse_ipv4(&target.resolve().await?).map_err(|e| anyhow!(e))
+}
+
+async fn probe_device(
+    ip: Ipv4Addr,
+    ports: Option<&str>,
+    top: usize,
+    per_port: Duration,
+    interface: Option<&str>,
+    allow_external: bool,
 ) -> Result<()> {
-    check_root()?;
+    if !allow_external {
+        let interface = interface::resolve(interface)?;
+        let interface = interface.as_str();
+        let network = sweep::interface_network(&scan::interface_config(interface)?)
+            .ok_or_else(|| KickerError::NoIpv4Address(interface.to_string()))?;
+        if !network.contains(ip) {
+            return Err(anyhow!(
+                "{} is outside the local subnet {}; pass --allow-external to probe it anyway",
+                ip,
+                network
+            ));
+        }
+    }
 
-    let mut rules = String::new();
-    rules.push_str(&format!("# Bandwidth limiting rules for {}\n", ip));
+    let ports = match ports {
+        Some(spec) => probe::parse_ports(spec)?,
+        None => probe::top_ports(top),
+    };
+    println!("Probing {} ports on {}...", ports.len(), ip);
+    let open = probe::scan_ports(ip, &ports, per_port).await;
 
-    // Simple rate limiting using state tracking
-    if let Some(up) = upload {
-        rules.push_str(&format!(
-            "pass out proto tcp from {} to any flags S/SA keep state \
-            (max-src-states {}, max-src-conn-rate {}/5)\n",
-            ip, up, up
-        ));
+    if open.is_empty() {
+        println!("No open ports found.");
+        return Ok(());
     }
+    let rows: Vec<[String; 2]> = open
+        .iter()
+        .map(|port| {
+            [
+                format!("{}/tcp", port),
+                probe::service_name(*port).unwrap_or("unknown").to_string(),
+            ]
+        })
+        .collect();
+    print_table(["PORT", "SERVICE"], &rows, |_| false);
+    Ok(())
+}
 
-    if let Some(down) = download {
-        rules.push_str(&format!(
-            "pass in proto tcp from any to {} flags S/SA keep state \
-            (max-src-states {}, max-src-conn-rate {}/5)\n",
-            ip, down, down
-        ));
+fn set_schedule(ctx: &RunContext, ip: &str, schedule: Option<Schedule>) -> Result<()> {
+    ctx.check_root()?;
+    let mut state = State::load()?;
+    state.schedules.retain(|s| s.ip != ip);
+    match schedule {
+        Some(schedule) => {
+            info!("Scheduled: {}", schedule);
+            state.schedules.push(schedule);
+        }
+        None => info!("Cleared the schedule for {}", ip),
     }
+    schedule::install(ctx, &state.schedules)?;
+    // Bring the rules in line with the new windows right away
+    if schedule::enforce(&mut state)? {
+        ctx.firewall.apply(ctx, &state.targets)?;
+    }
+    ctx.save(&state)
+}
 
-    save_state(&rules, persistent)?;
-
-    // Enable PF if not already enabled (ignore if already enabled)
-    let _ = run_sudo_command("pfctl", &["-e"]);
+fn print_schedules(schedules: &[Schedule]) {
+    if !schedules.is_empty() {
+        println!("\nSchedules:");
+        for schedule in schedules {
+            println!("  {}", schedule);
+        }
+    }
+}
 
-    // Load the rules
-    run_sudo_command("pfctl", &["-f", PF_RULES_FILE])?;
+fn list_targets(json: bool) -> Result<()> {
+    let state = State::load()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&state.targets)?);
+        return Ok(());
+    }
+    if state.targets.is_empty() {
+        println!("No devices currently managed.");
+        print_schedules(&state.schedules);
+        return Ok(());
+    }
 
-    info!(
-        "Bandwidth limits applied for {} (persistent: {})",
-        ip, persistent
+    let dash = || "-".to_string();
+    let rate = |kbytes: Option<u32>| kbytes.map_or_else(dash, |k| format!("{} KB/s", k));
+    let rows: Vec<[String; 7]> = state
+        .targets
+        .iter()
+        .map(|t| {
+            [
+                t.ip.clone(),
+                t.mac.map_or_else(dash, |mac| mac.to_string()),
+                t.kind.label().to_string(),
+                rate(t.upload),
+                rate(t.download),
+                if t.persistent { "yes" } else { "no" }.to_string(),
+                format_age(t.added),
+            ]
+        })
+        .collect();
+    print_table(
+        [
+            "IP",
+            "MAC",
+            "MODE",
+            "UPLOAD",
+            "DOWNLOAD",
+            "PERSISTENT",
+            "ADDED",
+        ],
+        &rows,
+        |_| false,
     );
+    print_schedules(&state.schedules);
     Ok(())
 }
 
-fn remove_rules(ip: &str) -> Result<()> {
-    check_root()?;
-
-    // Flush all rules for the IP
-    run_sudo_command("pfctl", &["-F", "all"])?;
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum StatusFormat {
+    Text,
+    Json,
+    Csv,
+}
 
-    // Remove persistent rules if they exist
-    if Path::new("/etc/pf.anchors/com.wifi-kicker").exists() {
-        run_sudo_command("rm", &["/etc/pf.anchors/com.wifi-kicker"])?;
+/// The command that sets `target` up again the way it was.
+fn reapply_command(target: &ManagedTarget) -> String {
+    let mut command = match target.kind {
+        RuleKind::Monitor => format!("sudo rust-wifi-kicker monitor --ip {}", target.ip),
+        RuleKind::Limit => format!("sudo rust-wifi-kicker limit --ip {}", target.ip),
+    };
+    if let Some(up) = target.upload {
+        command.push_str(&format!(" --upload {}", up));
     }
-
-    info!("Removed all rules for {}", ip);
-    Ok(())
+    if let Some(down) = target.download {
+        command.push_str(&format!(" --download {}", down));
+    }
+    if target.persistent {
+        command.push_str(" --persistent");
+    }
+    command
 }
 
-fn show_status() -> Result<()> {
-    check_root()?;
+/// Lists managed devices with whether their rules are actually loaded, and
+/// how to restore the ones that aren't (for example after `pfctl -F all`).
+fn print_reconciliation(report: &StatusReport) {
+    if report.targets.is_empty() {
+        return;
+    }
+    let (active, missing): (Vec<_>, Vec<_>) = report.targets.iter().partition(|t| t.rules_loaded);
+    let aliases = alias::load().unwrap_or_default();
+    let row = |t: &ManagedTarget, rules: &str| {
+        [
+            t.ip.clone(),
+            alias::names_for(&aliases, t.ip.parse().ok(), t.mac).unwrap_or_else(|| "-".to_string()),
+            t.mac.map_or_else(|| "-".to_string(), |mac| mac.to_string()),
+            t.kind.label().to_string(),
+            rules.to_string(),
+        ]
+    };
+    let rows: Vec<[String; 5]> = active
+        .iter()
+        .map(|t| row(&t.target, "active"))
+        .chain(
+            missing
+                .iter()
+                .map(|t| row(&t.target, "MISSING (rule not loaded)")),
+        )
+        .collect();
+    println!("\nManaged devices:");
+    print_table(["IP", "NAME", "MAC", "MODE", "RULES"], &rows, |_| false);
 
-    println!("Current PF rules:");
-    let rules_output = run_sudo_command("pfctl", &["-sr"])?;
-    println!("{}", String::from_utf8_lossy(&rules_output.stdout));
+    if !missing.is_empty() {
+        println!("\nRe-apply the missing rules with:");
+        for status in missing {
+            println!("  {}", reapply_command(&status.target));
+        }
+    }
+}
 
-    println!("\nCurrent states:");
-    let states_output = run_sudo_command("pfctl", &["-ss"])?;
-    println!("{}", String::from_utf8_lossy(&states_output.stdout));
+fn show_usage(firewall: &dyn Firewall, sudo: &SudoRunner, ip: &str, json: bool) -> Result<()> {
+    let usage = rust_wifi_kicker::usage(firewall, sudo, ip)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&usage)?);
+        return Ok(());
+    }
+    let rules: Vec<String> = State::load()?
+        .targets
+        .iter()
+        .filter(|t| t.ip == ip)
+        .map(ManagedTarget::rule_summary)
+        .collect();
+    println!("Traffic for {} ({}):", ip, rules.join(", "));
+    println!(
+        "  Received: {} ({} packets)",
+        format_bytes(usage.in_bytes),
+        usage.in_packets
+    );
+    println!(
+        "  Sent:     {} ({} packets)",
+        format_bytes(usage.out_bytes),
+        usage.out_packets
+    );
+    Ok(())
+}
 
+fn show_status(firewall: &dyn Firewall, sudo: &SudoRunner, format: StatusFormat) -> Result<()> {
+    let report = rust_wifi_kicker::status(firewall, sudo)?;
+    match format {
+        StatusFormat::Text => {
+            firewall.show(sudo)?;
+            print_reconciliation(&report);
+        }
+        StatusFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        StatusFormat::Csv => {
+            // One row per connection, tagged with the managed device it
+            // belongs to, if any
+            println!(
+                "{}",
+                csv_row(&["target", "proto", "src", "dst", "packets", "bytes"])
+            );
+            for s in &report.states {
+                let target = report
+                    .targets
+                    .iter()
+                    .find(|t| s.involves(&t.target.ip))
+                    .map_or("", |t| t.target.ip.as_str());
+                println!(
+                    "{}",
+                    csv_row(&[
+                        target,
+                        &s.proto,
+                        &s.src,
+                        &s.dst,
+                        &s.packets.to_string(),
+                        &s.bytes.to_string(),
+                    ])
+                );
+            }
+        }
+    }
     Ok(())
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
+async fn main() -> ExitCode {
+    // Parsed first, so --help and --version work whatever the config says
     let cli = Cli::parse();
+    // Commands that touch nothing the config sets run without it, so a
+    // broken file can still be located
+    let needs_config = !matches!(
+        cli.command,
+        Commands::Config { .. } | Commands::Completions { .. }
+    );
+    let config = match needs_config.then(config::load).transpose() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    // RUST_LOG still overrides the flags, for filtering by module
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => "error",
+        (false, 0) => config.log_level.as_deref().unwrap_or("info"),
+        (false, 1) => "debug",
+        (false, _) => "trace",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level)).init();
+    match run(cli, config).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            if let Some(hint) = e.downcast_ref::<KickerError>().and_then(KickerError::hint) {
+                eprintln!("{}", hint);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The external programs `command` runs. Dry runs don't execute the
+/// privileged ones, so they aren't required then.
+fn required_tools(command: &Commands, firewall: &dyn Firewall, dry_run: bool) -> Vec<&'static str> {
+    let mut tools = Vec::new();
+    let privileged = |tools: &mut Vec<&'static str>, extra: &[&'static str]| {
+        if !dry_run {
+            tools.push("sudo");
+            tools.extend_from_slice(firewall.tools());
+            tools.extend_from_slice(extra);
+        }
+    };
+    let neighbors = if cfg!(target_os = "linux") && find_in_path("ip").is_some() {
+        "ip"
+    } else {
+        "arp"
+    };
+    match command {
+        Commands::Scan {
+            method,
+            require_nmap,
+            os_detect,
+            nmap_path,
+            ..
+        } => {
+            tools.extend(["ifconfig", neighbors]);
+            // An nmap outside PATH is checked when the scan starts
+            if (*require_nmap || *os_detect || *method == Some(ScanMethod::Nmap))
+                && nmap_path.is_none()
+            {
+                tools.push("nmap");
+            }
+        }
+        Commands::Watch { method, .. } => {
+            tools.extend(["ifconfig", neighbors]);
+            if cfg!(target_os = "macos") {
+                tools.push("osascript");
+            }
+            if *method == Some(ScanMethod::Nmap) {
+                tools.push("nmap");
+            }
+        }
+        Commands::Monitor { .. }
+        | Commands::Limit { .. }
+        | Commands::Allow { .. }
+        | Commands::Remove { .. } => {
+            privileged(&mut tools, &[]);
+        }
+        Commands::Schedule { .. } => {
+            let launchd: &[&str] = if cfg!(target_os = "macos") {
+                &["launchctl"]
+            } else {
+                &[]
+            };
+            privileged(&mut tools, launchd);
+        }
+        Commands::Daemon { .. }
+        | Commands::ScheduleTick
+        | Commands::Import { .. }
+        | Commands::Clear { .. } => privileged(&mut tools, &[]),
+        Commands::Status { .. } | Commands::Usage { .. } | Commands::Top => {
+            tools.push("sudo");
+            tools.extend_from_slice(firewall.tools());
+        }
+        Commands::Backup | Commands::Restore { .. } => {
+            tools.push("tar");
+            privileged(&mut tools, &[]);
+        }
+        Commands::Interfaces { .. } => tools.push("ifconfig"),
+        Commands::UpdateOui { .. } => tools.push("curl"),
+        Commands::List { .. }
+        | Commands::Wake { .. }
+        | Commands::Alias { .. }
+        | Commands::Group { .. }
+        | Commands::Config { .. }
+        | Commands::Export
+        | Commands::Devices { .. }
+        | Commands::Probe { .. }
+        | Commands::Completions { .. } => {}
+    }
+    tools
+}
+
+async fn run(mut cli: Cli, config: Config) -> Result<ExitCode> {
+    apply_config(&mut cli.command, &config);
+    let settings = RunSettings::new(cli.dry_run, Duration::from_secs(cli.timeout));
+    let sudo = SudoRunner::new(settings.timeout);
+    let firewall = firewall::detect();
+    let ctx = RunContext {
+        dry_run: cli.dry_run,
+        runner: if cli.dry_run {
+            settings.dry_runner.as_ref()
+        } else {
+            &sudo
+        },
+        firewall: firewall.as_ref(),
+    };
+    check_dependencies(&required_tools(&cli.command, ctx.firewall, cli.dry_run))?;
 
     match &cli.command {
-        Commands::Scan { interface } => {
-            scan_network(interface)?;
+        Commands::Scan {
+            interface,
+            range,
+            force,
+            json,
+            csv,
+            output,
+            output_file,
+            html,
+            method,
+            require_nmap,
+            no_resolve,
+            no_mdns,
+            no_netbios,
+            no_fingerprint,
+            os_detect,
+            nmap_timeout,
+            nmap_path,
+            nmap_arg,
+            snmp,
+            community,
+            ping6,
+            profile,
+            filter,
+            exclude,
+            sort,
+            reverse,
+            cached,
+            max_age,
+            refresh: _,
+            watch,
+            diff_only,
+            new_only,
+            window,
+            fail_on_conflict,
+            auto_block,
+            auto_block_dry_run,
+            except,
+        } => {
+            if *os_detect {
+                check_root()?;
+            }
+            let interface = interface::resolve(interface.as_deref())?;
+            let (excluded_ranges, exclude) = split_excludes(exclude)?;
+            let scanned = match range {
+                Some(range) => Some(*range),
+                None => sweep::interface_network(&scan::interface_config(&interface)?),
+            };
+            let excluded_ranges = excluded_ranges
+                .into_iter()
+                .filter(|excluded| {
+                    let overlaps = scanned.is_none_or(|scanned| {
+                        scanned.contains(excluded.network()) || excluded.contains(scanned.network())
+                    });
+                    if !overlaps {
+                        eprintln!(
+                            "Warning: --exclude {} is outside the scanned range {}; ignoring it",
+                            excluded,
+                            scanned.map_or("-".to_string(), |s| s.to_string())
+                        );
+                    }
+                    overlaps
+                })
+                .collect();
+            let options = ScanOptions {
+                interface: &interface,
+                range: *range,
+                force: *force,
+                exclude: excluded_ranges,
+                method: match require_nmap {
+                    true => Some(ScanMethod::Nmap),
+                    false => *method,
+                },
+                resolve: !*no_resolve,
+                mdns: !*no_mdns,
+                netbios: !*no_netbios,
+                fingerprint: !*no_fingerprint,
+                os_detect: *os_detect,
+                nmap_timeout: Duration::from_secs(*nmap_timeout),
+                nmap: NmapCommand {
+                    path: nmap_path.clone(),
+                    args: nmap_arg.clone(),
+                },
+                snmp: snmp.map(|address| SnmpRouter {
+                    address,
+                    community: community.clone(),
+                }),
+                ping_ipv6: *ping6,
+                profile: *profile,
+                filter: DeviceFilter::new(filter, &exclude),
+                sort: *sort,
+                reverse: *reverse,
+                progress: !cli.quiet && std::io::stdout().is_terminal(),
+                max_age: max_age.or(cached.then_some(DEFAULT_CACHE_AGE)),
+            };
+            match watch {
+                Some(seconds) => {
+                    let auto_block = match (auto_block, auto_block_dry_run) {
+                        (false, false) => None,
+                        (_, report_only) => {
+                            if !report_only {
+                                ctx.check_root()?;
+                            }
+                            Some(scan::AutoBlock {
+                                settings: settings.clone(),
+                                except: resolve_excepts(except)?,
+                                report_only: *report_only,
+                            })
+                        }
+                    };
+                    scan::watch(&options, Duration::from_secs(*seconds), auto_block).await?
+                }
+                None => {
+                    let format = match (json, csv, html) {
+                        (true, _, _) => OutputFormat::Json,
+                        (_, true, _) => OutputFormat::Csv,
+                        (_, _, Some(_)) => OutputFormat::Html,
+                        _ => OutputFormat::Text,
+                    };
+                    let output = html.as_deref().or(output.as_deref());
+                    let summary = scan::scan_network(
+                        &options,
+                        format,
+                        output,
+                        output_file.as_ref(),
+                        *diff_only,
+                        new_only.then_some(*window),
+                    )
+                    .await?;
+                    if *fail_on_conflict && !summary.conflicts.is_empty() {
+                        return Ok(ExitCode::from(EXIT_CONFLICT));
+                    }
+                    if *new_only && summary.shown > 0 {
+                        return Ok(ExitCode::from(EXIT_NEW_DEVICES));
+                    }
+                    if *diff_only && summary.has_new {
+                        return Ok(ExitCode::from(EXIT_NEW_DEVICES));
+                    }
+                }
+            }
+        }
+        Commands::Wake {
+            target,
+            interface,
+            port,
+            count,
+        } => {
+            wake_device(
+                &ctx,
+                target.resolve_mac()?,
+                interface.as_deref(),
+                *port,
+                *count,
+            )?;
+        }
+        Commands::Watch {
+            interface,
+            interval,
+            join_only,
+            method,
+        } => {
+            let interface = interface::resolve(interface.as_deref())?;
+            let options = ScanOptions {
+                interface: &interface,
+                range: None,
+                force: false,
+                exclude: Vec::new(),
+                method: *method,
+                resolve: true,
+                mdns: true,
+                netbios: true,
+                // Device types don't help telling who joined, and cost probes
+                fingerprint: false,
+                os_detect: false,
+                nmap_timeout: scan::DEFAULT_NMAP_TIMEOUT,
+                nmap: NmapCommand {
+                    path: config.nmap_path.clone(),
+                    args: config.nmap_args.clone(),
+                },
+                snmp: None,
+                ping_ipv6: false,
+                profile: ScanProfile::Normal,
+                filter: DeviceFilter::new(&[], &[]),
+                sort: SortKey::Ip,
+                reverse: false,
+                progress: false,
+                max_age: None,
+            };
+            scan::notify_changes(&options, Duration::from_secs(*interval), *join_only).await?;
         }
-        Commands::Monitor { ip, persistent } => {
-            setup_monitoring(ip, *persistent)?;
+        Commands::Monitor {
+            target,
+            persistent,
+            force,
+            auto_scan,
+            all,
+            ..
+        } => {
+            let ips = target.resolve_all(*auto_scan, *all).await?;
+            for ip in &ips {
+                interface::check_host_role(ip, *force)?;
+            }
+            rust_wifi_kicker::monitor(&ctx, &ips, *persistent)?;
         }
         Commands::Limit {
-            ip,
+            target,
             upload,
             download,
             persistent,
+            force,
+            auto_scan,
+            all,
+            ..
+        } => {
+            let ips = target.resolve_all(*auto_scan, *all).await?;
+            for ip in &ips {
+                interface::check_host_role(ip, *force)?;
+            }
+            rust_wifi_kicker::limit(&ctx, &ips, *upload, *download, *persistent)?;
+        }
+        Commands::Allow { target } => {
+            let ips = target.resolve_all(false, false).await?;
+            for ip in rust_wifi_kicker::allow(&ctx, &ips)? {
+                println!("{} is not blocked by wifi-kicker; nothing to allow", ip);
+            }
+        }
+        Commands::Remove {
+            target,
+            auto_scan,
+            all,
+            ..
+        } => {
+            let ips = target.resolve_all(*auto_scan, *all).await?;
+            remove_rules(&ctx, &ips)?;
+        }
+        Commands::Schedule {
+            target,
+            block_from,
+            block_until,
+            days,
+            clear,
+        } => {
+            let ip = target.resolve().await?;
+            let schedule = match (clear, block_from, block_until) {
+                (false, Some(from), Some(until)) => Some(Schedule::new(&ip, *from, *until, days)),
+                _ => None,
+            };
+            set_schedule(&ctx, &ip, schedule)?;
+        }
+        Commands::Daemon { interval } => {
+            daemon::run(&settings, Duration::from_secs(*interval)).await?;
+        }
+        Commands::ScheduleTick => {
+            schedule::tick(&ctx)?;
+        }
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+        }
+        Commands::Status { format } => {
+            show_status(ctx.firewall, &sudo, *format)?;
+        }
+        Commands::Usage {
+            target,
+            json,
+            reset,
+        } => {
+            show_usage(ctx.firewall, &sudo, &target.resolve().await?, *json)?;
+            if *reset {
+                rust_wifi_kicker::reset_usage(&ctx)?;
+                if !*json {
+                    println!("Counters reset.");
+                }
+            }
+        }
+        Commands::Top => {
+            top::run(ctx.firewall, &sudo).await?;
+        }
+        Commands::List { json } => {
+            list_targets(*json)?;
+        }
+        Commands::Alias { action } => match action {
+            AliasAction::Add { name, target } => {
+                alias::add(name, *target)?;
+                println!("{} now refers to {}", name, target);
+            }
+            AliasAction::Set { name, target } => {
+                alias::set(name, *target)?;
+                println!("{} now refers to {}", name, target);
+            }
+            AliasAction::List { json } => alias::print_aliases(*json)?,
+            AliasAction::Rm { name } => {
+                if !alias::remove(name)? {
+                    return Err(anyhow!("No alias named {}", name));
+                }
+                println!("Removed alias {}", name);
+            }
+        },
+        Commands::Config {
+            action: ConfigAction::Path,
+        } => {
+            println!("{}", config::path()?.display());
+        }
+        Commands::Group { action } => match action {
+            GroupAction::Add { name, members } => {
+                alias::add_to_group(name, members)?;
+                let count = alias::group_members(name)?.len();
+                println!("{} now has {} member(s)", name, count);
+            }
+            GroupAction::List { json } => alias::print_groups(*json)?,
+            GroupAction::Rm { name } => {
+                if !alias::remove_group(name)? {
+                    return Err(anyhow!("No group named {}", name));
+                }
+                println!("Removed group {}", name);
+            }
+        },
+        Commands::Devices {
+            action: Some(DevicesAction::Link { old, new }),
+            ..
+        } => {
+            history::link(*old, *new)?;
+            println!(
+                "Linked {} to {}; they now share one history entry",
+                old, new
+            );
+        }
+        Commands::Devices {
+            action: None,
+            since,
+            new_since,
+            json,
         } => {
-            setup_bandwidth_limit(ip, *upload, *download, *persistent)?;
+            history::print_devices(*since, *new_since, *json)?;
+        }
+        Commands::UpdateOui { url } => {
+            println!("Downloading {}...", url);
+            let update = oui::update(url)?;
+            println!(
+                "Updated the vendor database: {} prefixes ({} added, {} changed, {} removed)",
+                update.entries, update.added, update.changed, update.removed
+            );
+        }
+        Commands::Clear { yes, keep_backups } => {
+            ctx.check_root()?;
+            let question = match keep_backups {
+                true => "This deletes all of wifi-kicker's rules, aliases, groups, schedules and history. Continue?",
+                false => "This deletes all of wifi-kicker's rules, aliases, groups, schedules, history and PF backups. Continue?",
+            };
+            if !yes && !ctx.dry_run && !confirm(question)? {
+                println!("Aborted; nothing was changed.");
+                return Ok(ExitCode::SUCCESS);
+            }
+            rust_wifi_kicker::clear(&ctx, *keep_backups)?;
+        }
+        Commands::Export => {
+            println!("{}", serde_json::to_string_pretty(&export::export()?)?);
         }
-        Commands::Remove { ip } => {
-            remove_rules(ip)?;
+        Commands::Import { path, force } => {
+            let (export, force) = (export::read(path)?, *force);
+            settings
+                .run_blocking(move |ctx| export::import(ctx, export, force))
+                .await?;
         }
-        Commands::Status => {
-            show_status()?;
+        Commands::Backup => {
+            ctx.check_root()?;
+            let path = backup::backup(&ctx)?;
+            println!("Saved PF configuration to {}", path.display());
+        }
+        Commands::Restore { path } => {
+            ctx.check_root()?;
+            backup::restore(&ctx, path)?;
+        }
+        Commands::Interfaces { json } => {
+            interface::print_interfaces(&interface::list()?, *json)?;
+        }
+        Commands::Probe {
+            target,
+            ports,
+            top,
+            timeout_ms,
+            interface,
+            allow_external,
+        } => {
+            probe_device(
+                probe_address(target).await?,
+                ports.as_deref(),
+                *top,
+                Duration::from_millis(*timeout_ms),
+                interface.as_deref(),
+                *allow_external,
+            )
+            .await?;
         }
     }
 
-    Ok(())
+    if cli.dry_run {
+        let skipped = settings.dry_runner.invocations().len();
+        println!("Dry run: {} command(s) not executed", skipped);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::Mutex;
+
+    /// HOME is process-wide, so tests that keep aliases take turns.
+    static HOME: Mutex<()> = Mutex::new(());
+
+    /// Runs `test` with HOME set to a fresh directory named after it.
+    fn with_home(name: &str, test: impl FnOnce()) {
+        let _home = HOME.lock().unwrap_or_else(|e| e.into_inner());
+        let dir =
+            std::env::temp_dir().join(format!("wifi-kicker-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("HOME", &dir);
+        test();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    /// Runs a future to completion, for tests of async helpers that need no
+    /// network.
+    fn block_on<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn target() -> Target {
+        Target {
+            ip: Vec::new(),
+            mac: None,
+            name: None,
+            host: None,
+            cidr: None,
+            group: None,
+            device: None,
+        }
+    }
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("rust-wifi-kicker").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn parse_ip_rejects_malformed_addresses() {
+        for ip in [
+            "999.1.1.1",
+            "192.168.1.256",
+            "192.168.1",
+            "blah",
+            "10.0.0.1/24",
+        ] {
+            assert_eq!(
+                parse_ip(ip),
+                Err(format!("{} is not a valid IPv4 or IPv6 address", ip))
+            );
+        }
+        assert_eq!(
+            parse_ip("192.168.1.10"),
+            Ok("192.168.1.10".parse().unwrap())
+        );
+        assert_eq!(parse_ip("fd00::10"), Ok("fd00::10".parse().unwrap()));
+    }
+
+    #[test]
+    fn parse_ipv4_rejects_ipv6_literals() {
+        assert_eq!(
+            parse_ipv4("fe80::1"),
+            Err("fe80::1 is an IPv6 address; only IPv4 is supported here".to_string())
+        );
+        assert_eq!(
+            parse_ipv4("256.0.0.1"),
+            Err("256.0.0.1 is not a valid IPv4 address".to_string())
+        );
+        assert_eq!(parse_ipv4("10.0.0.1"), Ok(Ipv4Addr::new(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn clap_rejects_bad_targets_before_running_anything() {
+        for command in ["monitor", "limit", "remove"] {
+            let error = parse(&[command, "--ip", "999.1.1.1"]).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+            assert!(error.to_string().contains("999.1.1.1 is not a valid"));
+
+            let error = parse(&[command, "--mac", "aa:bb:cc:dd:ee"]).err().unwrap();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        }
+        assert!(parse(&["monitor", "--ip", "10.0.0.1,10.0.0.2"]).is_ok());
+    }
+
+    #[test]
+    fn resolve_prefers_ip_then_mac_name_cidr_device() {
+        with_home("resolve-precedence", || {
+            alias::set(
+                "kids-ipad",
+                AliasTarget::Ip("192.168.1.50".parse().unwrap()),
+            )
+            .unwrap();
+            let cidr = Some("192.168.4.0/24".parse().unwrap());
+
+            let everything = Target {
+                ip: vec!["192.168.1.10".parse().unwrap()],
+                // Never looked up, since --ip wins
+                mac: Some("aa:bb:cc:dd:ee:ff".parse().unwrap()),
+                name: Some("kids-ipad".to_string()),
+                cidr,
+                device: Some(1),
+                ..target()
+            };
+            assert_eq!(block_on(everything.resolve()).unwrap(), "192.168.1.10");
+
+            let name = Target {
+                name: Some("kids-ipad".to_string()),
+                cidr,
+                device: Some(1),
+                ..target()
+            };
+            assert_eq!(block_on(name.resolve()).unwrap(), "192.168.1.50");
+
+            let network = Target {
+                cidr,
+                device: Some(1),
+                ..target()
+            };
+            assert_eq!(block_on(network.resolve()).unwrap(), "192.168.4.0/24");
+
+            // An undefined name is an error, not a fall through to --cidr
+            let unknown = Target {
+                name: Some("nobody".to_string()),
+                cidr,
+                ..target()
+            };
+            let error = block_on(unknown.resolve()).unwrap_err().to_string();
+            assert!(error.starts_with("No alias named nobody"), "{}", error);
+        });
+    }
+
+    #[test]
+    fn resolve_needs_a_single_device_selector() {
+        let error = block_on(target().resolve()).unwrap_err().to_string();
+        assert!(error.starts_with("One of --ip, --mac, --name"), "{}", error);
+
+        let group = Target {
+            group: Some("kids".to_string()),
+            ..target()
+        };
+        assert!(block_on(group.resolve())
+            .unwrap_err()
+            .to_string()
+            .contains("--group"));
+
+        let several = Target {
+            ip: vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+            ..target()
+        };
+        assert!(block_on(several.resolve()).is_err());
+    }
+
+    #[test]
+    fn probe_takes_an_alias() {
+        with_home("probe-alias", || {
+            alias::set("nas", AliasTarget::Ip("192.168.1.60".parse().unwrap())).unwrap();
+            alias::set("nas6", AliasTarget::Ip("fd00::60".parse().unwrap())).unwrap();
+            let probe_target = |args: &[&str]| match parse(&[&["probe"], args].concat()) {
+                Ok(Cli {
+                    command: Commands::Probe { target, .. },
+                    ..
+                }) => block_on(probe_address(&target)),
+                _ => panic!("probe {:?} didn't parse", args),
+            };
+
+            assert_eq!(
+                probe_target(&["--name", "nas"]).unwrap(),
+                Ipv4Addr::new(192, 168, 1, 60)
+            );
+            assert_eq!(
+                probe_target(&["--ip", "192.168.1.61"]).unwrap(),
+                Ipv4Addr::new(192, 168, 1, 61)
+            );
+            let error = probe_target(&["--name", "nas6"]).unwrap_err();
+            assert!(error.to_string().contains("only IPv4 is supported here"));
+            assert!(probe_target(&["--cidr", "192.168.1.0/24"]).is_err());
+            assert!(probe_target(&["--name", "nobody"]).is_err());
+        });
+    }
 }
@@ -0,0 +1,320 @@
+//! Minimal Bonjour browsing. Queries are sent from an ephemeral port, which
+//! per RFC 6762 §6.7 makes responders answer us directly by unicast, so the
+//! source address of each reply identifies the device.
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+
+const MDNS_ADDR: (Ipv4Addr, u16) = (Ipv4Addr::new(224, 0, 0, 251), 5353);
+const SERVICES_META: &str = "_services._dns-sd._udp.local";
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+/// Apple devices describe their hardware under this service, which isn't
+/// browsable: it has to be asked for by instance name.
+const DEVICE_INFO: &str = "_device-info._tcp.local";
+
+/// What a single device told us about itself.
+#[derive(Debug, Clone, Default)]
+pub struct MdnsInfo {
+    /// Friendly instance name, e.g. "Living Room TV"
+    pub name: Option<String>,
+    /// Service types the device advertises, e.g. "_airplay._tcp"
+    pub services: BTreeSet<String>,
+}
+
+#[derive(Debug)]
+enum RecordData {
+    A(Ipv4Addr),
+    Ptr(String),
+    /// The `key=value` strings of a TXT record
+    Txt(Vec<String>),
+    Other,
+}
+
+#[derive(Debug)]
+struct Record {
+    name: String,
+    data: RecordData,
+}
+
+fn build_query(names: &[&str], qtype: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(512);
+    packet.extend_from_slice(&[0, 0]); // id, unused by mDNS
+    packet.extend_from_slice(&[0, 0]); // flags: standard query
+    packet.extend_from_slice(&(names.len() as u16).to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0, 0, 0]); // an/ns/ar counts
+    for name in names {
+        for label in name.split('.').filter(|l| !l.is_empty()) {
+            packet.push(label.len().min(63) as u8);
+            packet.extend_from_slice(&label.as_bytes()[..label.len().min(63)]);
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes()); // class IN
+    }
+    packet
+}
+
+/// Reads a possibly-compressed domain name starting at `pos`, returning the
+/// dotted name and the offset just past it in the original position.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Bound pointer chasing so malformed packets can't loop forever
+    for _ in 0..128 {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xC0 == 0xC0 => {
+                let target = ((l & 0x3F) << 8) | *packet.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            l => {
+                let label = packet.get(pos + 1..pos + 1 + l)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+        }
+    }
+    None
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([
+        *packet.get(pos)?,
+        *packet.get(pos + 1)?,
+    ]))
+}
+
+/// Splits TXT record data into its length-prefixed strings.
+fn read_txt(mut data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    while let Some((&len, rest)) = data.split_first() {
+        let len = (len as usize).min(rest.len());
+        strings.push(String::from_utf8_lossy(&rest[..len]).into_owned());
+        data = &rest[len..];
+    }
+    strings
+}
+
+/// Parses all answer, authority and additional records in a response.
+fn parse_response(packet: &[u8]) -> Option<Vec<Record>> {
+    let questions = read_u16(packet, 4)?;
+    let records = read_u16(packet, 6)? as usize
+        + read_u16(packet, 8)? as usize
+        + read_u16(packet, 10)? as usize;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        let (_, next) = read_name(packet, pos)?;
+        pos = next + 4;
+    }
+
+    let mut parsed = Vec::with_capacity(records);
+    for _ in 0..records {
+        let (name, next) = read_name(packet, pos)?;
+        let rtype = read_u16(packet, next)?;
+        let rdlen = read_u16(packet, next + 8)? as usize;
+        let rdata = next + 10;
+        packet.get(rdata..rdata + rdlen)?;
+        let data = match rtype {
+            TYPE_A if rdlen == 4 => {
+                let [a, b, c, d] = packet[rdata..rdata + 4] else {
+                    return None;
+                };
+                RecordData::A(Ipv4Addr::new(a, b, c, d))
+            }
+            TYPE_PTR => RecordData::Ptr(read_name(packet, rdata)?.0),
+            TYPE_TXT => RecordData::Txt(read_txt(&packet[rdata..rdata + rdlen])),
+            _ => RecordData::Other,
+        };
+        parsed.push(Record { name, data });
+        pos = rdata + rdlen;
+    }
+    Some(parsed)
+}
+
+/// Sends one query and collects every reply until `deadline`, keyed by the
+/// address that answered.
+async fn query(
+    names: &[&str],
+    qtype: u16,
+    deadline: Instant,
+) -> std::io::Result<Vec<(Ipv4Addr, Vec<Record>)>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket
+        .send_to(&build_query(names, qtype), MDNS_ADDR)
+        .await?;
+
+    let mut replies = Vec::new();
+    let mut buf = vec![0u8; 9000];
+    while let Ok(Ok((len, from))) = timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        if let (SocketAddr::V4(from), Some(records)) = (from, parse_response(&buf[..len])) {
+            replies.push((*from.ip(), records));
+        }
+    }
+    Ok(replies)
+}
+
+/// The name an address is looked up under in reverse (PTR) queries.
+fn reverse_name(ip: Ipv4Addr) -> String {
+    let [a, b, c, d] = ip.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+}
+
+/// Asks `ips` for their `.local` host names with mDNS reverse queries,
+/// which works where the system resolver doesn't consult mDNS (most Linux
+/// setups). Queries go out in small batches from one socket and replies
+/// are collected for `budget`.
+pub async fn reverse_lookup(ips: &[Ipv4Addr], budget: Duration) -> HashMap<Ipv4Addr, String> {
+    let mut hosts = HashMap::new();
+    if ips.is_empty() {
+        return hosts;
+    }
+    let names: HashMap<String, Ipv4Addr> = ips.iter().map(|ip| (reverse_name(*ip), *ip)).collect();
+    let Ok(socket) = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await else {
+        return hosts;
+    };
+    let deadline = Instant::now() + budget;
+    let questions: Vec<&str> = names.keys().map(String::as_str).collect();
+    for batch in questions.chunks(16) {
+        let _ = socket
+            .send_to(&build_query(batch, TYPE_PTR), MDNS_ADDR)
+            .await;
+    }
+
+    let mut buf = vec![0u8; 9000];
+    while let Ok(Ok((len, _))) = timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        for record in parse_response(&buf[..len]).unwrap_or_default() {
+            if let (Some(ip), RecordData::Ptr(host)) =
+                (names.get(&record.name.to_lowercase()), record.data)
+            {
+                hosts
+                    .entry(*ip)
+                    .or_insert_with(|| host.trim_end_matches('.').to_string());
+            }
+        }
+    }
+    hosts
+}
+
+/// Asks for the addresses of the `.local` host `name`, collecting answers
+/// for `budget`.
+pub async fn lookup_host(name: &str, budget: Duration) -> Vec<Ipv4Addr> {
+    let name = name.trim_end_matches('.');
+    let Ok(replies) = query(&[name], TYPE_A, Instant::now() + budget).await else {
+        return Vec::new();
+    };
+    let mut addresses = Vec::new();
+    for record in replies.into_iter().flat_map(|(_, records)| records) {
+        if let RecordData::A(ip) = record.data {
+            if record.name.eq_ignore_ascii_case(name) && !addresses.contains(&ip) {
+                addresses.push(ip);
+            }
+        }
+    }
+    addresses
+}
+
+/// Asks each device for the `model=` key of its device-info TXT record.
+/// `instances` pairs an address with the instance name the device
+/// advertised its services under, which its device-info record shares.
+/// Returns the raw model identifiers, e.g. "MacBookPro18,3".
+pub async fn device_models(
+    instances: &[(Ipv4Addr, String)],
+    budget: Duration,
+) -> HashMap<Ipv4Addr, String> {
+    let mut models = HashMap::new();
+    // Dots in an instance name would be taken for label separators
+    let names: Vec<String> = instances
+        .iter()
+        .filter(|(_, instance)| !instance.contains('.'))
+        .map(|(_, instance)| format!("{}.{}", instance, DEVICE_INFO))
+        .collect();
+    if names.is_empty() {
+        return models;
+    }
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let replies = query(&names, TYPE_TXT, Instant::now() + budget)
+        .await
+        .unwrap_or_default();
+    for (ip, records) in replies {
+        for record in records {
+            let RecordData::Txt(strings) = record.data else {
+                continue;
+            };
+            if !record.name.to_lowercase().ends_with(DEVICE_INFO) {
+                continue;
+            }
+            if let Some(model) = strings.iter().find_map(|s| s.strip_prefix("model=")) {
+                models.entry(ip).or_insert_with(|| model.to_string());
+            }
+        }
+    }
+    models
+}
+
+/// Returns the first label of a service instance name, which is the
+/// human-readable part ("Living Room TV._airplay._tcp.local").
+fn instance_label(instance: &str, service: &str) -> Option<String> {
+    let label = instance.strip_suffix(service)?.strip_suffix('.')?;
+    (!label.is_empty()).then(|| label.to_string())
+}
+
+/// Enumerates advertised service types, then their instances, within
+/// `budget`. Devices that don't answer are simply absent from the result.
+pub async fn browse(budget: Duration) -> HashMap<Ipv4Addr, MdnsInfo> {
+    let start = Instant::now();
+    let mut found: HashMap<Ipv4Addr, MdnsInfo> = HashMap::new();
+
+    let mut service_types = BTreeSet::new();
+    let meta = query(&[SERVICES_META], TYPE_PTR, start + budget / 3)
+        .await
+        .unwrap_or_default();
+    for (ip, records) in meta {
+        for record in records {
+            if let (true, RecordData::Ptr(service)) =
+                (record.name.eq_ignore_ascii_case(SERVICES_META), record.data)
+            {
+                found
+                    .entry(ip)
+                    .or_default()
+                    .services
+                    .insert(service.trim_end_matches(".local").to_string());
+                service_types.insert(service);
+            }
+        }
+    }
+    if service_types.is_empty() {
+        return found;
+    }
+
+    let names: Vec<&str> = service_types.iter().map(String::as_str).collect();
+    let instances = query(&names, TYPE_PTR, start + budget)
+        .await
+        .unwrap_or_default();
+    for (ip, records) in instances {
+        for record in records {
+            match record.data {
+                RecordData::Ptr(instance) if service_types.contains(&record.name) => {
+                    let info = found.entry(ip).or_default();
+                    info.services
+                        .insert(record.name.trim_end_matches(".local").to_string());
+                    if info.name.is_none() {
+                        info.name = instance_label(&instance, &record.name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    found
+}
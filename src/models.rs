@@ -0,0 +1,28 @@
+//! Product names for Apple model identifiers, from an embedded table.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const EMBEDDED_MODELS: &str = include_str!("apple_models.txt");
+
+fn table() -> &'static HashMap<&'static str, &'static str> {
+    static TABLE: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        EMBEDDED_MODELS
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let (identifier, name) = line.split_once('\t')?;
+                Some((identifier, name.trim()))
+            })
+            .collect()
+    })
+}
+
+/// The product name for `identifier` (e.g. "MacBookPro18,3"), or the
+/// identifier itself when the table doesn't know it.
+pub fn product_name(identifier: &str) -> String {
+    table()
+        .get(identifier)
+        .map_or_else(|| identifier.to_string(), |name| name.to_string())
+}
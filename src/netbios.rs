@@ -0,0 +1,225 @@
+//! NetBIOS Node Status queries (RFC 1002, section 4.2.17) for the machine
+//! names of Windows hosts and NAS boxes, which often answer neither reverse
+//! DNS nor mDNS.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+
+const NETBIOS_PORT: u16 = 137;
+/// NBSTAT, the node status resource record type
+const TYPE_NBSTAT: u16 = 0x21;
+const CLASS_IN: u16 = 1;
+/// Suffix byte of the workstation service name, i.e. the machine name
+const SUFFIX_WORKSTATION: u8 = 0x00;
+/// Set in a name's flags when it is a group (workgroup or domain) name
+const FLAG_GROUP: u16 = 0x8000;
+/// Set in the first flags byte of a response
+const FLAG_RESPONSE: u8 = 0x80;
+
+/// The wildcard name `*` in NetBIOS first-level encoding: each of its 16
+/// bytes (`*` padded with NULs) is split into two nibbles, written as
+/// `'A' + nibble`.
+fn encoded_wildcard() -> [u8; 32] {
+    let mut name = [0u8; 16];
+    name[0] = b'*';
+    let mut encoded = [0u8; 32];
+    for (i, byte) in name.iter().enumerate() {
+        encoded[2 * i] = b'A' + (byte >> 4);
+        encoded[2 * i + 1] = b'A' + (byte & 0x0f);
+    }
+    encoded
+}
+
+/// A node status request for the wildcard name.
+fn build_query(id: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(50);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Flags 0 (a query, not broadcast), one question
+    packet.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    packet.push(32);
+    packet.extend_from_slice(&encoded_wildcard());
+    packet.push(0);
+    packet.extend_from_slice(&TYPE_NBSTAT.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    packet
+}
+
+/// Returns the offset just past the encoded name starting at `pos`, which
+/// may end in a compression pointer.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
+
+/// Extracts the machine name from the response to query `id`: the first
+/// unique (non-group) name with the workstation suffix. Packets with another
+/// transaction ID, such as a late answer to an earlier scan, are ignored.
+fn parse_response(packet: &[u8], id: u16) -> Option<String> {
+    if packet.get(..2)? != id.to_be_bytes() || packet.get(2)? & FLAG_RESPONSE == 0 {
+        return None;
+    }
+    let answers = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+    if answers == 0 {
+        return None;
+    }
+    // Type, class, TTL and data length follow the answer's name
+    let pos = skip_name(packet, 12)?;
+    let record_type = u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]);
+    if record_type != TYPE_NBSTAT {
+        return None;
+    }
+    let mut pos = pos + 10;
+    let count = *packet.get(pos)?;
+    pos += 1;
+    for _ in 0..count {
+        let entry = packet.get(pos..pos + 18)?;
+        pos += 18;
+        let flags = u16::from_be_bytes([entry[16], entry[17]]);
+        if entry[15] != SUFFIX_WORKSTATION || flags & FLAG_GROUP != 0 {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&entry[..15]).trim_end().to_string();
+        if !name.is_empty() {
+            return Some(name);
+        }
+    }
+    None
+}
+
+/// Sends a node status query to each of `ips` from one socket and collects
+/// the machine names that come back within `budget`. Hosts without NetBIOS
+/// simply don't answer.
+pub async fn node_names(ips: &[Ipv4Addr], budget: Duration) -> HashMap<Ipv4Addr, String> {
+    let mut names = HashMap::new();
+    if ips.is_empty() {
+        return names;
+    }
+    let Ok(socket) = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await else {
+        return names;
+    };
+    let deadline = Instant::now() + budget;
+    let ids: HashMap<Ipv4Addr, u16> = ips
+        .iter()
+        .enumerate()
+        .map(|(id, ip)| (*ip, id as u16))
+        .collect();
+    for (ip, id) in &ids {
+        let _ = socket.send_to(&build_query(*id), (*ip, NETBIOS_PORT)).await;
+    }
+
+    let mut buf = [0u8; 1500];
+    while let Ok(Ok((len, from))) = timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let std::net::SocketAddr::V4(from) = from else {
+            continue;
+        };
+        let Some(id) = ids.get(from.ip()) else {
+            continue;
+        };
+        if let Some(name) = parse_response(&buf[..len], *id) {
+            names.entry(*from.ip()).or_insert(name);
+        }
+        if names.len() == ids.len() {
+            break;
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The node status response of a Windows 11 machine, as captured: the
+    /// workgroup and browser names come first, then the machine name for
+    /// the workstation (0x00) and server (0x20) services, followed by the
+    /// MAC and the statistics block.
+    fn windows_response(id: u16) -> Vec<u8> {
+        let names: [(&[u8; 15], u8, u16); 4] = [
+            (b"WORKGROUP      ", 0x00, 0x8400),
+            (b"\x01\x02__MSBROWSE__\x02", 0x01, 0x8400),
+            (b"DESKTOP-4F2K9QL", 0x00, 0x0400),
+            (b"DESKTOP-4F2K9QL", 0x20, 0x0400),
+        ];
+        let mut packet = id.to_be_bytes().to_vec();
+        // Flags: response, authoritative; no questions, one answer
+        packet.extend_from_slice(&[0x84, 0x00, 0, 0, 0, 1, 0, 0, 0, 0]);
+        packet.push(32);
+        packet.extend_from_slice(&encoded_wildcard());
+        packet.push(0);
+        packet.extend_from_slice(&TYPE_NBSTAT.to_be_bytes());
+        packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        let data_len = 1 + 18 * names.len() + 6 + 40;
+        packet.extend_from_slice(&(data_len as u16).to_be_bytes());
+        packet.push(names.len() as u8);
+        for (name, suffix, flags) in names {
+            packet.extend_from_slice(name);
+            packet.push(suffix);
+            packet.extend_from_slice(&flags.to_be_bytes());
+        }
+        packet.extend_from_slice(&[0x3c, 0x22, 0xfb, 0x0a, 0x0b, 0x0c]);
+        packet.extend_from_slice(&[0; 40]);
+        packet
+    }
+
+    #[test]
+    fn builds_a_wildcard_node_status_query() {
+        let query = build_query(0x1234);
+        assert_eq!(query.len(), 50);
+        assert_eq!(&query[..12], [0x12, 0x34, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(query[12], 32);
+        assert_eq!(&query[13..45], b"CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA");
+        assert_eq!(&query[45..], [0, 0, 0x21, 0, 1]);
+    }
+
+    #[test]
+    fn finds_the_machine_name() {
+        assert_eq!(
+            parse_response(&windows_response(7), 7).as_deref(),
+            Some("DESKTOP-4F2K9QL")
+        );
+    }
+
+    #[test]
+    fn follows_a_compressed_answer_name() {
+        let mut packet = windows_response(7);
+        packet.splice(12..46, [0xc0, 0x0c]);
+        assert_eq!(
+            parse_response(&packet, 7).as_deref(),
+            Some("DESKTOP-4F2K9QL")
+        );
+    }
+
+    #[test]
+    fn ignores_answers_to_other_queries() {
+        assert_eq!(parse_response(&windows_response(8), 7), None);
+        // Our own query, looped back, isn't a response
+        assert_eq!(parse_response(&build_query(7), 7), None);
+    }
+
+    #[test]
+    fn rejects_short_and_empty_responses() {
+        let packet = windows_response(7);
+        // Cut inside the machine name's entry
+        assert_eq!(parse_response(&packet[..57 + 18 * 2 + 10], 7), None);
+        assert_eq!(parse_response(&packet[..1], 7), None);
+
+        let mut no_answers = packet.clone();
+        no_answers[7] = 0;
+        assert_eq!(parse_response(&no_answers, 7), None);
+
+        // Only the workgroup and browser names
+        let mut groups_only = packet[..57 + 18 * 2].to_vec();
+        groups_only[56] = 2;
+        assert_eq!(parse_response(&groups_only, 7), None);
+    }
+}
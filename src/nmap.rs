@@ -0,0 +1,195 @@
+//! nmap's XML output (`-oX -`), which carries each host's status, MAC,
+//! vendor and reverse DNS name as attributes rather than free text.
+//!
+//! ```xml
+//! <host><status state="up" reason="arp-response"/>
+//! <address addr="192.168.1.1" addrtype="ipv4"/>
+//! <address addr="00:11:22:33:44:55" addrtype="mac" vendor="Netgear"/>
+//! <hostnames><hostname name="router.lan" type="PTR"/></hostnames>
+//! </host>
+//! ```
+
+use crate::device::{unix_now, Device, DeviceSource, NameSource};
+use crate::mac::MacAddr;
+use crate::oui;
+use anyhow::{anyhow, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::net::Ipv4Addr;
+
+/// What one `<host>` element said about its host.
+#[derive(Default)]
+struct Host {
+    up: bool,
+    ip: Option<Ipv4Addr>,
+    mac: Option<MacAddr>,
+    vendor: Option<String>,
+    hostname: Option<String>,
+}
+
+impl Host {
+    fn into_device(self, seen: u64) -> Option<Device> {
+        if !self.up {
+            return None;
+        }
+        Some(Device {
+            // The bundled OUI list knows about randomized MACs; nmap's
+            // vendor is the fallback
+            vendor: self
+                .mac
+                .and_then(|mac| oui::lookup_vendor(&mac))
+                .or(self.vendor),
+            mac: self.mac,
+            hostname_source: self.hostname.is_some().then_some(NameSource::Dns),
+            hostname: self.hostname,
+            last_seen: Some(seen),
+            ..Device::new(self.ip?, DeviceSource::Nmap)
+        })
+    }
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    let value = element.try_get_attribute(name).ok()??;
+    Some(value.unescape_value().ok()?.into_owned())
+}
+
+/// Returns the hosts nmap reported up. Output cut short, as when nmap was
+/// stopped at its time limit, still yields the hosts before the cut; output
+/// that isn't nmap XML at all is an error.
+pub fn parse_hosts(xml: &str) -> Result<Vec<Device>> {
+    let seen = unix_now();
+    let mut reader = Reader::from_str(xml);
+    let mut nmaprun = false;
+    let mut host: Option<Host> = None;
+    let mut devices = Vec::new();
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(_) if nmaprun => break,
+            Err(e) => return Err(anyhow!("Unreadable nmap XML: {}", e)),
+        };
+        match event {
+            Event::Start(e) | Event::Empty(e) => match e.name().as_ref() {
+                b"nmaprun" => nmaprun = true,
+                b"host" => host = Some(Host::default()),
+                b"status" => {
+                    if let Some(host) = &mut host {
+                        host.up = attribute(&e, "state").as_deref() == Some("up");
+                    }
+                }
+                b"address" => {
+                    let Some(host) = &mut host else { continue };
+                    let addr = attribute(&e, "addr");
+                    match attribute(&e, "addrtype").as_deref() {
+                        Some("ipv4") => host.ip = addr.and_then(|a| a.parse().ok()),
+                        Some("mac") => {
+                            host.mac = addr.and_then(|a| a.parse().ok());
+                            host.vendor = attribute(&e, "vendor");
+                        }
+                        _ => {}
+                    }
+                }
+                b"hostname" => {
+                    // The PTR name, or the name the target was given as
+                    if let Some(host) = host.as_mut().filter(|h| h.hostname.is_none()) {
+                        host.hostname = attribute(&e, "name").filter(|name| !name.is_empty());
+                    }
+                }
+                _ => {}
+            },
+            Event::End(e) if e.name().as_ref() == b"host" => {
+                devices.extend(host.take().and_then(|host| host.into_device(seen)));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if !nmaprun {
+        return Err(anyhow!("No <nmaprun> element in nmap's output"));
+    }
+    // A host whose element was cut off
+    devices.extend(host.and_then(|host| host.into_device(seen)));
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `nmap -sn -oX - 192.168.1.0/24` from nmap 7.94, run as root so the
+    /// hosts carry their MACs; with `-v` down hosts are listed too.
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE nmaprun>
+<?xml-stylesheet href="file:///usr/share/nmap/nmap.xsl" type="text/xsl"?>
+<!-- Nmap 7.94 scan initiated Tue Oct 14 10:00:00 2026 as: nmap -sn -v -oX - 192.168.1.0/24 -->
+<nmaprun scanner="nmap" args="nmap -sn -v -oX - 192.168.1.0/24" start="1791972000" startstr="Tue Oct 14 10:00:00 2026" version="7.94" xmloutputversion="1.05">
+<verbose level="1"/>
+<debugging level="0"/>
+<host><status state="up" reason="arp-response" reason_ttl="0"/>
+<address addr="192.168.1.1" addrtype="ipv4"/>
+<address addr="A4:2B:B0:11:22:33" addrtype="mac" vendor="TP-Link Technologies"/>
+<hostnames>
+<hostname name="router.lan" type="PTR"/>
+</hostnames>
+<times srtt="2040" rttvar="5000" to="100000"/>
+</host>
+<host><status state="down" reason="no-response" reason_ttl="0"/>
+<address addr="192.168.1.2" addrtype="ipv4"/>
+</host>
+<host><status state="up" reason="arp-response" reason_ttl="0"/>
+<address addr="192.168.1.23" addrtype="ipv4"/>
+<address addr="7A:10:22:33:44:55" addrtype="mac"/>
+<hostnames>
+</hostnames>
+</host>
+<runstats><finished time="1791972003" timestr="Tue Oct 14 10:00:03 2026" summary="Nmap done at Tue Oct 14 10:00:03 2026; 256 IP addresses (2 hosts up) scanned in 3.05 seconds" elapsed="3.05" exit="success"/><hosts up="2" down="254" total="256"/>
+</runstats>
+</nmaprun>
+"#;
+
+    #[test]
+    fn keeps_the_hosts_that_are_up() {
+        let devices = parse_hosts(XML).unwrap();
+        let ips: Vec<String> = devices.iter().map(|d| d.ip.to_string()).collect();
+        assert_eq!(ips, ["192.168.1.1", "192.168.1.23"]);
+    }
+
+    #[test]
+    fn reads_mac_vendor_and_name() {
+        let devices = parse_hosts(XML).unwrap();
+        let router = &devices[0];
+        let mac: MacAddr = "a4:2b:b0:11:22:33".parse().unwrap();
+        assert_eq!(router.mac, Some(mac));
+        // nmap's vendor only fills in for prefixes the OUI table lacks
+        assert_eq!(
+            router.vendor,
+            oui::lookup_vendor(&mac).or(Some("TP-Link Technologies".to_string()))
+        );
+        assert_eq!(router.hostname.as_deref(), Some("router.lan"));
+        assert_eq!(router.hostname_source, Some(NameSource::Dns));
+
+        let phone = &devices[1];
+        assert_eq!(phone.vendor.as_deref(), Some(oui::PRIVATE_MAC_LABEL));
+        assert_eq!(phone.hostname, None);
+    }
+
+    #[test]
+    fn truncated_output_keeps_the_hosts_before_the_cut() {
+        // Stopped in the middle of the third host
+        let cut = XML.find("<address addr=\"192.168.1.23\"").unwrap() + 20;
+        let devices = parse_hosts(&XML[..cut]).unwrap();
+        let ips: Vec<String> = devices.iter().map(|d| d.ip.to_string()).collect();
+        assert_eq!(ips, ["192.168.1.1"]);
+
+        // Stopped right after the third host's address
+        let cut = XML.find("<hostnames>\n</hostnames>").unwrap();
+        assert_eq!(parse_hosts(&XML[..cut]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn output_that_isnt_nmap_xml_is_an_error() {
+        assert!(parse_hosts("").is_err());
+        assert!(parse_hosts("Starting Nmap 7.94 ( https://nmap.org )\n").is_err());
+        assert!(parse_hosts("<html><body>nope</body></html>").is_err());
+    }
+}
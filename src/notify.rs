@@ -0,0 +1,37 @@
+//! Desktop notifications: `osascript` on macOS, `notify-send` elsewhere.
+
+use anyhow::{anyhow, Context, Result};
+use std::process::Command;
+
+/// Quotes `s` as an AppleScript string literal.
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn notify(title: &str, message: &str) -> Result<()> {
+    let (program, output) = if cfg!(target_os = "macos") {
+        let script = format!(
+            "display notification {} with title {}",
+            applescript_string(message),
+            applescript_string(title)
+        );
+        (
+            "osascript",
+            Command::new("osascript").args(["-e", &script]).output(),
+        )
+    } else {
+        (
+            "notify-send",
+            Command::new("notify-send").args([title, message]).output(),
+        )
+    };
+    let output = output.with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
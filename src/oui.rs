@@ -0,0 +1,181 @@
+//! MAC vendor lookup against the IEEE OUI registry: the copy `update-oui`
+//! downloaded to `~/.wifi-kicker/oui.txt` if there is one, else a trimmed,
+//! embedded copy.
+
+use crate::mac::MacAddr;
+use crate::store;
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::process::Command;
+use std::sync::OnceLock;
+
+const EMBEDDED_OUI: &str = include_str!("oui.txt");
+
+/// Where the IEEE publishes the MA-L (24-bit prefix) assignments as CSV.
+pub const IEEE_OUI_URL: &str = "https://standards-oui.ieee.org/oui/oui.csv";
+/// The downloaded table, in the embedded table's format.
+const OUI_FILE: &str = "oui.txt";
+/// The registry lists over 30,000 prefixes; a download with far fewer is
+/// truncated or not the registry at all.
+const MIN_ENTRIES: usize = 10_000;
+/// Seconds curl may take for the download (a few MB).
+const DOWNLOAD_TIMEOUT: &str = "120";
+
+/// Label shown instead of a vendor for locally-administered (usually
+/// private, randomized) MAC addresses, whose prefix doesn't identify a
+/// manufacturer.
+pub const PRIVATE_MAC_LABEL: &str = "Private MAC";
+
+fn table() -> &'static HashMap<u32, &'static str> {
+    static TABLE: OnceLock<HashMap<u32, &'static str>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        // Read once per process, so leaking it costs nothing
+        let downloaded = read_downloaded().map(|contents| &*contents.leak());
+        match downloaded.map(parse_table) {
+            Some(table) if !table.is_empty() => table,
+            _ => parse_table(EMBEDDED_OUI),
+        }
+    })
+}
+
+fn read_downloaded() -> Option<String> {
+    fs::read_to_string(store::data_dir().ok()?.join(OUI_FILE)).ok()
+}
+
+fn parse_table(contents: &str) -> HashMap<u32, &str> {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (prefix, vendor) = line.split_once('\t')?;
+            Some((u32::from_str_radix(prefix, 16).ok()?, vendor.trim()))
+        })
+        .collect()
+}
+
+/// Returns the vendor for `mac`, or [`PRIVATE_MAC_LABEL`] when the address is
+/// locally administered. `None` means the prefix isn't in the table.
+pub fn lookup_vendor(mac: &MacAddr) -> Option<String> {
+    if mac.is_locally_administered() {
+        return Some(PRIVATE_MAC_LABEL.to_string());
+    }
+    table().get(&mac.oui()).map(|vendor| vendor.to_string())
+}
+
+/// Splits a CSV line into its fields, unquoting quoted ones.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Reads the registry's `Registry,Assignment,Organization Name,...` rows,
+/// keeping the MA-L assignments.
+fn parse_ieee_csv(csv: &str) -> HashMap<u32, String> {
+    csv.lines()
+        .filter_map(|line| {
+            let fields = csv_fields(line);
+            let [registry, assignment, name, ..] = fields.as_slice() else {
+                return None;
+            };
+            if registry != "MA-L" || assignment.len() != 6 {
+                return None;
+            }
+            let prefix = u32::from_str_radix(assignment, 16).ok()?;
+            let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+            (!name.is_empty()).then_some((prefix, name))
+        })
+        .collect()
+}
+
+/// What [`update`] changed, compared with the table in use before.
+pub struct OuiUpdate {
+    pub entries: usize,
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+}
+
+/// Downloads the registry from `url` with curl and replaces the downloaded
+/// table with it. A failed or implausibly small download leaves the old
+/// table in place.
+pub fn update(url: &str) -> Result<OuiUpdate> {
+    let download = store::work_file("oui.csv.download")?;
+    let output = Command::new("curl")
+        .args([
+            "-fsSL",
+            "--max-time",
+            DOWNLOAD_TIMEOUT,
+            "-o",
+            &download,
+            url,
+        ])
+        .output()
+        .context("Failed to run curl")?;
+    let csv = if output.status.success() {
+        fs::read_to_string(&download).with_context(|| format!("Failed to read {}", download))
+    } else {
+        Err(anyhow!(
+            "Downloading {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    };
+    let _ = fs::remove_file(&download);
+    let new = parse_ieee_csv(&csv?);
+    if new.len() < MIN_ENTRIES {
+        return Err(anyhow!(
+            "{} has only {} MA-L prefixes (expected over {}); keeping the current table",
+            url,
+            new.len(),
+            MIN_ENTRIES
+        ));
+    }
+
+    let current = read_downloaded();
+    let old = parse_table(current.as_deref().unwrap_or(EMBEDDED_OUI));
+    let update = OuiUpdate {
+        entries: new.len(),
+        added: new
+            .keys()
+            .filter(|prefix| !old.contains_key(prefix))
+            .count(),
+        changed: new
+            .iter()
+            .filter(|(prefix, name)| old.get(prefix).is_some_and(|old| old != name))
+            .count(),
+        removed: old
+            .keys()
+            .filter(|prefix| !new.contains_key(prefix))
+            .count(),
+    };
+
+    let mut prefixes: Vec<(&u32, &String)> = new.iter().collect();
+    prefixes.sort();
+    let mut contents = format!("# IEEE OUI table, downloaded from {}\n", url);
+    for (prefix, name) in prefixes {
+        contents.push_str(&format!("{:06X}\t{}\n", prefix, name));
+    }
+    // Written aside and renamed, so an interrupted write can't leave half
+    // a table behind
+    let path = store::data_dir()?.join(OUI_FILE);
+    let staged = path.with_extension("txt.new");
+    fs::write(&staged, contents)
+        .with_context(|| format!("Failed to write {}", staged.display()))?;
+    fs::rename(&staged, &path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    store::hand_back(&path);
+    Ok(update)
+}
@@ -0,0 +1,138 @@
+//! ICMP echo round trips to discovered devices, through the system `ping`
+//! since raw sockets would need more than the root we already require.
+
+use crate::device::Device;
+use nix::unistd::geteuid;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Echo requests per device.
+const PING_COUNT: u8 = 3;
+const MAX_IN_FLIGHT: usize = 32;
+
+/// Round-trip times of the echo replies, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    pub min_ms: f64,
+    pub avg_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingReply {
+    pub latency: Latency,
+    /// TTL of the first reply
+    pub ttl: Option<u8>,
+}
+
+/// Extracts the TTL from a reply line of `ping` output, e.g.
+/// `64 bytes from 192.168.1.1: icmp_seq=0 ttl=64 time=2.1 ms`.
+fn parse_ttl(output: &str) -> Option<u8> {
+    output.split_whitespace().find_map(|field| {
+        field
+            .strip_prefix("ttl=")
+            .or_else(|| field.strip_prefix("TTL="))?
+            .parse()
+            .ok()
+    })
+}
+
+/// Extracts min/avg from the summary line: macOS prints
+/// `round-trip min/avg/max/stddev = 1.2/2.3/3.4/0.9 ms`, Linux
+/// `rtt min/avg/max/mdev = 1.2/2.3/3.4/0.9 ms`.
+fn parse_latency(output: &str) -> Option<Latency> {
+    let line = output.lines().find(|l| l.contains("min/avg/max"))?;
+    let (_, values) = line.split_once('=')?;
+    let mut values = values.trim().split('/');
+    Some(Latency {
+        min_ms: values.next()?.parse().ok()?,
+        avg_ms: values.next()?.parse().ok()?,
+    })
+}
+
+/// Pings `ip` a few times, waiting at most a second per reply.
+pub async fn ping(ip: Ipv4Addr) -> Option<PingReply> {
+    // The per-reply timeout flag differs: -W (seconds) on Linux, -t on macOS
+    let timeout_flag = if cfg!(target_os = "linux") {
+        "-W"
+    } else {
+        "-t"
+    };
+    // Only root may send faster than one echo per second
+    let interval = if geteuid().is_root() { "0.2" } else { "1" };
+    let output = Command::new("ping")
+        .args(["-c", &PING_COUNT.to_string(), "-i", interval])
+        .args([timeout_flag, "1", &ip.to_string()])
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    Some(PingReply {
+        latency: parse_latency(&output)?,
+        ttl: parse_ttl(&output),
+    })
+}
+
+/// Sends one echo request to each of `unicast` and one broadcast ping to
+/// each of `broadcast`, all at once, and waits for them to end (a second
+/// at most). Hosts that answer land in the ARP cache; the replies
+/// themselves don't matter.
+pub fn prod(unicast: &[Ipv4Addr], broadcast: &[Ipv4Addr]) {
+    let timeout_flag = if cfg!(target_os = "linux") {
+        "-W"
+    } else {
+        "-t"
+    };
+    // Linux ping refuses broadcast addresses without -b
+    let broadcast_flag: &[&str] = if cfg!(target_os = "linux") {
+        &["-b"]
+    } else {
+        &[]
+    };
+    let targets = unicast
+        .iter()
+        .map(|ip| (ip, &[][..]))
+        .chain(broadcast.iter().map(|ip| (ip, broadcast_flag)));
+    let children: Vec<std::process::Child> = targets
+        .filter_map(|(ip, extra)| {
+            std::process::Command::new("ping")
+                .args(["-c", "1", timeout_flag, "1"])
+                .args(extra)
+                .arg(ip.to_string())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()
+        })
+        .collect();
+    for mut child in children {
+        let _ = child.wait();
+    }
+}
+
+/// Pings every device that answered the scan concurrently, filling in
+/// `latency` and `ttl`.
+pub async fn ping_devices(devices: &mut [Device]) {
+    let semaphore = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let mut tasks = JoinSet::new();
+    for (index, device) in devices.iter().enumerate().filter(|(_, d)| d.up) {
+        let semaphore = Arc::clone(&semaphore);
+        let ip = device.ip;
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (index, ping(ip).await)
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Ok((index, Some(reply))) = result {
+            devices[index].latency = Some(reply.latency);
+            devices[index].ttl = reply.ttl;
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! TCP connect port scan of a single device.
+
+use anyhow::{anyhow, bail, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Commonly open ports on home and office networks, most likely first, with
+/// the service usually behind them.
+const WELL_KNOWN_PORTS: &[(u16, &str)] = &[
+    (80, "http"),
+    (443, "https"),
+    (22, "ssh"),
+    (53, "dns"),
+    (445, "smb"),
+    (139, "netbios-ssn"),
+    (8080, "http-alt"),
+    (21, "ftp"),
+    (23, "telnet"),
+    (3389, "rdp"),
+    (5900, "vnc"),
+    (548, "afp"),
+    (631, "ipp"),
+    (9100, "jetdirect"),
+    (515, "lpd"),
+    (62078, "iphone-sync"),
+    (7000, "airplay"),
+    (5000, "upnp"),
+    (1900, "ssdp"),
+    (8008, "chromecast"),
+    (8009, "chromecast"),
+    (8443, "https-alt"),
+    (554, "rtsp"),
+    (1883, "mqtt"),
+    (8883, "mqtt-tls"),
+    (3306, "mysql"),
+    (5432, "postgresql"),
+    (6379, "redis"),
+    (27017, "mongodb"),
+    (25, "smtp"),
+    (110, "pop3"),
+    (143, "imap"),
+    (993, "imaps"),
+    (995, "pop3s"),
+    (587, "submission"),
+    (111, "rpcbind"),
+    (2049, "nfs"),
+    (135, "msrpc"),
+    (5353, "mdns"),
+    (8000, "http-alt"),
+    (8888, "http-alt"),
+    (9000, "http-alt"),
+    (32400, "plex"),
+    (8123, "home-assistant"),
+    (49152, "upnp"),
+    (10000, "webmin"),
+    (5060, "sip"),
+    (1723, "pptp"),
+    (3000, "http-dev"),
+    (6881, "bittorrent"),
+];
+
+pub fn service_name(port: u16) -> Option<&'static str> {
+    WELL_KNOWN_PORTS
+        .iter()
+        .find(|(p, _)| *p == port)
+        .map(|(_, name)| *name)
+}
+
+/// The `n` most common ports from the built-in list.
+pub fn top_ports(n: usize) -> Vec<u16> {
+    WELL_KNOWN_PORTS.iter().take(n).map(|(p, _)| *p).collect()
+}
+
+/// Parses a port list such as `22,80,8000-8100`.
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>> {
+    let mut ports = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let parse = |p: &str| {
+            p.parse::<u16>()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(|| anyhow!("Invalid port: {}", p))
+        };
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    bail!("Invalid port range: {}", part);
+                }
+                ports.extend(start..=end);
+            }
+            None => ports.push(parse(part)?),
+        }
+    }
+    if ports.is_empty() {
+        bail!("No ports given");
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// Returns the ports on `ip` that accepted a TCP connection within
+/// `per_port` timeout, in ascending order.
+pub async fn scan_ports(ip: Ipv4Addr, ports: &[u16], per_port: Duration) -> Vec<u16> {
+    let semaphore = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let mut tasks = JoinSet::new();
+    for &port in ports {
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let connect = TcpStream::connect(SocketAddr::from((ip, port)));
+            matches!(timeout(per_port, connect).await, Ok(Ok(_))).then_some(port)
+        });
+    }
+
+    let mut open = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Some(port)) = result {
+            open.push(port);
+        }
+    }
+    open.sort_unstable();
+    open
+}
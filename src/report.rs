@@ -0,0 +1,108 @@
+//! `scan --html`: a single self-contained page listing the devices, for
+//! people who'd rather not read a terminal. Styles and the column sorting
+//! script are inline, so the file works offline.
+
+use crate::device::{format_age, Device};
+use crate::history::History;
+
+const STYLE: &str = "
+body { font: 14px -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.4em; margin-bottom: 0.2em; }
+p.summary { color: #666; margin-top: 0; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 6px 10px; border-bottom: 1px solid #e4e4e4; }
+th { background: #f6f6f6; cursor: pointer; user-select: none; white-space: nowrap; }
+th::after { content: ' \\2195'; color: #bbb; }
+tr.stale td { color: #999; }
+td.rules { font-weight: 600; color: #b3261e; }
+code { font-size: 0.95em; }
+";
+
+/// Sorts by a cell's `data-sort` value when it has one (numbers for IPs and
+/// timestamps), otherwise by its text; clicking again reverses.
+const SCRIPT: &str = "
+document.querySelectorAll('th').forEach(function (th, column) {
+  th.addEventListener('click', function () {
+    var body = th.closest('table').tBodies[0];
+    var rows = Array.prototype.slice.call(body.rows);
+    var ascending = th.dataset.order !== 'asc';
+    th.dataset.order = ascending ? 'asc' : 'desc';
+    var key = function (row) {
+      var cell = row.cells[column];
+      return cell.dataset.sort !== undefined ? Number(cell.dataset.sort) : cell.textContent.toLowerCase();
+    };
+    rows.sort(function (a, b) {
+      var x = key(a), y = key(b);
+      return (x < y ? -1 : x > y ? 1 : 0) * (ascending ? 1 : -1);
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+";
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Renders `devices` as an HTML document. `history` supplies when each MAC
+/// was first seen.
+pub fn render_html(interface: &str, devices: &[Device], history: &History) -> String {
+    let text = |value: Option<&str>| escape(value.unwrap_or("-"));
+    let time = |timestamp: Option<u64>| match timestamp {
+        Some(t) => format!("<td data-sort=\"{}\">{}</td>", t, format_age(t)),
+        None => "<td data-sort=\"0\">-</td>".to_string(),
+    };
+
+    let mut rows = String::new();
+    for d in devices {
+        let first_seen = d
+            .mac
+            .and_then(|mac| history.get(&mac))
+            .map(|k| k.first_seen);
+        rows.push_str(&format!(
+            "<tr{}><td>{}</td><td data-sort=\"{}\"><code>{}</code></td><td><code>{}</code></td><td>{}</td><td>{}</td>{}{}<td>{}</td><td class=\"rules\">{}</td></tr>\n",
+            if d.is_stale() { " class=\"stale\"" } else { "" },
+            text(d.mdns_name.as_deref().or(d.hostname.as_deref())),
+            u32::from(d.ip),
+            d.ip,
+            d.mac.map_or("-".to_string(), |mac| mac.to_string()),
+            text(d.vendor.as_deref()),
+            escape(
+                d.model
+                    .as_deref()
+                    .unwrap_or(d.class.map_or("-", |class| class.label()))
+            ),
+            time(first_seen),
+            time(d.last_seen),
+            if d.is_stale() { "stale" } else { "up" },
+            escape(d.rules.as_deref().unwrap_or_default()),
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>Devices on {interface}</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<h1>Devices on {interface}</h1>
+<p class=\"summary\">{count} device(s). Click a column heading to sort.</p>
+<table>
+<thead><tr><th>Name</th><th>IP</th><th>MAC</th><th>Vendor</th><th>Type</th><th>First seen</th><th>Last seen</th><th>Status</th><th>Rules</th></tr></thead>
+<tbody>
+{rows}</tbody>
+</table>
+<script>{SCRIPT}</script>
+</body>
+</html>
+",
+        interface = escape(interface),
+        count = devices.len(),
+    )
+}
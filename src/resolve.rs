@@ -0,0 +1,126 @@
+//! Reverse DNS for scan results. Lookups go through the system resolver, so
+//! on macOS `.local` names from mDNSResponder are returned as well; hosts it
+//! has no name for are then asked over mDNS directly, and those still
+//! unnamed for their NetBIOS name. Host names given as targets are looked
+//! up forward the same way.
+
+use crate::device::{Device, NameSource};
+use crate::{mdns, netbios};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_IN_FLIGHT: usize = 32;
+/// How long mDNS reverse queries wait for replies.
+const MDNS_BUDGET: Duration = Duration::from_secs(2);
+/// How long NetBIOS node status queries wait for replies.
+const NETBIOS_BUDGET: Duration = Duration::from_secs(1);
+
+async fn reverse_lookup(ip: IpAddr) -> Option<String> {
+    let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip));
+    let name = timeout(LOOKUP_TIMEOUT, lookup).await.ok()?.ok()?.ok()?;
+    let name = name.trim_end_matches('.');
+    (!name.is_empty() && name != ip.to_string()).then(|| name.to_string())
+}
+
+/// The IPv4 addresses `host` resolves to, from the system resolver or, for
+/// `.local` names it doesn't know, mDNS. Empty when neither answers.
+pub async fn lookup_host(host: &str) -> Vec<Ipv4Addr> {
+    let name = host.to_string();
+    let lookup = tokio::task::spawn_blocking(move || dns_lookup::lookup_host(&name));
+    let mut addresses = Vec::new();
+    if let Ok(Ok(Ok(found))) = timeout(LOOKUP_TIMEOUT, lookup).await {
+        for ip in found {
+            if let IpAddr::V4(v4) = ip {
+                if !addresses.contains(&v4) {
+                    addresses.push(v4);
+                }
+            }
+        }
+    }
+    if addresses.is_empty() && host.trim_end_matches('.').ends_with(".local") {
+        addresses = mdns::lookup_host(host, MDNS_BUDGET).await;
+    }
+    addresses
+}
+
+/// Fills in `hostname` for devices that don't have one yet, from reverse
+/// DNS, or failing that mDNS, or with `netbios` a NetBIOS query to the
+/// hosts that are up. Lookups run concurrently, each bounded by a timeout;
+/// hosts that none answers for are left blank. Results, including
+/// failures, are recorded in `cache` and not looked up again.
+pub async fn resolve_hostnames(
+    devices: &mut [Device],
+    cache: &mut HashMap<Ipv4Addr, Option<(String, NameSource)>>,
+    netbios: bool,
+) {
+    let semaphore = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let mut tasks = JoinSet::new();
+    for (index, device) in devices.iter_mut().enumerate() {
+        if device.hostname.is_some() {
+            continue;
+        }
+        if let Some(cached) = cache.get(&device.ip) {
+            device.hostname = cached.as_ref().map(|(name, _)| name.clone());
+            device.hostname_source = cached.as_ref().map(|(_, source)| *source);
+            continue;
+        }
+        let ip = IpAddr::V4(device.ip);
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            (index, reverse_lookup(ip).await)
+        });
+    }
+
+    let mut unnamed = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok((index, Some(name))) => {
+                cache.insert(devices[index].ip, Some((name.clone(), NameSource::Dns)));
+                devices[index].hostname_source = Some(NameSource::Dns);
+                devices[index].hostname = Some(name);
+            }
+            Ok((index, None)) => unnamed.push(index),
+            Err(_) => {}
+        }
+    }
+
+    let ips: Vec<Ipv4Addr> = unnamed.iter().map(|&index| devices[index].ip).collect();
+    let mut found = mdns::reverse_lookup(&ips, MDNS_BUDGET).await;
+    let mut still_unnamed = Vec::new();
+    for index in unnamed {
+        let device = &mut devices[index];
+        match found.remove(&device.ip) {
+            Some(name) => {
+                cache.insert(device.ip, Some((name.clone(), NameSource::Mdns)));
+                device.hostname_source = Some(NameSource::Mdns);
+                device.hostname = Some(name);
+            }
+            None => still_unnamed.push(index),
+        }
+    }
+
+    let ips: Vec<Ipv4Addr> = still_unnamed
+        .iter()
+        .map(|&index| &devices[index])
+        .filter(|d| netbios && d.up)
+        .map(|d| d.ip)
+        .collect();
+    let mut found = netbios::node_names(&ips, NETBIOS_BUDGET).await;
+    for index in still_unnamed {
+        let device = &mut devices[index];
+        let name = found.remove(&device.ip);
+        cache.insert(
+            device.ip,
+            name.clone().map(|name| (name, NameSource::Netbios)),
+        );
+        device.hostname_source = name.is_some().then_some(NameSource::Netbios);
+        device.hostname = name;
+    }
+}
@@ -0,0 +1,129 @@
+//! Rule generation for managed targets: PF rules on macOS, an nftables
+//! script on Linux.
+
+use crate::state::{Family, ManagedTarget, RuleKind};
+
+/// Table holding every nftables rule wifi-kicker installs.
+pub const NFT_TABLE: &str = "wifi_kicker";
+
+/// Tags every rule of a target, so `usage` can find its counters: a PF
+/// label or an nft comment.
+pub fn label(ip: &str) -> String {
+    format!("wifi-kicker:{}", ip)
+}
+
+/// PF rules for one target.
+pub fn target_rules(target: &ManagedTarget) -> String {
+    let ip = &target.ip;
+    let label = label(ip);
+    let (family, icmp) = match target.family {
+        Family::Inet => ("inet", "icmp"),
+        Family::Inet6 => ("inet6", "ipv6-icmp"),
+    };
+    match target.kind {
+        RuleKind::Monitor => format!(
+            "# Monitoring rules for {ip}\n\
+             block drop in {family} proto {{tcp udp {icmp}}} from {ip} to any label \"{label}\"\n\
+             block drop out {family} proto {{tcp udp {icmp}}} from any to {ip} label \"{label}\"\n",
+        ),
+        RuleKind::Limit => {
+            let mut rules = format!("# Bandwidth limiting rules for {}\n", ip);
+            // Traffic is shaped by dummynet pipes; PF only classifies it into them
+            if let Some(pipe) = target.upload_pipe {
+                rules.push_str(&format!(
+                    "dummynet out quick {} proto {{tcp udp}} from {} to any pipe {} label \"{}\"\n",
+                    family, ip, pipe, label
+                ));
+            }
+            if let Some(pipe) = target.download_pipe {
+                rules.push_str(&format!(
+                    "dummynet in quick {} proto {{tcp udp}} from any to {} pipe {} label \"{}\"\n",
+                    family, ip, pipe, label
+                ));
+            }
+            rules
+        }
+    }
+}
+
+/// Renders the complete ruleset for `targets`, with the dummynet rules of
+/// limited devices grouped ahead of the block rules.
+pub fn render<'a>(targets: impl IntoIterator<Item = &'a ManagedTarget>) -> String {
+    let (limits, blocks): (Vec<_>, Vec<_>) =
+        targets.into_iter().partition(|t| t.kind == RuleKind::Limit);
+    limits
+        .into_iter()
+        .chain(blocks)
+        .map(target_rules)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// nft statements for one target. Limits are policers: packets above the
+/// rate are dropped rather than queued. The counters come before the
+/// policer, so they see all of the device's traffic.
+pub fn nft_target_rules(target: &ManagedTarget) -> Vec<String> {
+    let ip = &target.ip;
+    let label = label(ip);
+    let proto = match target.family {
+        Family::Inet => "ip",
+        Family::Inet6 => "ip6",
+    };
+    match target.kind {
+        RuleKind::Monitor => vec![
+            format!("{} saddr {} counter drop comment \"{}\"", proto, ip, label),
+            format!("{} daddr {} counter drop comment \"{}\"", proto, ip, label),
+        ],
+        RuleKind::Limit => target
+            .upload
+            .map(|up| {
+                format!(
+                    "{} saddr {} counter limit rate over {} kbytes/second drop comment \"{}\"",
+                    proto, ip, up, label
+                )
+            })
+            .into_iter()
+            .chain(target.download.map(|down| {
+                format!(
+                    "{} daddr {} counter limit rate over {} kbytes/second drop comment \"{}\"",
+                    proto, ip, down, label
+                )
+            }))
+            .collect(),
+    }
+}
+
+/// Renders an `nft -f` script that atomically replaces the wifi-kicker table
+/// with rules for `targets`. Traffic is filtered both when routed through
+/// this host and when addressed to it. With no targets the table is simply
+/// deleted.
+pub fn render_nft<'a>(targets: impl IntoIterator<Item = &'a ManagedTarget>) -> String {
+    // Creating the table first makes the delete succeed even when it
+    // doesn't exist yet
+    let mut script = format!(
+        "table inet {table}\ndelete table inet {table}\n",
+        table = NFT_TABLE
+    );
+    let statements: Vec<String> = targets.into_iter().flat_map(nft_target_rules).collect();
+    if statements.is_empty() {
+        return script;
+    }
+
+    script.push_str(&format!("table inet {} {{\n", NFT_TABLE));
+    for (chain, hook) in [
+        ("forward", "forward"),
+        ("input", "input"),
+        ("output", "output"),
+    ] {
+        script.push_str(&format!(
+            "    chain {} {{\n        type filter hook {} priority 0; policy accept;\n",
+            chain, hook
+        ));
+        for statement in &statements {
+            script.push_str(&format!("        {}\n", statement));
+        }
+        script.push_str("    }\n");
+    }
+    script.push_str("}\n");
+    script
+}
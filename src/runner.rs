@@ -0,0 +1,231 @@
+//! Execution of privileged commands. Firewall code goes through
+//! [`CommandRunner`] so the same paths can run for real or be recorded.
+
+use crate::error::KickerError;
+use anyhow::{Context, Result};
+use log::{error, warn};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::io::Read;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub trait CommandRunner {
+    /// Runs `cmd` with `args` as root, failing if it exits unsuccessfully.
+    fn run(&self, cmd: &str, args: &[&str]) -> Result<Output>;
+
+    /// Whether commands really run, and so need root. Runners that only
+    /// record them don't.
+    fn runs_commands(&self) -> bool {
+        true
+    }
+}
+
+/// Runs commands through `sudo`, stopping any that take longer than
+/// `timeout`.
+pub struct SudoRunner {
+    pub timeout: Duration,
+}
+
+impl SudoRunner {
+    /// Long enough for pfctl and friends, which normally finish at once.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(timeout: Duration) -> Self {
+        SudoRunner { timeout }
+    }
+}
+
+impl Default for SudoRunner {
+    fn default() -> Self {
+        SudoRunner::new(Self::DEFAULT_TIMEOUT)
+    }
+}
+
+/// Reads a child's pipe to the end on its own thread, so a command that
+/// writes a lot can't stall on a full pipe while we wait for it.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Stops `child`, a `sudo` process. SIGTERM comes first since sudo passes
+/// it on to the command, which SIGKILL would leave running.
+fn stop(child: &mut Child) {
+    let _ = kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM);
+    let grace = Instant::now() + Duration::from_secs(1);
+    while Instant::now() < grace {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+impl CommandRunner for SudoRunner {
+    fn run(&self, cmd: &str, args: &[&str]) -> Result<Output> {
+        let mut child = Command::new("sudo")
+            .arg(cmd)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run sudo command: {} {:?}", cmd, args))?;
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                error!("Command timed out: {} {:?}", cmd, args);
+                stop(&mut child);
+                return Err(KickerError::TimedOut {
+                    cmd: cmd.to_string(),
+                    secs: self.timeout.as_secs(),
+                }
+                .into());
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let output = Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        };
+
+        if !output.status.success() {
+            error!("Command failed: {} {:?}", cmd, args);
+            error!("Error: {}", String::from_utf8_lossy(&output.stderr));
+            return Err(KickerError::CommandFailed {
+                cmd: cmd.to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }
+            .into());
+        }
+
+        Ok(output)
+    }
+}
+
+/// How often [`run_retry`] tries a command by default.
+pub const DEFAULT_ATTEMPTS: u32 = 3;
+/// The wait after the first failed attempt; it doubles after each one.
+const FIRST_BACKOFF: Duration = Duration::from_millis(200);
+
+/// stderr fragments of failures that go away on their own: pfctl and dnctl
+/// racing another process for the PF device. Anything else, such as a
+/// syntax error in the rules, fails the same way every time.
+const TRANSIENT_ERRORS: [&str; 4] = [
+    "Device busy",
+    "Resource busy",
+    "Resource temporarily unavailable",
+    "Interrupted system call",
+];
+
+/// Whether `e` is a failed command whose stderr says it may work if tried
+/// again.
+fn is_transient(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<KickerError>(),
+        Some(KickerError::CommandFailed { stderr, .. })
+            if TRANSIENT_ERRORS.iter().any(|pattern| stderr.contains(pattern))
+    )
+}
+
+/// Runs `cmd` through `runner` up to `attempts` times, backing off
+/// exponentially between attempts, but only while it fails transiently.
+///
+/// Blocks the calling thread while it backs off, as [`CommandRunner::run`]
+/// does while a command runs; async code gets here through
+/// [`crate::RunSettings::run_blocking`].
+pub fn run_retry(
+    runner: &dyn CommandRunner,
+    cmd: &str,
+    args: &[&str],
+    attempts: u32,
+) -> Result<Output> {
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match runner.run(cmd, args) {
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                warn!(
+                    "{} failed transiently, retrying in {}ms ({}/{})",
+                    cmd,
+                    backoff.as_millis(),
+                    attempt,
+                    attempts
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Records commands instead of running them, answering each with a
+/// successful exit and `stdout`. Backs `--dry-run`, where every command is
+/// also printed.
+pub struct MockRunner {
+    stdout: Vec<u8>,
+    echo: bool,
+    invocations: Mutex<Vec<Vec<String>>>,
+}
+
+impl MockRunner {
+    /// Answers every command with `stdout`, printing each one if `echo`.
+    pub fn new(stdout: impl Into<Vec<u8>>, echo: bool) -> Self {
+        MockRunner {
+            stdout: stdout.into(),
+            echo,
+            invocations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Every command recorded so far, as `[cmd, args...]`.
+    pub fn invocations(&self) -> Vec<Vec<String>> {
+        self.invocations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl CommandRunner for MockRunner {
+    fn run(&self, cmd: &str, args: &[&str]) -> Result<Output> {
+        if self.echo {
+            println!("would run: sudo {} {}", cmd, args.join(" "));
+        }
+        let mut invocation = vec![cmd.to_string()];
+        invocation.extend(args.iter().map(|a| a.to_string()));
+        self.invocations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(invocation);
+        Ok(Output {
+            status: ExitStatus::from_raw(0),
+            stdout: self.stdout.clone(),
+            stderr: Vec::new(),
+        })
+    }
+
+    fn runs_commands(&self) -> bool {
+        false
+    }
+}
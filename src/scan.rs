@@ -0,0 +1,2017 @@
+//! The `scan` command: discovery, name enrichment and output.
+
+use crate::alias::{self, AliasTarget};
+use crate::anomaly::{self, Conflict};
+use crate::device::{
+    csv_row, format_age, merge_devices, parse_arp_table, parse_ip_neigh, parse_ipv6_neighbors,
+    parse_nmap_greppable, parse_nmap_os, print_device_table, print_table, sort_devices, unix_now,
+    Device, DeviceFilter, DeviceSource, HostRole, NameSource, SortKey,
+};
+use crate::error::KickerError;
+use crate::fingerprint::{self, DeviceClass};
+use crate::mac::MacAddr;
+use crate::mdns::MdnsInfo;
+use crate::runner::SudoRunner;
+use crate::snmp::{self, SnmpRouter};
+use crate::state::{ManagedTarget, RuleKind, State};
+use crate::wifi::{self, WifiLink};
+use crate::{
+    dhcp, diff, find_in_path, firewall, history, interface, mdns, models, nmap, notify, oui, ping,
+    probe, report, resolve, store, sweep, RunSettings,
+};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressStyle};
+use ipnetwork::Ipv4Network;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::time::timeout;
+
+/// Bonjour names learned by `scan`, keyed by IP.
+pub const NAMES_FILE: &str = "names.json";
+/// Prefixes shorter than this (more than 65536 addresses) need `--force`.
+const MIN_PREFIX: u8 = 16;
+/// Default upper bound for each nmap run, plenty for a /24; a /16 takes a
+/// few minutes, so larger ranges need `--nmap-timeout`.
+pub const DEFAULT_NMAP_TIMEOUT: Duration = Duration::from_secs(120);
+/// How long `--os-detect` gives nmap per host, as its `--host-timeout`.
+const OS_HOST_TIMEOUT: &str = "30s";
+/// `arp -a` resolves names and can stall on a slow resolver.
+const NEIGHBOR_TIMEOUT: Duration = Duration::from_secs(10);
+/// Two echo requests a second apart, then a moment for late answers
+const PING6_TIMEOUT: Duration = Duration::from_secs(4);
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ScanMethod {
+    /// nmap ping scan
+    Nmap,
+    /// Built-in TCP connect sweep of the interface subnet
+    Native,
+}
+
+/// How much effort `scan` puts in, traded against speed.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanProfile {
+    /// ARP cache plus a short native sweep, without names or extra probes
+    Fast,
+    /// Discovery with names, latency and device types
+    #[default]
+    Normal,
+    /// Normal plus longer timeouts and a probe of common ports on every device
+    Thorough,
+}
+
+/// The knobs behind each profile, all tuned here.
+pub struct ProfileSettings {
+    /// Use the native sweep even when nmap is installed
+    pub prefer_native: bool,
+    /// Per-port connect timeout of the native sweep
+    pub sweep_timeout: Duration,
+    /// Hosts the sweep probes at once; kept well under macOS's default 256
+    /// open file limit
+    pub sweep_concurrency: usize,
+    pub resolve: bool,
+    pub mdns: bool,
+    /// How long to wait for Bonjour replies
+    pub mdns_budget: Duration,
+    /// Ping every device for its round trip time
+    pub latency: bool,
+    pub fingerprint: bool,
+    /// Number of common ports to probe on each device, 0 for none
+    pub probe_ports: usize,
+    pub probe_timeout: Duration,
+}
+
+impl ScanProfile {
+    pub fn label(self) -> &'static str {
+        match self {
+            ScanProfile::Fast => "fast",
+            ScanProfile::Normal => "normal",
+            ScanProfile::Thorough => "thorough",
+        }
+    }
+
+    pub fn settings(self) -> ProfileSettings {
+        let normal = ProfileSettings {
+            prefer_native: false,
+            sweep_timeout: Duration::from_millis(300),
+            sweep_concurrency: 128,
+            resolve: true,
+            mdns: true,
+            mdns_budget: Duration::from_secs(3),
+            latency: true,
+            fingerprint: true,
+            probe_ports: 0,
+            probe_timeout: Duration::from_millis(500),
+        };
+        match self {
+            ScanProfile::Fast => ProfileSettings {
+                prefer_native: true,
+                sweep_timeout: Duration::from_millis(150),
+                resolve: false,
+                mdns: false,
+                latency: false,
+                fingerprint: false,
+                ..normal
+            },
+            ScanProfile::Normal => normal,
+            ScanProfile::Thorough => ProfileSettings {
+                sweep_timeout: Duration::from_millis(800),
+                sweep_concurrency: 64,
+                mdns_budget: Duration::from_secs(6),
+                probe_ports: 20,
+                ..normal
+            },
+        }
+    }
+}
+
+/// How `scan` prints its results.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Tables and the change summary
+    Text,
+    Json,
+    /// A standalone HTML page
+    Html,
+    Csv,
+}
+
+/// A file `scan --output-file` saves the devices to, in the format its
+/// extension names.
+#[derive(Clone, Debug)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    json: bool,
+}
+
+impl OutputFile {
+    /// Accepts paths ending in `.json` or `.csv`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let path = PathBuf::from(s);
+        let extension = path.extension().and_then(|e| e.to_str());
+        match extension.map(str::to_ascii_lowercase).as_deref() {
+            Some("json") => Ok(Self { path, json: true }),
+            Some("csv") => Ok(Self { path, json: false }),
+            _ => Err(format!(
+                "{} should end in .json or .csv, which picks the format to write",
+                s
+            )),
+        }
+    }
+
+    /// Writes `contents` to the file, creating its directory first.
+    fn write(&self, contents: &str) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        emit(contents, Some(&self.path))
+    }
+}
+
+pub struct ScanOptions<'a> {
+    pub interface: &'a str,
+    /// Subnet to scan instead of the interface's own
+    pub range: Option<Ipv4Network>,
+    /// Allow ranges larger than a /16
+    pub force: bool,
+    /// Addresses never to probe, in the sweep, nmap or any lookup
+    pub exclude: Vec<Ipv4Network>,
+    pub method: Option<ScanMethod>,
+    pub resolve: bool,
+    pub mdns: bool,
+    /// Ask hosts that reverse DNS and mDNS have no name for their NetBIOS
+    /// name; part of the `resolve` step
+    pub netbios: bool,
+    /// Guess each device's type, which probes a few ports on it
+    pub fingerprint: bool,
+    /// Have nmap guess the OS of every device that answered; needs root
+    pub os_detect: bool,
+    /// How long each nmap run may take before it is stopped
+    pub nmap_timeout: Duration,
+    pub nmap: NmapCommand,
+    /// Also read this router's ARP table
+    pub snmp: Option<SnmpRouter>,
+    /// Ping ff02::1 before reading the IPv6 neighbor cache
+    pub ping_ipv6: bool,
+    /// Timeouts, concurrency and which lookups run; `resolve`, `mdns` and
+    /// `fingerprint` can only turn its lookups off
+    pub profile: ScanProfile,
+    /// Limits which devices are displayed
+    pub filter: DeviceFilter,
+    /// Show a spinner with the running phase on stderr
+    pub progress: bool,
+    pub sort: SortKey,
+    /// Sort descending
+    pub reverse: bool,
+    /// Serve the saved scan instead of rescanning when it is at most this
+    /// many seconds old
+    pub max_age: Option<u64>,
+}
+
+/// Lookups remembered between passes of `scan --watch`. `None` (or an empty
+/// `MdnsInfo`) records a lookup that found nothing, so it isn't retried
+/// every pass.
+#[derive(Default)]
+pub struct LookupCache {
+    hostnames: HashMap<Ipv4Addr, Option<(String, NameSource)>>,
+    mdns: HashMap<Ipv4Addr, MdnsInfo>,
+    classes: HashMap<Ipv4Addr, DeviceClass>,
+    os: HashMap<Ipv4Addr, Option<String>>,
+    models: HashMap<Ipv4Addr, Option<String>>,
+}
+
+/// Returns `ifconfig` output for `interface`, failing if it doesn't exist.
+pub fn interface_config(interface: &str) -> Result<String> {
+    let ifconfig_output = Command::new("ifconfig")
+        .arg(interface)
+        .output()
+        .context("Failed to check interface")?;
+
+    if !ifconfig_output.status.success() {
+        return Err(KickerError::InterfaceNotFound(interface.to_string()).into());
+    }
+    Ok(String::from_utf8_lossy(&ifconfig_output.stdout).into_owned())
+}
+
+/// The IPv4 subnet `interface` is attached to, from the address and
+/// netmask `ifconfig` reports for it.
+pub fn local_subnet(interface: &str) -> Result<Ipv4Network> {
+    subnet_of(interface, &interface_config(interface)?)
+}
+
+/// [`local_subnet`] from the interface's `ifconfig` output.
+fn subnet_of(interface: &str, ifconfig: &str) -> Result<Ipv4Network> {
+    sweep::interface_network(ifconfig)
+        .ok_or_else(|| KickerError::NoIpv4Address(interface.to_string()).into())
+}
+
+/// Ping scans the local subnet of `interface` with nmap and returns the
+/// hosts that answered, giving nmap up to `limit`. Unlike `scan`, nothing is
+/// looked up or saved.
+pub async fn nmap_sweep(
+    nmap: &NmapCommand,
+    interface: &str,
+    limit: Duration,
+) -> Result<Vec<Device>> {
+    let network = local_subnet(interface)?;
+    check_range_size(network, false)?;
+    let program = nmap
+        .program()?
+        .ok_or_else(|| anyhow!("nmap is not installed, so the subnet can't be swept"))?;
+    info!("Sweeping {} on {} with nmap", network, interface);
+    let (interface_arg, network) = (format!("-e{}", interface), network.to_string());
+    let mut args = vec!["-sn", &interface_arg, "-oX", "-", &network];
+    args.extend(nmap.args.iter().map(String::as_str));
+    let output = command_output(&program, &args, limit).await?;
+    if !output.status.success() {
+        return Err(KickerError::CommandFailed {
+            cmd: program,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    nmap::parse_hosts(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn timed_out(cmd: &str, limit: Duration) -> anyhow::Error {
+    KickerError::TimedOut {
+        cmd: cmd.to_string(),
+        secs: limit.as_secs(),
+    }
+    .into()
+}
+
+/// Runs `cmd` without blocking the runtime, killing it after `limit`.
+async fn command_output(cmd: &str, args: &[&str], limit: Duration) -> Result<Output> {
+    let output = tokio::process::Command::new(cmd)
+        .args(args)
+        .kill_on_drop(true)
+        .output();
+    timeout(limit, output)
+        .await
+        .map_err(|_| timed_out(cmd, limit))?
+        .with_context(|| format!("Failed to run {}", cmd))
+}
+
+/// Like [`command_output`], but hands each line of stdout to `on_line` as
+/// it arrives, so long runs can report progress.
+async fn command_lines(
+    cmd: &str,
+    args: &[&str],
+    limit: Duration,
+    mut on_line: impl FnMut(&str),
+) -> Result<Output> {
+    let mut child = tokio::process::Command::new(cmd)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to run {}", cmd))?;
+    let (Some(stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        return Err(anyhow!("No output pipes for {}", cmd));
+    };
+
+    let run = async {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut collected = Vec::new();
+        let read_stdout = async {
+            while let Some(line) = lines.next_line().await? {
+                on_line(&line);
+                collected.extend_from_slice(line.as_bytes());
+                collected.push(b'\n');
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        let mut errors = Vec::new();
+        let (read, _) = tokio::join!(read_stdout, stderr.read_to_end(&mut errors));
+        read?;
+        let status = child.wait().await?;
+        Ok::<_, std::io::Error>(Output {
+            status,
+            stdout: collected,
+            stderr: errors,
+        })
+    };
+    // Dropping the child on timeout kills it
+    timeout(limit, run)
+        .await
+        .map_err(|_| timed_out(cmd, limit))?
+        .with_context(|| format!("Failed to run {}", cmd))
+}
+
+/// A command listing the neighbor cache, with the parser for its output.
+type NeighborCommand = (
+    &'static str,
+    &'static [&'static str],
+    fn(&str) -> Vec<Device>,
+);
+
+/// `ip neigh` on Linux, `arp -a` elsewhere or when iproute2 is missing.
+fn neighbor_command() -> NeighborCommand {
+    if cfg!(target_os = "linux") && find_in_path("ip").is_some() {
+        ("ip", &["neigh", "show"], parse_ip_neigh)
+    } else {
+        ("arp", &["-a"], parse_arp_table)
+    }
+}
+
+/// Reads the neighbor (ARP) cache.
+pub fn neighbor_table() -> Result<Vec<Device>> {
+    let (cmd, args, parse) = neighbor_command();
+    let output = Command::new(cmd)
+        .args(args)
+        .output()
+        .context("Failed to read the neighbor table")?;
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Like [`neighbor_table`], for the scan path, where a stalled `arp` must
+/// not hang the whole scan.
+async fn read_neighbor_table() -> Result<Vec<Device>> {
+    let (cmd, args, parse) = neighbor_command();
+    let output = command_output(cmd, args, NEIGHBOR_TIMEOUT)
+        .await
+        .context("Failed to read the neighbor table")?;
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Pings the all-nodes multicast address on `interface`, so that the IPv6
+/// neighbor cache lists every node that answers. Best-effort.
+async fn ping_all_nodes(interface: &str) {
+    let target = format!("ff02::1%{}", interface);
+    let output = if cfg!(target_os = "linux") {
+        command_output("ping", &["-6", "-c", "2", &target], PING6_TIMEOUT).await
+    } else {
+        command_output("ping6", &["-c", "2", &target], PING6_TIMEOUT).await
+    };
+    if let Err(e) = output {
+        info!("Pinging {} failed: {:#}", target, e);
+    }
+}
+
+/// Reads `interface`'s IPv6 neighbor cache (`ndp -an`, or `ip -6 neigh` on
+/// Linux). Best-effort, since IPv6 may be disabled.
+async fn ipv6_neighbor_table(interface: &str) -> Vec<(Ipv6Addr, MacAddr)> {
+    let output = if cfg!(target_os = "linux") {
+        command_output(
+            "ip",
+            &["-6", "neigh", "show", "dev", interface],
+            NEIGHBOR_TIMEOUT,
+        )
+        .await
+    } else {
+        command_output("ndp", &["-an"], NEIGHBOR_TIMEOUT).await
+    };
+    match output {
+        Ok(output) => {
+            let table = String::from_utf8_lossy(&output.stdout);
+            if cfg!(target_os = "linux") {
+                parse_ipv6_neighbors(&table)
+            } else {
+                // ndp lists every interface; the third column names it
+                let lines: Vec<&str> = table
+                    .lines()
+                    .filter(|line| line.split_whitespace().nth(2) == Some(interface))
+                    .collect();
+                parse_ipv6_neighbors(&lines.join("\n"))
+            }
+        }
+        Err(e) => {
+            warn!("Failed to read the IPv6 neighbor table: {:#}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Attaches `interface`'s IPv6 neighbors to devices with the same MAC and
+/// returns the ones that matched no IPv4 device. With `ping`, all nodes are
+/// pinged first to fill the neighbor cache.
+async fn attach_ipv6(
+    devices: &mut [Device],
+    interface: &str,
+    ping: bool,
+) -> Vec<(Ipv6Addr, MacAddr)> {
+    if ping {
+        ping_all_nodes(interface).await;
+    }
+    let mut unmatched = Vec::new();
+    for (ip, mac) in ipv6_neighbor_table(interface).await {
+        match devices.iter_mut().find(|d| d.mac == Some(mac)) {
+            Some(device) if !device.ipv6.contains(&ip) => device.ipv6.push(ip),
+            Some(_) => {}
+            None => unmatched.push((ip, mac)),
+        }
+    }
+    unmatched
+}
+
+/// Prints the IPv6 neighbors whose MAC no IPv4 device has; the others are
+/// in the device table's IPV6 column.
+fn print_ipv6_neighbors(unmatched: &[(Ipv6Addr, MacAddr)]) {
+    if unmatched.is_empty() {
+        return;
+    }
+    let rows: Vec<[String; 3]> = unmatched
+        .iter()
+        .map(|(ip, mac)| {
+            [
+                ip.to_string(),
+                mac.to_string(),
+                oui::lookup_vendor(mac).unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    println!("\nIPv6-only neighbors:");
+    print_table(["IPV6", "MAC", "VENDOR"], &rows, |_| false);
+}
+
+/// Prints what the interface is connected to: full link details when the
+/// wireless tools report them, else just the network name.
+fn print_network_header(interface: &str, link: Option<&WifiLink>) -> Result<()> {
+    if let Some(link) = link {
+        wifi::print_link(link);
+        return Ok(());
+    }
+
+    // Get current WiFi network name
+    let output = if cfg!(target_os = "linux") {
+        match Command::new("iwgetid").args([interface, "-r"]).output() {
+            Ok(output) => output,
+            // Wired-only machines often lack wireless-tools
+            Err(_) => return Ok(()),
+        }
+    } else {
+        Command::new("networksetup")
+            .args(["-getairportnetwork", interface])
+            .output()
+            .context("Failed to get current network")?
+    };
+
+    println!(
+        "Current network: {}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    Ok(())
+}
+
+/// Parses a `--range` value, normalizing host bits away
+/// (`192.168.50.7/24` becomes `192.168.50.0/24`).
+pub fn parse_range(s: &str) -> Result<Ipv4Network, String> {
+    if !s.contains('/') {
+        return Err("expected CIDR notation, e.g. 192.168.50.0/24".to_string());
+    }
+    let network: Ipv4Network = s.parse().map_err(|e| format!("{}", e))?;
+    Ipv4Network::new(network.network(), network.prefix()).map_err(|e| format!("{}", e))
+}
+
+/// nmap's output formats: XML, or the greppable text of versions whose XML
+/// didn't parse.
+#[derive(Clone, Copy)]
+enum NmapOutput {
+    Xml,
+    Greppable,
+}
+
+/// Which nmap to run, and extra arguments for every run of it.
+#[derive(Clone, Default)]
+pub struct NmapCommand {
+    /// An nmap binary outside `PATH`
+    pub path: Option<PathBuf>,
+    /// Passed after wifi-kicker's own arguments, e.g. "--min-rate=500"
+    pub args: Vec<String>,
+}
+
+impl NmapCommand {
+    /// Fails for arguments that would change nmap's output, which
+    /// wifi-kicker reads from stdout in a format of its choosing.
+    pub fn check_arg(arg: &str) -> Result<String, String> {
+        if arg.starts_with("-o") || arg.starts_with("--append-output") || arg == "--resume" {
+            return Err(format!(
+                "{} would change nmap's output, which wifi-kicker parses itself",
+                arg
+            ));
+        }
+        Ok(arg.to_string())
+    }
+
+    /// The program to run: `path` if it is an executable file, else nmap
+    /// from `PATH`, or `None` without either.
+    fn program(&self) -> Result<Option<String>> {
+        for arg in &self.args {
+            Self::check_arg(arg).map_err(|e| anyhow!(e))?;
+        }
+        let Some(path) = &self.path else {
+            return Ok(find_in_path("nmap").map(|_| "nmap".to_string()));
+        };
+        let executable = fs::metadata(path)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0);
+        if !executable {
+            return Err(anyhow!(
+                "nmap path {} is not an executable file",
+                path.display()
+            ));
+        }
+        Ok(Some(path.to_string_lossy().into_owned()))
+    }
+}
+
+/// The resolved nmap of one scan.
+struct NmapRun<'a> {
+    program: String,
+    extra_args: &'a [String],
+    /// How long each run may take
+    limit: Duration,
+}
+
+impl NmapRun<'_> {
+    async fn run(&self, args: &[&str], on_line: impl FnMut(&str)) -> Result<Output> {
+        let mut args = args.to_vec();
+        args.extend(self.extra_args.iter().map(String::as_str));
+        command_lines(&self.program, &args, self.limit, on_line).await
+    }
+}
+
+/// Ping scans `network` with nmap, reading its XML output; nmap output
+/// that isn't XML gets a second scan in the greppable format.
+async fn run_nmap_scan(
+    nmap: &NmapRun<'_>,
+    interface: &str,
+    network: Ipv4Network,
+    exclude: &[Ipv4Network],
+    progress: &ProgressBar,
+) -> Result<Vec<Device>> {
+    let xml = nmap_ping_scan(nmap, interface, network, exclude, progress, NmapOutput::Xml).await?;
+    match nmap::parse_hosts(&xml) {
+        Ok(devices) => Ok(devices),
+        Err(e) => {
+            warn!("{:#}; retrying with greppable output", e);
+            let text = nmap_ping_scan(
+                nmap,
+                interface,
+                network,
+                exclude,
+                progress,
+                NmapOutput::Greppable,
+            )
+            .await?;
+            Ok(parse_nmap_greppable(&text))
+        }
+    }
+}
+
+/// Runs an nmap ping scan and returns what it printed in `format`.
+async fn nmap_ping_scan(
+    nmap: &NmapRun<'_>,
+    interface: &str,
+    network: Ipv4Network,
+    exclude: &[Ipv4Network],
+    progress: &ProgressBar,
+    format: NmapOutput,
+) -> Result<String> {
+    let interface_arg = format!("-e{}", interface);
+    let network_arg = network.to_string();
+    let format_arg = match format {
+        NmapOutput::Xml => "-oX",
+        NmapOutput::Greppable => "-oG",
+    };
+    // -sn performs ping scan
+    let mut args = vec!["-sn", &interface_arg, format_arg, "-", &network_arg];
+    let exclude_arg = exclude
+        .iter()
+        .map(|network| network.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    if !exclude.is_empty() {
+        args.extend(["--exclude", &exclude_arg]);
+    }
+    // Both formats print each host as soon as it answers
+    let mut up = 0;
+    // In XML the address follows the status, usually on the next line
+    let mut address_next = false;
+    let count_hosts = |line: &str| {
+        let answered = match format {
+            NmapOutput::Xml => line.contains("<status state=\"up\""),
+            NmapOutput::Greppable => line.starts_with("Host:") && line.contains("Status: Up"),
+        };
+        if answered {
+            up += 1;
+            progress.set_message(format!("Running nmap ping scan ({} up so far)", up));
+        }
+        // Without a spinner to update, name each host as nmap finds it
+        if !progress.is_hidden() {
+            return;
+        }
+        let host = match format {
+            NmapOutput::Greppable if answered => line.split_whitespace().nth(1),
+            NmapOutput::Greppable => None,
+            NmapOutput::Xml => {
+                address_next |= answered;
+                let addr = line
+                    .split("<address addr=\"")
+                    .nth(1)
+                    .filter(|_| address_next);
+                if addr.is_some() {
+                    address_next = false;
+                }
+                addr.and_then(|rest| rest.split('"').next())
+            }
+        };
+        if let Some(host) = host {
+            info!("nmap: {} is up", host);
+        }
+    };
+    // kill_on_drop in command_lines means an interrupted or timed out scan
+    // doesn't leave nmap running
+    let nmap_output = nmap.run(&args, count_hosts).await?;
+    if !nmap_output.status.success() {
+        warn!(
+            "nmap exited with {}: {}",
+            nmap_output.status,
+            String::from_utf8_lossy(&nmap_output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&nmap_output.stdout).into_owned())
+}
+
+/// Runs nmap OS detection against the devices that answered the scan,
+/// except this machine. Each host gets at most [`OS_HOST_TIMEOUT`]; guesses
+/// (or their absence) are cached so watch passes don't repeat them.
+async fn detect_os(
+    nmap: &NmapRun<'_>,
+    devices: &mut [Device],
+    cache: &mut HashMap<Ipv4Addr, Option<String>>,
+    progress: &ProgressBar,
+) {
+    let targets: Vec<String> = devices
+        .iter()
+        .filter(|d| d.up && d.role != Some(HostRole::ThisHost) && !cache.contains_key(&d.ip))
+        .map(|d| d.ip.to_string())
+        .collect();
+    if !targets.is_empty() {
+        let mut args = vec![
+            "-O",
+            "--osscan-guess",
+            "--max-os-tries",
+            "1",
+            "--host-timeout",
+            OS_HOST_TIMEOUT,
+            "-Pn",
+            "-n",
+        ];
+        args.extend(targets.iter().map(String::as_str));
+        // Normal output starts each host's section with its report line
+        let mut done = 0;
+        let count_hosts = |line: &str| {
+            if line.starts_with("Nmap scan report for") {
+                done += 1;
+                progress.set_message(format!(
+                    "Detecting operating systems ({}/{})",
+                    done,
+                    targets.len()
+                ));
+            }
+        };
+        match nmap.run(&args, count_hosts).await {
+            Ok(output) => {
+                let mut guesses = parse_nmap_os(&String::from_utf8_lossy(&output.stdout));
+                for ip in &targets {
+                    if let Ok(ip) = ip.parse() {
+                        cache.insert(ip, guesses.remove(&ip));
+                    }
+                }
+            }
+            Err(e) => warn!("nmap OS detection failed: {:#}", e),
+        }
+    }
+    for device in devices {
+        device.os = cache.get(&device.ip).cloned().flatten();
+    }
+}
+
+async fn run_native_scan(
+    interface: &str,
+    ifconfig: &str,
+    network: Ipv4Network,
+    exclude: &[Ipv4Network],
+    settings: &ProfileSettings,
+    progress: &ProgressBar,
+) -> Result<Vec<Device>> {
+    let own_ip = sweep::parse_ifconfig_inet(ifconfig).map(|(addr, _)| addr);
+    info!("Sweeping {} on {}", network, interface);
+    progress.set_message(format!("Sweeping {}", network));
+    sweep::ping_sweep(
+        network,
+        own_ip,
+        exclude,
+        settings.sweep_timeout,
+        settings.sweep_concurrency,
+        progress,
+    )
+    .await
+}
+
+/// The network to scan: `range`, or else the subnet of the interface
+/// `ifconfig` describes, computed from its address and netmask.
+fn target_network(
+    interface: &str,
+    ifconfig: &str,
+    range: Option<Ipv4Network>,
+    force: bool,
+) -> Result<Ipv4Network> {
+    let network = match range {
+        Some(range) => range,
+        None => sweep::interface_network(ifconfig)
+            .ok_or_else(|| KickerError::NoIpv4Address(interface.to_string()))?,
+    };
+    check_range_size(network, force)?;
+    Ok(network)
+}
+
+fn check_range_size(network: Ipv4Network, force: bool) -> Result<()> {
+    if network.prefix() < MIN_PREFIX && !force {
+        return Err(KickerError::RangeTooLarge {
+            network,
+            min_prefix: MIN_PREFIX,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Fills in `mdns_name` from a fresh browse, or the cache when there was
+/// none, and remembers the names so later commands can refer to devices by
+/// them.
+fn apply_mdns_names(
+    devices: &mut [Device],
+    cache: &mut LookupCache,
+    found: Option<HashMap<Ipv4Addr, MdnsInfo>>,
+) {
+    if let Some(found) = found {
+        let mut names: BTreeMap<Ipv4Addr, String> = store::load(NAMES_FILE).unwrap_or_else(|e| {
+            warn!("Ignoring saved device names: {:#}", e);
+            BTreeMap::new()
+        });
+        for device in devices.iter() {
+            let info = found.get(&device.ip).cloned().unwrap_or_default();
+            if let Some(name) = &info.name {
+                names.insert(device.ip, name.clone());
+            }
+            cache.mdns.insert(device.ip, info);
+        }
+        if let Err(e) = store::save(NAMES_FILE, &names) {
+            warn!("Failed to save device names: {:#}", e);
+        }
+    }
+
+    for device in devices.iter_mut() {
+        if let Some(info) = cache.mdns.get(&device.ip) {
+            device.mdns_name = info.name.clone();
+            device.services = info.services.clone();
+        }
+    }
+}
+
+/// Bonjour services only Apple devices advertise, to recognise the ones
+/// hiding behind a randomized MAC.
+const APPLE_SERVICES: [&str; 3] = ["_companion-link._tcp", "_airplay._tcp", "_raop._tcp"];
+
+/// How long device-info queries wait for replies.
+const MODEL_BUDGET: Duration = Duration::from_secs(1);
+
+/// Fills in `model` for Apple devices from their device-info TXT records.
+/// Only devices with a Bonjour instance name can be asked; results,
+/// including no answer, are kept in `cache`.
+async fn identify_models(devices: &mut [Device], cache: &mut HashMap<Ipv4Addr, Option<String>>) {
+    let is_apple = |d: &Device| {
+        d.vendor.as_deref().is_some_and(|v| v.starts_with("Apple"))
+            || APPLE_SERVICES.iter().any(|s| d.services.contains(*s))
+    };
+    let instances: Vec<(Ipv4Addr, String)> = devices
+        .iter()
+        .filter(|d| is_apple(d) && !cache.contains_key(&d.ip))
+        .filter_map(|d| Some((d.ip, d.mdns_name.clone()?)))
+        .collect();
+    let mut found = mdns::device_models(&instances, MODEL_BUDGET).await;
+    for (ip, _) in instances {
+        cache.insert(ip, found.remove(&ip));
+    }
+    for device in devices.iter_mut() {
+        device.model = cache
+            .get(&device.ip)
+            .cloned()
+            .flatten()
+            .map(|identifier| models::product_name(&identifier));
+    }
+}
+
+/// A spinner on stderr naming the current phase, or a hidden one when
+/// progress is off so callers don't have to check.
+fn progress_spinner(enabled: bool) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::with_template("{spinner} {msg} [{elapsed}]")
+            .unwrap_or_else(|_| ProgressStyle::default_spinner()),
+    );
+    spinner.enable_steady_tick(Duration::from_millis(100));
+    spinner
+}
+
+/// Runs one discovery pass: ping scan, ARP cache, then name lookups.
+pub async fn discover(options: &ScanOptions<'_>, cache: &mut LookupCache) -> Result<Vec<Device>> {
+    let progress = progress_spinner(options.progress);
+    let devices = discover_with_progress(options, cache, &progress).await;
+    progress.finish_and_clear();
+    devices
+}
+
+async fn discover_with_progress(
+    options: &ScanOptions<'_>,
+    cache: &mut LookupCache,
+    progress: &ProgressBar,
+) -> Result<Vec<Device>> {
+    progress.set_message(format!("Checking interface {}", options.interface));
+    let ifconfig = interface_config(options.interface)?;
+    let settings = options.profile.settings();
+    let resolve_names = options.resolve && settings.resolve;
+    let browse_mdns = options.mdns && settings.mdns;
+
+    let nmap = options.nmap.program()?.map(|program| NmapRun {
+        program,
+        extra_args: &options.nmap.args,
+        limit: options.nmap_timeout,
+    });
+    let nmap_installed = nmap.is_some();
+    if options.os_detect && !nmap_installed {
+        return Err(KickerError::ToolMissing("nmap").into());
+    }
+    let method = match options.method {
+        Some(ScanMethod::Nmap) if !nmap_installed => {
+            return Err(KickerError::ToolMissing("nmap").into());
+        }
+        Some(method) => method,
+        None if settings.prefer_native => ScanMethod::Native,
+        None if nmap_installed => ScanMethod::Nmap,
+        None => {
+            // stderr, so --json and --csv output stays clean
+            progress.suspend(|| {
+                eprintln!(
+                    "Note: nmap not found; using the built-in sweep and the ARP cache instead"
+                )
+            });
+            ScanMethod::Native
+        }
+    };
+    let network = target_network(options.interface, &ifconfig, options.range, options.force)?;
+    // The nmap method is only chosen with nmap at hand
+    let scanned = match nmap.as_ref().filter(|_| method == ScanMethod::Nmap) {
+        Some(nmap) => {
+            progress.set_message("Running nmap ping scan");
+            match run_nmap_scan(nmap, options.interface, network, &options.exclude, progress).await
+            {
+                Ok(devices) => devices,
+                // The ARP cache alone is still worth showing, unless nmap
+                // was asked for explicitly
+                Err(e) if options.method.is_none() => {
+                    progress.suspend(|| eprintln!("Note: {:#}; showing the ARP cache only", e));
+                    Vec::new()
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        None => {
+            run_native_scan(
+                options.interface,
+                &ifconfig,
+                network,
+                &options.exclude,
+                &settings,
+                progress,
+            )
+            .await?
+        }
+    };
+
+    // Still include ARP cache for recently seen devices
+    progress.set_message("Reading ARP cache");
+    let mut devices = merge_devices(scanned, read_neighbor_table().await?);
+    if let Some(router) = &options.snmp {
+        progress.set_message(format!(
+            "Reading the ARP table of {} over SNMP",
+            router.address
+        ));
+        match snmp::arp_table(router).await {
+            Ok(entries) => snmp::merge(&mut devices, entries),
+            Err(e) => progress.suspend(|| eprintln!("Note: {:#}", e)),
+        }
+    }
+    mark_roles(&mut devices, &ifconfig);
+    dhcp::apply_lease_names(&mut devices, &dhcp::leases(options.interface));
+    // Excluded devices seen in the ARP cache are set aside, so that none of
+    // the lookups below probe them
+    let (excluded, mut devices): (Vec<Device>, Vec<Device>) = devices
+        .into_iter()
+        .partition(|d| options.exclude.iter().any(|network| network.contains(d.ip)));
+
+    // Reverse DNS and the Bonjour browse are independent and mostly
+    // waiting, so they overlap
+    progress.set_message(match (resolve_names, browse_mdns) {
+        (true, true) => "Resolving hostnames and browsing Bonjour",
+        (true, false) => "Resolving hostnames",
+        _ => "Browsing Bonjour",
+    });
+    // Skip the browse when every device was looked up in an earlier pass
+    let browse_needed = browse_mdns && devices.iter().any(|d| !cache.mdns.contains_key(&d.ip));
+    let browse = async {
+        match browse_needed {
+            true => Some(mdns::browse(settings.mdns_budget).await),
+            false => None,
+        }
+    };
+    let hostnames = &mut cache.hostnames;
+    let resolve = async {
+        if resolve_names {
+            resolve::resolve_hostnames(&mut devices, hostnames, options.netbios).await;
+        }
+    };
+    let (found, ()) = tokio::join!(browse, resolve);
+    if browse_mdns {
+        apply_mdns_names(&mut devices, cache, found);
+        progress.set_message("Asking Apple devices for their model");
+        identify_models(&mut devices, &mut cache.models).await;
+    }
+    if settings.latency {
+        progress.set_message("Measuring latency");
+        ping::ping_devices(&mut devices).await;
+    }
+    if options.fingerprint && settings.fingerprint {
+        progress.set_message("Guessing device types");
+        fingerprint::fingerprint_devices(&mut devices, &mut cache.classes).await;
+    }
+    if settings.probe_ports > 0 {
+        progress.set_message("Probing common ports");
+        let ports = probe::top_ports(settings.probe_ports);
+        // One device at a time keeps the open sockets to one device's ports
+        for device in devices
+            .iter_mut()
+            .filter(|d| d.up && d.role != Some(HostRole::ThisHost))
+        {
+            device.open_ports = probe::scan_ports(device.ip, &ports, settings.probe_timeout).await;
+        }
+    }
+    if let (true, Some(nmap)) = (options.os_detect, &nmap) {
+        progress.set_message("Detecting operating systems");
+        detect_os(nmap, &mut devices, &mut cache.os, progress).await;
+    }
+    devices.extend(excluded.into_iter().map(|d| Device {
+        excluded: true,
+        ..d
+    }));
+    devices.sort_by_key(|d| d.ip);
+    if let Err(e) = history::record(&devices) {
+        warn!("Failed to update device history: {:#}", e);
+    }
+    Ok(devices)
+}
+
+/// Tags the gateway and our own address. Neither reliably answers the sweep:
+/// routers often drop probes but stay in the ARP cache (merged in already,
+/// so they are tagged either way), and the sweep skips our own address, so a
+/// row for this machine is added if the scanner didn't report it.
+fn mark_roles(devices: &mut Vec<Device>, ifconfig: &str) {
+    if let Some(gateway) = interface::default_gateway() {
+        if let Some(device) = devices.iter_mut().find(|d| d.ip == gateway) {
+            device.role = Some(HostRole::Gateway);
+        }
+    }
+
+    let Some((own, _)) = sweep::parse_ifconfig_inet(ifconfig) else {
+        return;
+    };
+    match devices.iter_mut().find(|d| d.ip == own) {
+        Some(device) => device.role = Some(HostRole::ThisHost),
+        None => {
+            let mac = ifconfig.lines().find_map(|line| {
+                line.trim()
+                    .strip_prefix("ether ")?
+                    .split_whitespace()
+                    .next()?
+                    .parse()
+                    .ok()
+            });
+            devices.push(Device {
+                mac,
+                vendor: mac.and_then(|mac: MacAddr| oui::lookup_vendor(&mac)),
+                last_seen: Some(unix_now()),
+                role: Some(HostRole::ThisHost),
+                ..Device::new(own, DeviceSource::Sweep)
+            });
+        }
+    }
+}
+
+fn print_hidden(filter: &DeviceFilter, hidden: usize) {
+    if !filter.is_empty() {
+        println!("{} device(s) hidden by filters", hidden);
+    }
+}
+
+/// The devices the last `scan` listed, in the order shown, for `--device N`.
+const LISTING_FILE: &str = "last_scan.json";
+/// How old a listing `--device` still trusts: DHCP may have moved devices
+/// since.
+const LISTING_MAX_AGE: u64 = 600;
+
+#[derive(Serialize, Deserialize)]
+struct Listing {
+    scanned_at: u64,
+    interface: String,
+    devices: Vec<Device>,
+}
+
+/// Numbers the devices as `--device` counts them.
+fn print_numbered(devices: &[Device]) {
+    if devices.is_empty() {
+        return;
+    }
+    println!("\nPick a device with --device N (for monitor, limit, remove, ...):");
+    for (n, d) in devices.iter().enumerate() {
+        match d.mdns_name.as_deref().or(d.hostname.as_deref()) {
+            Some(name) => println!("  {:>2}  {} ({})", n + 1, d.ip, name),
+            None => println!("  {:>2}  {}", n + 1, d.ip),
+        }
+    }
+}
+
+/// The `n`th device (counting from 1) the last scan listed. Fails when
+/// there is no such row, or when the scan is over [`LISTING_MAX_AGE`] old.
+pub fn listed_device(n: usize) -> Result<Device> {
+    let rescan = "Run `scan` to list the devices again.";
+    let listing: Option<Listing> = store::load(LISTING_FILE)?;
+    let Some(listing) = listing else {
+        return Err(anyhow!("No scan has listed devices yet. {}", rescan));
+    };
+    if unix_now().saturating_sub(listing.scanned_at) > LISTING_MAX_AGE {
+        return Err(anyhow!(
+            "The last scan of {} was {}, so its device numbers may be out of date. {}",
+            listing.interface,
+            format_age(listing.scanned_at),
+            rescan
+        ));
+    }
+    let count = listing.devices.len();
+    n.checked_sub(1)
+        .and_then(|i| listing.devices.into_iter().nth(i))
+        .ok_or_else(|| {
+            anyhow!(
+                "The last scan listed {} device(s), so there is no device {}. {}",
+                count,
+                n,
+                rescan
+            )
+        })
+}
+
+fn last_scan_file(interface: &str) -> String {
+    format!("last_scan_{}.json", interface)
+}
+
+/// The latest scan of one interface: the baseline for the next scan's diff,
+/// and what `--cached` serves.
+#[derive(Serialize, Deserialize)]
+struct SavedScan {
+    scanned_at: u64,
+    devices: Vec<Device>,
+}
+
+/// Scans saved before they were timestamped are a bare device array; they
+/// still work as a diff baseline but are never fresh enough to serve.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredScan {
+    Saved(SavedScan),
+    Legacy(Vec<Device>),
+}
+
+/// Loads the latest scan of `interface`, if there is one.
+fn load_saved_scan(interface: &str) -> Option<SavedScan> {
+    let stored: Option<StoredScan> = store::load(&last_scan_file(interface)).unwrap_or_else(|e| {
+        warn!("Ignoring previous scan results: {:#}", e);
+        None
+    });
+    match stored? {
+        StoredScan::Saved(scan) => Some(scan),
+        StoredScan::Legacy(devices) => Some(SavedScan {
+            scanned_at: 0,
+            devices,
+        }),
+    }
+}
+
+/// Renders devices as CSV, one row per device. `first_seen` comes from the
+/// device history, so it is empty for devices without a MAC.
+fn render_csv(devices: &[Device]) -> Result<String> {
+    let history: history::History = store::load(history::HISTORY_FILE)?;
+    let mut out = csv_row(&[
+        "ip",
+        "mac",
+        "vendor",
+        "hostname",
+        "first_seen",
+        "last_seen",
+        "status",
+        "rules",
+    ]);
+    out.push('\n');
+    let text = |value: Option<String>| value.unwrap_or_default();
+    for d in devices {
+        let first_seen = d
+            .mac
+            .and_then(|mac| history.get(&mac))
+            .map(|k| k.first_seen);
+        out.push_str(&csv_row(&[
+            d.ip.to_string(),
+            text(d.mac.map(|mac| mac.to_string())),
+            text(d.vendor.clone()),
+            text(d.hostname.clone()),
+            text(first_seen.map(|t| t.to_string())),
+            text(d.last_seen.map(|t| t.to_string())),
+            if d.is_stale() { "stale" } else { "up" }.to_string(),
+            text(d.rules.clone()),
+        ]));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Prints machine-readable output, or writes it to `path`.
+fn emit(contents: &str, path: Option<&Path>) -> Result<()> {
+    match path {
+        Some(path) => {
+            fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+        }
+        None => {
+            print!("{}", contents);
+            Ok(())
+        }
+    }
+}
+
+/// The targets in the saved state with their RULES column text. Run as
+/// root, the live ruleset is checked too and rules that are no longer
+/// loaded (say after `pfctl -F all`) are flagged.
+fn managed_rules() -> Vec<(ManagedTarget, String)> {
+    let state = match State::load() {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("Failed to read the managed devices: {:#}", e);
+            return Vec::new();
+        }
+    };
+    let firewall = firewall::detect();
+    let loaded = match nix::unistd::geteuid().is_root() {
+        true => firewall.loaded_rules(&SudoRunner::default()).ok(),
+        false => None,
+    };
+    state
+        .targets
+        .into_iter()
+        .map(|target| {
+            let mut summary = target.rule_summary();
+            if loaded
+                .as_deref()
+                .is_some_and(|loaded| !firewall.is_loaded(&target, loaded))
+            {
+                summary.push_str(" (not loaded)");
+            }
+            (target, summary)
+        })
+        .collect()
+}
+
+/// Fills in the `rules` of the devices that are managed and returns the
+/// managed targets that weren't found among `devices`.
+fn mark_rules(
+    devices: &mut [Device],
+    managed: Vec<(ManagedTarget, String)>,
+) -> Vec<(ManagedTarget, String)> {
+    for device in devices.iter_mut() {
+        device.rules = None;
+    }
+    let mut offline = Vec::new();
+    for (target, summary) in managed {
+        // Network targets can cover several devices
+        let mut found = false;
+        for device in devices.iter_mut().filter(|d| {
+            target.covers(d.ip.into()) || d.ipv6.iter().any(|&ip| target.covers(ip.into()))
+        }) {
+            found = true;
+            // A device can be blocked and limited at the same time
+            device.rules = Some(match device.rules.take() {
+                Some(rules) => format!("{}, {}", rules, summary),
+                None => summary.clone(),
+            });
+        }
+        if !found {
+            offline.push((target, summary));
+        }
+    }
+    offline
+}
+
+/// Fills in the `alias` of the devices an alias names, by MAC or IP. An
+/// unreadable aliases file only costs the names.
+fn mark_aliases(devices: &mut [Device]) {
+    let aliases = alias::load().unwrap_or_else(|e| {
+        warn!("Not showing aliases: {:#}", e);
+        BTreeMap::new()
+    });
+    for device in devices.iter_mut() {
+        device.alias = alias::names_for(&aliases, Some(device.ip.into()), device.mac);
+    }
+}
+
+fn print_open_ports(devices: &[Device]) {
+    let rows: Vec<[String; 2]> = devices
+        .iter()
+        .filter(|d| !d.open_ports.is_empty())
+        .map(|d| {
+            let ports: Vec<String> = d
+                .open_ports
+                .iter()
+                .map(|&port| match probe::service_name(port) {
+                    Some(service) => format!("{}/tcp ({})", port, service),
+                    None => format!("{}/tcp", port),
+                })
+                .collect();
+            [d.ip.to_string(), ports.join(", ")]
+        })
+        .collect();
+    if !rows.is_empty() {
+        println!("\nOpen ports:");
+        print_table(["IP", "PORTS"], &rows, |_| false);
+    }
+}
+
+fn print_managed_offline(offline: &[(ManagedTarget, String)]) {
+    if offline.is_empty() {
+        return;
+    }
+    let dash = || "-".to_string();
+    let rows: Vec<[String; 3]> = offline
+        .iter()
+        .map(|(target, summary)| {
+            [
+                target.ip.clone(),
+                target.mac.map_or_else(dash, |mac| mac.to_string()),
+                summary.clone(),
+            ]
+        })
+        .collect();
+    println!("\nManaged but offline:");
+    print_table(["IP", "MAC", "RULES"], &rows, |_| false);
+}
+
+/// A device in JSON output, with all of its addresses in one list: the IPv4
+/// address first, then any IPv6 ones. `ip` stays for older consumers.
+#[derive(Serialize)]
+struct JsonDevice<'a> {
+    #[serde(flatten)]
+    device: &'a Device,
+    addresses: Vec<IpAddr>,
+}
+
+impl<'a> JsonDevice<'a> {
+    fn new(device: &'a Device) -> Self {
+        let addresses = std::iter::once(IpAddr::V4(device.ip))
+            .chain(device.ipv6.iter().copied().map(IpAddr::V6))
+            .collect();
+        JsonDevice { device, addresses }
+    }
+}
+
+/// The `scan --json` document.
+fn render_json(
+    interface: &str,
+    link: Option<&WifiLink>,
+    profile: ScanProfile,
+    devices: &[Device],
+    conflicts: &[Conflict],
+    totals: &ScanTotals,
+) -> Result<String> {
+    let result = serde_json::json!({
+        "interface": interface,
+        "link": link,
+        "profile": profile,
+        "devices": devices.iter().map(JsonDevice::new).collect::<Vec<_>>(),
+        "conflicts": conflicts,
+        "summary": totals,
+    });
+    Ok(format!("{}\n", serde_json::to_string_pretty(&result)?))
+}
+
+/// The counts under the device table, and `summary` in JSON output.
+#[derive(Debug, Serialize)]
+pub struct ScanTotals {
+    pub devices: usize,
+    pub online: usize,
+    /// Listed in the ARP cache but silent during the scan
+    pub arp_only: usize,
+    /// Devices that joined since the previous scan; absent when there was
+    /// none to compare with
+    pub new: Option<usize>,
+    /// Devices wifi-kicker blocks, whether or not they are online
+    pub blocked: usize,
+    pub gateway: Option<Ipv4Addr>,
+}
+
+impl ScanTotals {
+    fn new(
+        devices: &[Device],
+        changes: Option<&diff::ScanDiff>,
+        blocked: usize,
+        gateway: Option<Ipv4Addr>,
+    ) -> Self {
+        let online = devices.iter().filter(|d| d.up).count();
+        ScanTotals {
+            devices: devices.len(),
+            online,
+            arp_only: devices.len() - online,
+            new: changes.map(|c| c.joined.len()),
+            blocked,
+            gateway,
+        }
+    }
+
+    /// E.g. "23 devices (18 online, 5 ARP-only) — 2 new since last scan,
+    /// 3 currently blocked, gateway 192.168.1.1".
+    fn line(&self) -> String {
+        let mut line = format!(
+            "{} device{} ({} online, {} ARP-only)",
+            self.devices,
+            if self.devices == 1 { "" } else { "s" },
+            self.online,
+            self.arp_only
+        );
+        let mut details = Vec::new();
+        if let Some(new) = self.new {
+            details.push(format!("{} new since last scan", new));
+        }
+        details.push(format!("{} currently blocked", self.blocked));
+        if let Some(gateway) = self.gateway {
+            details.push(format!("gateway {}", gateway));
+        }
+        line.push_str(" \u{2014} ");
+        line.push_str(&details.join(", "));
+        line
+    }
+}
+
+/// What `scan_network` found worth an exit status.
+pub struct ScanSummary {
+    /// Devices appeared that weren't in the previous scan of the interface
+    pub has_new: bool,
+    pub conflicts: Vec<Conflict>,
+    /// Devices listed after filtering
+    pub shown: usize,
+}
+
+/// Keeps the devices the history first saw in the last `window` seconds,
+/// which includes any this scan just added to it. Devices without a MAC
+/// can't be looked up and are dropped.
+fn keep_new(devices: Vec<Device>, window: u64) -> Result<Vec<Device>> {
+    let history: history::History = store::load(history::HISTORY_FILE)?;
+    let cutoff = unix_now().saturating_sub(window);
+    Ok(devices
+        .into_iter()
+        .filter(|d| {
+            d.mac
+                .and_then(|mac| history.get(&mac))
+                .is_some_and(|known| known.first_seen >= cutoff)
+        })
+        .collect())
+}
+
+/// Scans once and prints the result. With `new_within`, only devices first
+/// seen in that many seconds are listed; `output_file` gets a copy of the
+/// devices listed.
+pub async fn scan_network(
+    options: &ScanOptions<'_>,
+    format: OutputFormat,
+    output: Option<&Path>,
+    output_file: Option<&OutputFile>,
+    diff_only: bool,
+    new_within: Option<u64>,
+) -> Result<ScanSummary> {
+    interface_config(options.interface)?;
+    let link = wifi::link(options.interface);
+    if format == OutputFormat::Text {
+        print_network_header(options.interface, link.as_ref())?;
+        println!("Scan profile: {}", options.profile.label());
+    }
+
+    let previous = load_saved_scan(options.interface);
+    let fresh = |scan: &SavedScan| {
+        options.max_age.is_some_and(|max| {
+            scan.scanned_at > 0 && unix_now().saturating_sub(scan.scanned_at) <= max
+        })
+    };
+    let (devices, mut ipv6_only, changes, conflicts, cached, scanned_at) = match previous {
+        Some(scan) if fresh(&scan) => {
+            if format == OutputFormat::Text {
+                println!(
+                    "\nUsing the scan from {} (pass --refresh to rescan)",
+                    format_age(scan.scanned_at)
+                );
+            }
+            let conflicts = anomaly::find(None, &scan.devices);
+            (
+                scan.devices,
+                Vec::new(),
+                None,
+                conflicts,
+                true,
+                scan.scanned_at,
+            )
+        }
+        previous => {
+            if format == OutputFormat::Text {
+                let ifconfig = interface_config(options.interface)?;
+                let network =
+                    target_network(options.interface, &ifconfig, options.range, options.force)?;
+                println!("\nScanning subnet {} on {}...", network, options.interface);
+            }
+            let mut devices = discover(options, &mut LookupCache::default()).await?;
+            let ipv6_only = attach_ipv6(&mut devices, options.interface, options.ping_ipv6).await;
+            let conflicts =
+                anomaly::find(previous.as_ref().map(|p| p.devices.as_slice()), &devices);
+            let changes = previous.map(|previous| diff::diff(&previous.devices, &devices));
+            let scan = SavedScan {
+                scanned_at: unix_now(),
+                devices,
+            };
+            if let Err(e) = store::save(&last_scan_file(options.interface), &scan) {
+                warn!("Failed to save scan results: {:#}", e);
+            }
+            (
+                scan.devices,
+                ipv6_only,
+                changes,
+                conflicts,
+                false,
+                scan.scanned_at,
+            )
+        }
+    };
+    let mut devices = devices;
+    let gateway = devices
+        .iter()
+        .find(|d| d.role == Some(HostRole::Gateway))
+        .map(|d| d.ip);
+    let managed = managed_rules();
+    let blocked = managed
+        .iter()
+        .filter(|(t, _)| t.kind == RuleKind::Monitor)
+        .count();
+    let offline = mark_rules(&mut devices, managed);
+    mark_aliases(&mut devices);
+    let (devices, hidden) = options.filter.apply(devices);
+    let mut devices = match new_within {
+        Some(window) => keep_new(devices, window)?,
+        None => devices,
+    };
+    sort_devices(&mut devices, options.sort, options.reverse);
+    if !options.filter.is_empty() {
+        // Unmatched neighbors have nothing to filter on
+        ipv6_only.clear();
+    }
+    let listing = Listing {
+        scanned_at,
+        interface: options.interface.to_string(),
+        devices,
+    };
+    if let Err(e) = store::save(LISTING_FILE, &listing) {
+        warn!("Failed to save the device list: {:#}", e);
+    }
+    let devices = listing.devices;
+    let totals = ScanTotals::new(&devices, changes.as_ref(), blocked, gateway);
+    let summary = |conflicts| ScanSummary {
+        has_new: changes.as_ref().is_some_and(|c| !c.joined.is_empty()),
+        conflicts,
+        shown: devices.len(),
+    };
+
+    let json = || {
+        render_json(
+            options.interface,
+            link.as_ref(),
+            options.profile,
+            &devices,
+            &conflicts,
+            &totals,
+        )
+    };
+    if let Some(file) = output_file {
+        file.write(&match file.json {
+            true => json()?,
+            false => render_csv(&devices)?,
+        })?;
+        // stderr when stdout carries --json or --csv output
+        let note = format!(
+            "Wrote {} device(s) to {}",
+            devices.len(),
+            file.path.display()
+        );
+        match format {
+            OutputFormat::Text => println!("{}", note),
+            _ => eprintln!("{}", note),
+        }
+    }
+
+    match format {
+        OutputFormat::Json => {
+            emit(&json()?, output)?;
+            return Ok(summary(conflicts));
+        }
+        OutputFormat::Html => {
+            let history: history::History = store::load(history::HISTORY_FILE)?;
+            emit(
+                &report::render_html(options.interface, &devices, &history),
+                output,
+            )?;
+            if let Some(path) = output {
+                println!("Saved the report to {}", path.display());
+            }
+            return Ok(summary(conflicts));
+        }
+        OutputFormat::Csv => {
+            emit(&render_csv(&devices)?, output)?;
+            // stderr, so the CSV stays clean
+            for conflict in &conflicts {
+                eprintln!("Warning: {}", anomaly::message(conflict));
+            }
+            return Ok(summary(conflicts));
+        }
+        OutputFormat::Text => {}
+    }
+
+    if let (Some(window), true) = (new_within, devices.is_empty() && !diff_only) {
+        println!(
+            "\nNo new devices (none first seen in the last {}).",
+            history::format_span(window)
+        );
+    } else if !diff_only {
+        match new_within {
+            Some(window) => println!(
+                "\nNew devices (first seen in the last {}):",
+                history::format_span(window)
+            ),
+            None => println!("\nDiscovered devices:"),
+        }
+        print_device_table(&devices, &HashSet::new());
+        print_hidden(&options.filter, hidden);
+        print_ipv6_neighbors(&ipv6_only);
+        print_managed_offline(&offline);
+        print_open_ports(&devices);
+        println!("\n{}", totals.line());
+        print_numbered(&devices);
+    }
+    if !conflicts.is_empty() {
+        println!("\nWarnings:");
+        for conflict in &conflicts {
+            println!("  {}", anomaly::message(conflict));
+        }
+    }
+    print_link_suggestions(&devices);
+
+    if cached {
+        return Ok(summary(conflicts));
+    }
+    println!("\nChanges since last scan:");
+    match &changes {
+        Some(changes) => diff::print_diff(changes),
+        None => println!(
+            "No previous scan of {}; saved this one as the baseline.",
+            options.interface
+        ),
+    }
+
+    Ok(summary(conflicts))
+}
+
+/// Offers to link private MACs that look like earlier MACs of the same
+/// device. Best-effort, like the history itself.
+fn print_link_suggestions(devices: &[Device]) {
+    let suggestions = match history::link_suggestions(devices) {
+        Ok(suggestions) => suggestions,
+        Err(e) => {
+            warn!("Failed to read the device history: {:#}", e);
+            return;
+        }
+    };
+    if suggestions.is_empty() {
+        return;
+    }
+    println!("\nPossibly the same device under a new private MAC:");
+    for s in &suggestions {
+        println!(
+            "  {} ({}) was seen before as {}; `devices link {} {}` merges their history",
+            s.new, s.name, s.old, s.old, s.new
+        );
+    }
+}
+
+/// `scan --watch --auto-block`: block devices the device history has never
+/// seen, as `monitor` would.
+pub struct AutoBlock {
+    /// Blocks run on the blocking pool, so a hung pfctl doesn't stall the
+    /// watch loop's worker
+    pub settings: RunSettings,
+    /// Devices never to block, besides the gateway and this host
+    pub except: Vec<AliasTarget>,
+    /// Only report what would be blocked
+    pub report_only: bool,
+}
+
+/// How many auto-block actions stay listed under the watch table.
+const AUTO_BLOCK_LOG: usize = 10;
+
+impl AutoBlock {
+    fn excepts(&self, device: &Device) -> bool {
+        self.except.iter().any(|target| match target {
+            AliasTarget::Ip(ip) => *ip == std::net::IpAddr::V4(device.ip),
+            AliasTarget::Mac(mac) => device.mac == Some(*mac),
+        })
+    }
+
+    /// Blocks (or reports) each device whose MAC isn't in `known`, adding
+    /// it there so it is dealt with once. Returns a line per action, each
+    /// also posted as a notification.
+    async fn run(&self, devices: &[Device], known: &mut HashSet<MacAddr>) -> Vec<String> {
+        let mut actions = Vec::new();
+        for device in devices {
+            let Some(mac) = device.mac else {
+                continue;
+            };
+            if !known.insert(mac) {
+                continue;
+            }
+            let description = diff::describe(device);
+            if let Some(role) = device.role {
+                info!("Not auto-blocking {} ({})", description, role.label());
+                continue;
+            }
+            if self.excepts(device) {
+                info!("Not auto-blocking {} (excepted)", description);
+                continue;
+            }
+            if device
+                .rules
+                .as_deref()
+                .is_some_and(|r| r.contains("blocked"))
+            {
+                continue;
+            }
+            let action = if self.report_only {
+                format!("Would block {}", description)
+            } else {
+                let ips = [device.ip.to_string()];
+                let blocked = self
+                    .settings
+                    .run_blocking(move |ctx| crate::monitor(ctx, &ips, false))
+                    .await;
+                match blocked {
+                    Ok(()) => format!("Blocked {}", description),
+                    Err(e) => format!("Failed to block {}: {:#}", description, e),
+                }
+            };
+            info!("{}", action);
+            if let Err(e) = notify::notify("New device auto-blocked", &action) {
+                warn!("Failed to post a notification: {:#}", e);
+            }
+            actions.push(action);
+        }
+        actions
+    }
+}
+
+/// Re-scans every `interval`, redrawing the table and highlighting devices
+/// that are new or changed since the previous pass, until Ctrl-C. With
+/// `auto_block`, devices missing from the device history when the watch
+/// started are blocked as they appear; an empty history is seeded by the
+/// first pass instead, so a fresh install doesn't block everything.
+pub async fn watch(
+    options: &ScanOptions<'_>,
+    interval: Duration,
+    auto_block: Option<AutoBlock>,
+) -> Result<()> {
+    interface_config(options.interface)?;
+    let mut cache = LookupCache::default();
+    let mut previous: Option<HashMap<Ipv4Addr, Device>> = None;
+    let history: history::History = store::load(history::HISTORY_FILE)?;
+    let mut seed = history.is_empty();
+    let mut known: HashSet<MacAddr> = history.into_keys().collect();
+    let mut actions: Vec<String> = Vec::new();
+
+    loop {
+        let devices = tokio::select! {
+            devices = discover(options, &mut cache) => devices?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let mut devices = devices;
+        let mut offline = mark_rules(&mut devices, managed_rules());
+        mark_aliases(&mut devices);
+        if let Some(auto_block) = &auto_block {
+            if seed {
+                known.extend(devices.iter().filter_map(|d| d.mac));
+                seed = false;
+            }
+            let new = auto_block.run(&devices, &mut known).await;
+            if !new.is_empty() {
+                if !auto_block.report_only {
+                    // Show the new blocks in the table right away
+                    offline = mark_rules(&mut devices, managed_rules());
+                }
+                actions.extend(new);
+                let excess = actions.len().saturating_sub(AUTO_BLOCK_LOG);
+                actions.drain(..excess);
+            }
+        }
+        let (mut devices, hidden) = options.filter.apply(devices);
+        sort_devices(&mut devices, options.sort, options.reverse);
+
+        let changed: HashSet<Ipv4Addr> = match &previous {
+            Some(previous) => devices
+                .iter()
+                .filter(|d| {
+                    previous
+                        .get(&d.ip)
+                        .is_none_or(|p| p.mac != d.mac || p.up != d.up)
+                })
+                .map(|d| d.ip)
+                .collect(),
+            None => HashSet::new(),
+        };
+
+        // Clear the screen and move the cursor home before redrawing
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "Watching {} every {}s (Ctrl-C to stop)\n",
+            options.interface,
+            interval.as_secs()
+        );
+        print_device_table(&devices, &changed);
+        print_hidden(&options.filter, hidden);
+        print_managed_offline(&offline);
+        if let Some(auto_block) = &auto_block {
+            println!(
+                "\n{}",
+                match auto_block.report_only {
+                    true => "Auto-block (dry run): new devices are only reported",
+                    false => "Auto-block: new devices are blocked",
+                }
+            );
+            for action in &actions {
+                println!("  {}", action);
+            }
+        }
+
+        let current: HashMap<Ipv4Addr, Device> = devices.into_iter().map(|d| (d.ip, d)).collect();
+        if let Some(previous) = &previous {
+            let mut gone: Vec<&Ipv4Addr> = previous
+                .keys()
+                .filter(|ip| !current.contains_key(ip))
+                .collect();
+            gone.sort();
+            if !gone.is_empty() {
+                let gone: Vec<String> = gone.iter().map(|ip| ip.to_string()).collect();
+                println!("\nGone since last pass: {}", gone.join(", "));
+            }
+        }
+        previous = Some(current);
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Rescans every `interval` and posts a desktop notification for each
+/// device that joined, and unless `join_only` left, since the previous
+/// pass, until Ctrl-C. The first pass only sets the baseline, which is kept
+/// in memory.
+pub async fn notify_changes(
+    options: &ScanOptions<'_>,
+    interval: Duration,
+    join_only: bool,
+) -> Result<()> {
+    interface_config(options.interface)?;
+    let mut cache = LookupCache::default();
+    let mut previous: Option<Vec<Device>> = None;
+    println!(
+        "Watching {} every {}s for devices {} (Ctrl-C to stop)",
+        options.interface,
+        interval.as_secs(),
+        if join_only {
+            "joining"
+        } else {
+            "joining or leaving"
+        }
+    );
+
+    loop {
+        let devices = tokio::select! {
+            devices = discover(options, &mut cache) => devices?,
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        // Devices linger in the ARP cache for a while after they leave, so
+        // only the ones that answered count
+        let (devices, _) = options
+            .filter
+            .apply(devices.into_iter().filter(|d| d.up).collect());
+
+        match &previous {
+            Some(previous) => {
+                let changes = diff::diff(previous, &devices);
+                let mut events: Vec<(&str, &Device)> =
+                    changes.joined.iter().map(|d| ("joined", d)).collect();
+                if !join_only {
+                    events.extend(changes.departed.iter().map(|d| ("left", d)));
+                }
+                for (event, device) in events {
+                    let description = diff::describe(device);
+                    println!("{:<7} {}", event, description);
+                    let title = format!("Device {} {}", event, options.interface);
+                    if let Err(e) = notify::notify(&title, &description) {
+                        warn!("Failed to post a notification: {:#}", e);
+                    }
+                }
+            }
+            None => println!("Baseline: {} device(s) online", devices.len()),
+        }
+        previous = Some(devices);
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_output_parses_back_into_devices() {
+        let mut router = Device::new(Ipv4Addr::new(192, 168, 1, 1), DeviceSource::Nmap);
+        router.mac = Some("a4:2b:b0:11:22:33".parse().unwrap());
+        router.vendor = Some("TP-Link".to_string());
+        router.hostname = Some("router.lan".to_string());
+        router.role = Some(HostRole::Gateway);
+        let mut phone = Device::new(Ipv4Addr::new(192, 168, 1, 23), DeviceSource::Arp);
+        phone.ipv6 = vec!["fe80::7810:22ff:fe33:4455".parse().unwrap()];
+        let devices = vec![router, phone];
+        let totals = ScanTotals::new(&devices, None, 0, Some(devices[0].ip));
+
+        let output =
+            render_json("en0", None, ScanProfile::default(), &devices, &[], &totals).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+        let parsed: Vec<Device> = serde_json::from_value(document["devices"].clone()).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].ip, devices[0].ip);
+        assert_eq!(parsed[0].mac, devices[0].mac);
+        assert_eq!(parsed[0].vendor.as_deref(), Some("TP-Link"));
+        assert_eq!(parsed[0].hostname.as_deref(), Some("router.lan"));
+        assert!(parsed[0].up);
+        assert!(!parsed[1].up);
+        assert_eq!(parsed[1].ipv6, devices[1].ipv6);
+        assert_eq!(
+            document["devices"][1]["addresses"],
+            serde_json::json!(["192.168.1.23", "fe80::7810:22ff:fe33:4455"])
+        );
+        assert_eq!(document["summary"]["online"], 1);
+    }
+
+    #[test]
+    fn local_subnet_converts_the_netmask_to_a_prefix() {
+        let subnet = |inet: &str| {
+            let ifconfig = format!(
+                "en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500\n\
+                 \tether a4:83:e7:12:34:56\n\
+                 \t{}\n\
+                 \tstatus: active\n",
+                inet
+            );
+            subnet_of("en0", &ifconfig).map(|n| n.to_string())
+        };
+        let cases = [
+            (
+                "inet 10.1.2.3 netmask 0xff000000 broadcast 10.255.255.255",
+                "10.0.0.0/8",
+            ),
+            (
+                "inet 172.16.5.4 netmask 0xffff0000 broadcast 172.16.255.255",
+                "172.16.0.0/16",
+            ),
+            (
+                "inet 172.16.21.4 netmask 0xfffff000 broadcast 172.16.31.255",
+                "172.16.16.0/20",
+            ),
+            (
+                "inet 192.168.3.77 netmask 0xfffffe00 broadcast 192.168.3.255",
+                "192.168.2.0/23",
+            ),
+            (
+                "inet 192.168.1.23 netmask 0xffffff00 broadcast 192.168.1.255",
+                "192.168.1.0/24",
+            ),
+            (
+                "inet 192.168.1.6 netmask 0xfffffffc broadcast 192.168.1.7",
+                "192.168.1.4/30",
+            ),
+            (
+                "inet 10.8.0.2 --> 10.8.0.1 netmask 0xffffffff",
+                "10.8.0.2/32",
+            ),
+            (
+                "inet 192.168.8.10  netmask 255.255.252.0  broadcast 192.168.11.255",
+                "192.168.8.0/22",
+            ),
+        ];
+        for (inet, expected) in cases {
+            assert_eq!(subnet(inet).unwrap(), expected, "{}", inet);
+        }
+    }
+
+    #[test]
+    fn local_subnet_needs_an_ipv4_address() {
+        let ifconfig = "utun0: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1380\n\
+                        \tinet6 fe80::a1b2:c3d4:e5f6:789%utun0 prefixlen 64 scopeid 0xf\n";
+        let error = subnet_of("utun0", ifconfig).unwrap_err();
+        assert_eq!(error.to_string(), "Interface utun0 has no IPv4 address");
+        // Not a run of leading ones
+        let ifconfig = "en0: flags=8863<UP> mtu 1500\n\tinet 192.168.1.23 netmask 0xff00ff00\n";
+        assert!(subnet_of("en0", ifconfig).is_err());
+    }
+}
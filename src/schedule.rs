@@ -0,0 +1,351 @@
+//! Recurring block windows. Schedules live in the state file; a launchd job
+//! runs `schedule-tick` at every window boundary, which blocks the devices
+//! whose window is open and unblocks the rest.
+
+use crate::state::{ManagedTarget, RuleKind, State};
+use crate::{store, RunContext};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use log::info;
+use quick_xml::escape::escape;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+const LAUNCHD_LABEL: &str = "com.wifi-kicker.schedule";
+/// A LaunchDaemon rather than a LaunchAgent: the rules can only be changed
+/// as root.
+const LAUNCHD_PLIST: &str = "/Library/LaunchDaemons/com.wifi-kicker.schedule.plist";
+
+/// A wall-clock time, written `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid time {:?}, expected HH:MM (24-hour)", s);
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        if minute.len() != 2 {
+            return Err(invalid());
+        }
+        let hour: u8 = hour.parse().map_err(|_| invalid())?;
+        let minute: u8 = minute.parse().map_err(|_| invalid())?;
+        if hour > 23 || minute > 59 {
+            return Err(invalid());
+        }
+        Ok(TimeOfDay { hour, minute })
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+        Weekday::Sat,
+        Weekday::Sun,
+    ];
+
+    /// ISO number, 1 (Monday) to 7 (Sunday), as printed by `date +%u`.
+    fn iso(self) -> u8 {
+        self as u8 + 1
+    }
+
+    fn from_iso(n: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(n).checked_sub(1)?).copied()
+    }
+
+    fn previous(self) -> Self {
+        Self::ALL[(self as usize + 6) % 7]
+    }
+}
+
+/// Blocks `ip` from `block_from` until `block_until` on each of `days`. A
+/// window that ends before it starts runs past midnight, into the next day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub ip: String,
+    pub block_from: TimeOfDay,
+    pub block_until: TimeOfDay,
+    pub days: Vec<Weekday>,
+}
+
+impl Schedule {
+    pub fn new(ip: &str, block_from: TimeOfDay, block_until: TimeOfDay, days: &[Weekday]) -> Self {
+        Schedule {
+            ip: ip.to_string(),
+            block_from,
+            block_until,
+            days: if days.is_empty() {
+                Weekday::ALL.to_vec()
+            } else {
+                days.to_vec()
+            },
+        }
+    }
+
+    /// Whether the window is open at `time` on `day`.
+    pub fn is_active(&self, day: Weekday, time: TimeOfDay) -> bool {
+        if self.block_from <= self.block_until {
+            self.days.contains(&day) && self.block_from <= time && time < self.block_until
+        } else {
+            (self.days.contains(&day) && time >= self.block_from)
+                || (self.days.contains(&day.previous()) && time < self.block_until)
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let days: Vec<String> = self
+            .days
+            .iter()
+            .map(|d| format!("{:?}", d).to_lowercase())
+            .collect();
+        write!(
+            f,
+            "{} blocked {}-{} on {}",
+            self.ip,
+            self.block_from,
+            self.block_until,
+            days.join(",")
+        )
+    }
+}
+
+/// The local weekday and time, from `date`.
+fn local_now() -> Result<(Weekday, TimeOfDay)> {
+    let output = Command::new("date")
+        .arg("+%u %H:%M")
+        .output()
+        .context("Failed to read the local time")?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let (day, time) = output
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("Unexpected date output: {}", output))?;
+    let day = day
+        .parse()
+        .ok()
+        .and_then(Weekday::from_iso)
+        .ok_or_else(|| anyhow!("Unexpected weekday from date: {}", day))?;
+    Ok((day, time.parse().map_err(|e| anyhow!("{}", e))?))
+}
+
+/// Adds block rules for every scheduled device whose window is open and
+/// drops them for the others. Returns whether `state` changed. The block
+/// rule of a scheduled device belongs to the schedule, so it is removed when
+/// the window closes even if it was added by hand.
+pub fn enforce(state: &mut State) -> Result<bool> {
+    let (day, time) = local_now()?;
+    let mut changed = false;
+    for schedule in state.schedules.clone() {
+        let blocked = state
+            .targets
+            .iter()
+            .any(|t| t.ip == schedule.ip && t.kind == RuleKind::Monitor);
+        match (schedule.is_active(day, time), blocked) {
+            (true, false) => {
+                info!("Schedule window open, blocking {}", schedule.ip);
+                state.upsert(ManagedTarget::new(
+                    &schedule.ip,
+                    None,
+                    RuleKind::Monitor,
+                    false,
+                ));
+                changed = true;
+            }
+            (false, true) => {
+                info!("Schedule window closed, unblocking {}", schedule.ip);
+                state.take(&schedule.ip, Some(RuleKind::Monitor));
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+    Ok(changed)
+}
+
+/// Enforces the saved schedules and reloads the rules if that changed them.
+pub fn tick(ctx: &RunContext) -> Result<()> {
+    ctx.check_root()?;
+    let mut state = State::load()?;
+    if enforce(&mut state)? {
+        ctx.firewall.apply(ctx, &state.targets)?;
+        ctx.save(&state)?;
+    }
+    Ok(())
+}
+
+/// Renders a launchd job that runs `schedule-tick` at the start and end of
+/// every window. Windows past midnight end on the following day. The job
+/// gets `home` as its HOME, so it reads the state kept there rather than
+/// root's.
+pub fn render_plist(binary: &str, home: &str, schedules: &[Schedule]) -> String {
+    let mut intervals = Vec::new();
+    for schedule in schedules {
+        for day in &schedule.days {
+            let end_day = if schedule.block_until < schedule.block_from {
+                Weekday::ALL[day.iso() as usize % 7]
+            } else {
+                *day
+            };
+            for (day, time) in [(*day, schedule.block_from), (end_day, schedule.block_until)] {
+                // launchd counts weekdays from Sunday = 0
+                let entry = format!(
+                    "        <dict>\n\
+                     \x20           <key>Weekday</key><integer>{}</integer>\n\
+                     \x20           <key>Hour</key><integer>{}</integer>\n\
+                     \x20           <key>Minute</key><integer>{}</integer>\n\
+                     \x20       </dict>\n",
+                    day.iso() % 7,
+                    time.hour,
+                    time.minute
+                );
+                if !intervals.contains(&entry) {
+                    intervals.push(entry);
+                }
+            }
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \x20   <key>Label</key>\n\
+         \x20   <string>{label}</string>\n\
+         \x20   <key>ProgramArguments</key>\n\
+         \x20   <array>\n\
+         \x20       <string>{binary}</string>\n\
+         \x20       <string>schedule-tick</string>\n\
+         \x20   </array>\n\
+         \x20   <key>EnvironmentVariables</key>\n\
+         \x20   <dict>\n\
+         \x20       <key>HOME</key>\n\
+         \x20       <string>{home}</string>\n\
+         \x20   </dict>\n\
+         \x20   <key>RunAtLoad</key>\n\
+         \x20   <true/>\n\
+         \x20   <key>StartCalendarInterval</key>\n\
+         \x20   <array>\n\
+         {intervals}\
+         \x20   </array>\n\
+         </dict>\n\
+         </plist>\n",
+        label = LAUNCHD_LABEL,
+        binary = escape(binary),
+        home = escape(home),
+        intervals = intervals.concat()
+    )
+}
+
+/// Removes the launchd job, if one is installed.
+pub fn uninstall(ctx: &RunContext) -> Result<()> {
+    if !Path::new(LAUNCHD_PLIST).exists() {
+        return Ok(());
+    }
+    let _ = ctx.runner.run(
+        "launchctl",
+        &["bootout", &format!("system/{}", LAUNCHD_LABEL)],
+    );
+    ctx.runner.run("rm", &["-f", LAUNCHD_PLIST])?;
+    Ok(())
+}
+
+/// Installs (or, with no schedules left, removes) the launchd job. On other
+/// platforms `schedule-tick` has to be run periodically some other way.
+pub fn install(ctx: &RunContext, schedules: &[Schedule]) -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        info!(
+            "Run `HOME={} rust-wifi-kicker schedule-tick` every minute (e.g. from root's crontab) to enforce schedules",
+            store::home()?.display()
+        );
+        return Ok(());
+    }
+    let domain_target = format!("system/{}", LAUNCHD_LABEL);
+    // Fails harmlessly when the job isn't loaded yet
+    let _ = ctx.runner.run("launchctl", &["bootout", &domain_target]);
+    if schedules.is_empty() {
+        ctx.runner.run("rm", &["-f", LAUNCHD_PLIST])?;
+        return Ok(());
+    }
+
+    let binary = std::env::current_exe().context("Failed to locate the running binary")?;
+    ctx.install(
+        "com.wifi-kicker.schedule.plist",
+        &render_plist(
+            &binary.to_string_lossy(),
+            &store::home()?.to_string_lossy(),
+            schedules,
+        ),
+        LAUNCHD_PLIST,
+    )?;
+    ctx.runner
+        .run("launchctl", &["bootstrap", "system", LAUNCHD_PLIST])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plist_runs_the_tick_with_the_callers_home() {
+        let schedule = Schedule::new(
+            "192.168.1.100",
+            "22:00".parse().unwrap(),
+            "07:00".parse().unwrap(),
+            &[Weekday::Sun],
+        );
+        let plist = render_plist("/usr/local/bin/rust-wifi-kicker", "/Users/a&b", &[schedule]);
+        assert!(plist.contains(
+            "<key>EnvironmentVariables</key>\n    <dict>\n        <key>HOME</key>\n        <string>/Users/a&amp;b</string>"
+        ));
+        // Starts Sunday 22:00, ends Monday 07:00
+        assert!(plist.contains("<key>Weekday</key><integer>0</integer>\n            <key>Hour</key><integer>22</integer>"));
+        assert!(plist.contains("<key>Weekday</key><integer>1</integer>\n            <key>Hour</key><integer>7</integer>"));
+    }
+}
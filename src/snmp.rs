@@ -0,0 +1,332 @@
+//! `scan --snmp`: the router's own ARP table, read over SNMP v2c. It covers
+//! devices on other bands or behind client isolation, which never show up
+//! in this machine's ARP cache.
+
+use crate::device::{Device, DeviceSource};
+use crate::mac::MacAddr;
+use crate::oui;
+use anyhow::{anyhow, Context, Result};
+use snmp2::{AsyncSession, Oid, Value, Varbinds};
+use std::net::Ipv4Addr;
+use std::time::Duration;
+use tokio::time::timeout;
+
+pub const DEFAULT_COMMUNITY: &str = "public";
+const SNMP_PORT: u16 = 161;
+/// Routers that answer SNMP at all answer quickly
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_REPETITIONS: u32 = 32;
+/// Ends walks of agents that never report the end of a table
+const MAX_REQUESTS: usize = 64;
+
+/// The router to ask, and the community string it accepts.
+pub struct SnmpRouter {
+    pub address: Ipv4Addr,
+    pub community: String,
+}
+
+/// The two MIB tables mapping IPs to MACs.
+#[derive(Clone, Copy)]
+enum Table {
+    /// ipNetToMediaPhysAddress (RFC 1213), indexed by ifIndex and the
+    /// four bytes of the address
+    NetToMedia,
+    /// ipNetToPhysicalPhysAddress (RFC 4293), indexed by ifIndex, address
+    /// type (1 for IPv4), length and the address bytes
+    NetToPhysical,
+}
+
+impl Table {
+    fn column(self) -> &'static [u64] {
+        match self {
+            Table::NetToMedia => &[1, 3, 6, 1, 2, 1, 4, 22, 1, 2],
+            Table::NetToPhysical => &[1, 3, 6, 1, 2, 1, 4, 35, 1, 4],
+        }
+    }
+
+    /// The IPv4 address in a row's index, the part of its OID after the
+    /// column's.
+    fn address(self, index: &[u64]) -> Option<Ipv4Addr> {
+        let bytes = match (self, index) {
+            (Table::NetToMedia, [_, bytes @ ..]) => bytes,
+            (Table::NetToPhysical, [_, 1, 4, bytes @ ..]) => bytes,
+            _ => return None,
+        };
+        let octets: Vec<u8> = bytes
+            .iter()
+            .map(|&b| u8::try_from(b).ok())
+            .collect::<Option<_>>()?;
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        Some(Ipv4Addr::from(octets))
+    }
+}
+
+/// One row of `table`: the IP in the OID's index and the MAC in the value.
+/// Incomplete entries (empty or all-zero MACs) are skipped.
+fn parse_row(table: Table, oid: &Oid, value: &Value) -> Option<(Ipv4Addr, MacAddr)> {
+    let arcs: Vec<u64> = oid.iter()?.collect();
+    let ip = table.address(arcs.strip_prefix(table.column())?)?;
+    let Value::OctetString(bytes) = value else {
+        return None;
+    };
+    let mac = MacAddr(<[u8; 6]>::try_from(*bytes).ok()?);
+    (mac.0 != [0; 6]).then_some((ip, mac))
+}
+
+/// Takes the rows of `table` from the answer to a GETBULK request for the
+/// OIDs after `next`. Also returns the OID to continue from, or `None` once
+/// the answer leaves the table.
+fn walk_step(
+    table: Table,
+    column: &Oid,
+    next: &Oid,
+    varbinds: Varbinds,
+    rows: &mut Vec<(Ipv4Addr, MacAddr)>,
+) -> Option<Oid<'static>> {
+    let mut last = None;
+    for (oid, value) in varbinds {
+        if matches!(value, Value::EndOfMibView) || !oid.starts_with(column) {
+            return None;
+        }
+        rows.extend(parse_row(table, &oid, &value));
+        last = Some(oid.to_owned());
+    }
+    // An agent repeating itself would otherwise loop forever
+    last.filter(|oid| oid != next)
+}
+
+/// Walks `table` with GETBULK requests until the agent's answers leave it.
+async fn walk(session: &mut AsyncSession, table: Table) -> Result<Vec<(Ipv4Addr, MacAddr)>> {
+    let column = Oid::from(table.column()).map_err(|e| anyhow!("Invalid OID: {:?}", e))?;
+    let mut next = column.clone();
+    let mut rows = Vec::new();
+    for _ in 0..MAX_REQUESTS {
+        let pdu = timeout(
+            REQUEST_TIMEOUT,
+            session.getbulk(&[&next], 0, MAX_REPETITIONS),
+        )
+        .await
+        .map_err(|_| anyhow!("no answer within {}s", REQUEST_TIMEOUT.as_secs()))??;
+        match walk_step(table, &column, &next, pdu.varbinds, &mut rows) {
+            Some(oid) => next = oid,
+            None => break,
+        }
+    }
+    Ok(rows)
+}
+
+/// Reads the router's IPv4 ARP entries, trying the older ipNetToMediaTable
+/// first and the ipNetToPhysicalTable when that is empty.
+pub async fn arp_table(router: &SnmpRouter) -> Result<Vec<(Ipv4Addr, MacAddr)>> {
+    let failed = || {
+        format!(
+            "Router {} didn't answer SNMP (is it enabled, with community {:?}?)",
+            router.address, router.community
+        )
+    };
+    let mut session =
+        AsyncSession::new_v2c((router.address, SNMP_PORT), router.community.as_bytes(), 0)
+            .await
+            .with_context(failed)?;
+    let rows = walk(&mut session, Table::NetToMedia)
+        .await
+        .with_context(failed)?;
+    if !rows.is_empty() {
+        return Ok(rows);
+    }
+    walk(&mut session, Table::NetToPhysical)
+        .await
+        .with_context(failed)
+}
+
+/// Adds the router's entries to `devices`: the MAC of devices found without
+/// one, and devices not found at all, sourced "snmp". A MAC differing from
+/// this machine's view is left alone, since extenders answer ARP for the
+/// devices behind them.
+pub fn merge(devices: &mut Vec<Device>, entries: Vec<(Ipv4Addr, MacAddr)>) {
+    for (ip, mac) in entries {
+        match devices.iter_mut().find(|d| d.ip == ip) {
+            Some(device) if device.mac.is_none() => {
+                device.mac = Some(mac);
+                device.vendor = oui::lookup_vendor(&mac);
+            }
+            Some(_) => {}
+            None => devices.push(Device {
+                mac: Some(mac),
+                vendor: oui::lookup_vendor(&mac),
+                ..Device::new(ip, DeviceSource::Snmp)
+            }),
+        }
+    }
+    devices.sort_by_key(|d| d.ip);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snmp2::Pdu;
+
+    /// A BER type-length-value.
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            len @ 0..=127 => out.push(len as u8),
+            len @ 128..=255 => out.extend([0x81, len as u8]),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    fn oid(arcs: &[u64]) -> Vec<u8> {
+        let mut content = vec![(arcs[0] * 40 + arcs[1]) as u8];
+        for &arc in &arcs[2..] {
+            let mut bytes = vec![(arc & 0x7f) as u8];
+            let mut rest = arc >> 7;
+            while rest > 0 {
+                bytes.push((rest & 0x7f) as u8 | 0x80);
+                rest >>= 7;
+            }
+            content.extend(bytes.iter().rev());
+        }
+        tlv(0x06, &content)
+    }
+
+    fn row(table: Table, index: &[u64], value: Vec<u8>) -> (Vec<u64>, Vec<u8>) {
+        ([table.column(), index].concat(), value)
+    }
+
+    fn mac(bytes: [u8; 6]) -> Vec<u8> {
+        tlv(0x04, &bytes)
+    }
+
+    /// The encoded v2c GetResponse carrying `rows`, as a router sends it.
+    fn response(rows: &[(Vec<u64>, Vec<u8>)]) -> Vec<u8> {
+        let varbinds: Vec<u8> = rows
+            .iter()
+            .flat_map(|(arcs, value)| tlv(0x30, &[oid(arcs), value.clone()].concat()))
+            .collect();
+        let pdu = [
+            tlv(0x02, &[0x2a]),
+            tlv(0x02, &[0]),
+            tlv(0x02, &[0]),
+            tlv(0x30, &varbinds),
+        ]
+        .concat();
+        tlv(
+            0x30,
+            &[tlv(0x02, &[1]), tlv(0x04, b"public"), tlv(0xa2, &pdu)].concat(),
+        )
+    }
+
+    /// Runs one step of a walk of `table` that asked for the OIDs after
+    /// `next` and got `packet` back.
+    fn step(
+        table: Table,
+        next: &[u64],
+        packet: &[u8],
+    ) -> (Vec<(Ipv4Addr, MacAddr)>, Option<Vec<u64>>) {
+        let column = Oid::from(table.column()).unwrap();
+        let next = Oid::from(next).unwrap();
+        let pdu = Pdu::from_bytes(packet).unwrap();
+        let mut rows = Vec::new();
+        let next = walk_step(table, &column, &next, pdu.varbinds, &mut rows);
+        (rows, next.map(|oid| oid.iter().unwrap().collect()))
+    }
+
+    const ROUTER_MAC: [u8; 6] = [0xa4, 0x2b, 0xb0, 0x11, 0x22, 0x33];
+    const PHONE_MAC: [u8; 6] = [0x7a, 0x10, 0x22, 0x33, 0x44, 0x55];
+
+    #[test]
+    fn walk_stops_where_the_table_ends() {
+        // The last varbind is the next column, ipNetToMediaNetAddress
+        let packet = response(&[
+            row(Table::NetToMedia, &[2, 192, 168, 1, 1], mac(ROUTER_MAC)),
+            row(Table::NetToMedia, &[2, 192, 168, 1, 23], mac(PHONE_MAC)),
+            // Incomplete entry
+            row(Table::NetToMedia, &[2, 192, 168, 1, 40], mac([0; 6])),
+            (
+                vec![1, 3, 6, 1, 2, 1, 4, 22, 1, 3, 2, 192, 168, 1, 1],
+                tlv(0x40, &[192, 168, 1, 1]),
+            ),
+        ]);
+        let (rows, next) = step(Table::NetToMedia, Table::NetToMedia.column(), &packet);
+        assert_eq!(
+            rows,
+            [
+                (Ipv4Addr::new(192, 168, 1, 1), MacAddr(ROUTER_MAC)),
+                (Ipv4Addr::new(192, 168, 1, 23), MacAddr(PHONE_MAC)),
+            ]
+        );
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn walk_continues_after_a_full_answer() {
+        let last = [2, 192, 168, 1, 23];
+        let packet = response(&[
+            row(Table::NetToMedia, &[2, 192, 168, 1, 1], mac(ROUTER_MAC)),
+            row(Table::NetToMedia, &last, mac(PHONE_MAC)),
+        ]);
+        let (rows, next) = step(Table::NetToMedia, Table::NetToMedia.column(), &packet);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(next, Some([Table::NetToMedia.column(), &last].concat()));
+
+        // An agent that answers with the OID asked about again is stuck
+        let again = response(&[row(Table::NetToMedia, &last, mac(PHONE_MAC))]);
+        let asked = [Table::NetToMedia.column(), &last].concat();
+        assert_eq!(step(Table::NetToMedia, &asked, &again).1, None);
+    }
+
+    #[test]
+    fn walk_stops_at_the_end_of_the_mib() {
+        let packet = response(&[
+            row(
+                Table::NetToPhysical,
+                &[3, 1, 4, 10, 0, 0, 5],
+                mac(PHONE_MAC),
+            ),
+            (
+                vec![1, 3, 6, 1, 2, 1, 4, 35, 1, 4, 3, 1, 4, 10, 0, 0, 5],
+                tlv(0x82, &[]),
+            ),
+        ]);
+        let (rows, next) = step(Table::NetToPhysical, Table::NetToPhysical.column(), &packet);
+        assert_eq!(rows, [(Ipv4Addr::new(10, 0, 0, 5), MacAddr(PHONE_MAC))]);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn ipv6_rows_of_the_physical_table_are_skipped() {
+        let mut ipv6 = vec![3, 2, 16, 0xfe, 0x80];
+        ipv6.extend([0; 14]);
+        let packet = response(&[
+            row(Table::NetToPhysical, &ipv6, mac(PHONE_MAC)),
+            row(
+                Table::NetToPhysical,
+                &[3, 1, 4, 10, 0, 0, 1],
+                mac(ROUTER_MAC),
+            ),
+        ]);
+        let (rows, next) = step(Table::NetToPhysical, Table::NetToPhysical.column(), &packet);
+        assert_eq!(rows, [(Ipv4Addr::new(10, 0, 0, 1), MacAddr(ROUTER_MAC))]);
+        assert!(next.is_some());
+    }
+
+    #[test]
+    fn rows_without_a_mac_are_skipped() {
+        let packet = response(&[
+            // A MAC in the wrong type, and one of the wrong length
+            row(Table::NetToMedia, &[2, 192, 168, 1, 1], tlv(0x02, &[1])),
+            row(
+                Table::NetToMedia,
+                &[2, 192, 168, 1, 2],
+                tlv(0x04, &[0xa4, 0x2b]),
+            ),
+            // An address byte out of range
+            row(Table::NetToMedia, &[2, 192, 168, 1, 300], mac(ROUTER_MAC)),
+        ]);
+        let (rows, _) = step(Table::NetToMedia, Table::NetToMedia.column(), &packet);
+        assert_eq!(rows, []);
+    }
+}
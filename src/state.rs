@@ -0,0 +1,164 @@
+//! Bookkeeping for the devices this tool manages, so the full PF ruleset can
+//! be regenerated whenever one of them changes.
+
+use crate::device::unix_now;
+use crate::mac::MacAddr;
+use crate::schedule::Schedule;
+use crate::store;
+use anyhow::Result;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// Managed targets, in the data directory.
+const STATE_FILE: &str = "state.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    /// All traffic to and from the device is blocked
+    Monitor,
+    /// Traffic is shaped through dummynet pipes
+    Limit,
+}
+
+impl RuleKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            RuleKind::Monitor => "monitor",
+            RuleKind::Limit => "limit",
+        }
+    }
+}
+
+/// Address family of a target, which selects `inet` or `inet6` rules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Family {
+    #[default]
+    Inet,
+    Inet6,
+}
+
+impl Family {
+    /// The family of a target address or network as stored in `ip`.
+    pub fn of(ip: &str) -> Self {
+        if ip.contains(':') {
+            Family::Inet6
+        } else {
+            Family::Inet
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManagedTarget {
+    pub ip: String,
+    /// Recorded so rules regenerate for the right family; state files from
+    /// before IPv6 support are all IPv4
+    #[serde(default)]
+    pub family: Family,
+    pub mac: Option<MacAddr>,
+    pub kind: RuleKind,
+    /// Upload cap in KB/s and the dummynet pipe enforcing it
+    pub upload: Option<u32>,
+    pub upload_pipe: Option<u32>,
+    /// Download cap in KB/s and the dummynet pipe enforcing it
+    pub download: Option<u32>,
+    pub download_pipe: Option<u32>,
+    pub persistent: bool,
+    /// Unix timestamp of when the rule was added
+    pub added: u64,
+}
+
+impl ManagedTarget {
+    pub fn new(ip: &str, mac: Option<MacAddr>, kind: RuleKind, persistent: bool) -> Self {
+        ManagedTarget {
+            ip: ip.to_string(),
+            family: Family::of(ip),
+            mac,
+            kind,
+            upload: None,
+            upload_pipe: None,
+            download: None,
+            download_pipe: None,
+            persistent,
+            added: unix_now(),
+        }
+    }
+
+    /// What the rules do, as `scan` shows it: "blocked" or e.g.
+    /// "limited (100/- KB/s)" for upload/download caps.
+    pub fn rule_summary(&self) -> String {
+        let rate = |kbytes: Option<u32>| kbytes.map_or("-".to_string(), |k| k.to_string());
+        match self.kind {
+            RuleKind::Monitor => "blocked".to_string(),
+            RuleKind::Limit => format!(
+                "limited ({}/{} KB/s)",
+                rate(self.upload),
+                rate(self.download)
+            ),
+        }
+    }
+
+    /// Whether the rules apply to `ip`: it is the target's address, or in
+    /// its range for targets given as a network.
+    pub fn covers(&self, ip: IpAddr) -> bool {
+        self.ip
+            .parse::<IpNetwork>()
+            .is_ok_and(|network| network.contains(ip))
+    }
+
+    pub fn pipes(&self) -> impl Iterator<Item = u32> {
+        self.upload_pipe.into_iter().chain(self.download_pipe)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub targets: Vec<ManagedTarget>,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+}
+
+impl State {
+    /// Loads the state file; a missing file means nothing is managed yet.
+    pub fn load() -> Result<Self> {
+        store::load(STATE_FILE)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        store::save(STATE_FILE, self)
+    }
+
+    /// Adds `target`, replacing any existing rule of the same kind for its IP.
+    /// Returns the replaced entry.
+    pub fn upsert(&mut self, target: ManagedTarget) -> Option<ManagedTarget> {
+        let replaced = self.take(&target.ip, Some(target.kind)).pop();
+        self.targets.push(target);
+        replaced
+    }
+
+    /// Removes and returns the entries for `ip`, optionally only those of
+    /// one kind.
+    pub fn take(&mut self, ip: &str, kind: Option<RuleKind>) -> Vec<ManagedTarget> {
+        let (taken, kept) = self
+            .targets
+            .drain(..)
+            .partition(|t| t.ip == ip && kind.is_none_or(|k| t.kind == k));
+        self.targets = kept;
+        taken
+    }
+
+    /// The lowest dummynet pipe number not used by any target, skipping
+    /// `reserved` (pipes handed out but not yet recorded).
+    pub fn free_pipe(&self, reserved: &[u32]) -> u32 {
+        (1..)
+            .find(|n| {
+                !reserved.contains(n)
+                    && !self.targets.iter().flat_map(|t| t.pipes()).any(|p| p == *n)
+            })
+            .unwrap_or(1)
+    }
+}
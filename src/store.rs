@@ -0,0 +1,98 @@
+//! Small JSON files kept under `~/.wifi-kicker/`.
+
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use nix::unistd::geteuid;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::os::unix::fs::{chown, DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// The home directory the data directory is in. Jobs that run as root on
+/// their own are handed it, so they find the state `sudo` saved.
+pub fn home() -> Result<PathBuf> {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("HOME is not set"))
+}
+
+/// The tool's private data directory, created on first use. It is only
+/// accessible to its owner, since firewall rules are staged in it before
+/// being installed into /etc.
+pub fn data_dir() -> Result<PathBuf> {
+    let dir = home()?.join(".wifi-kicker");
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    // Directories from older versions were created with the default umask
+    if let Ok(metadata) = fs::metadata(&dir) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            let _ = fs::set_permissions(&dir, fs::Permissions::from_mode(0o700));
+        }
+    }
+    hand_back(&dir);
+    Ok(dir)
+}
+
+/// The user and group IDs of whoever ran wifi-kicker through `sudo`, from
+/// `SUDO_UID` and `SUDO_GID`; `None` unless running as root.
+fn sudo_owner() -> Option<(u32, u32)> {
+    if !geteuid().is_root() {
+        return None;
+    }
+    let id = |var| env::var(var).ok()?.parse().ok();
+    Some((id("SUDO_UID")?, id("SUDO_GID")?))
+}
+
+/// Gives `path`, something in the data directory, back to the user who ran
+/// wifi-kicker through `sudo`, so root doesn't end up owning their state.
+/// Only when the home directory is theirs: with sudo's HOME of /root the
+/// data directory is root's anyway. Best-effort.
+pub fn hand_back(path: &Path) {
+    let Some((uid, gid)) = sudo_owner() else {
+        return;
+    };
+    let home_is_theirs = home().is_ok_and(|home| fs::metadata(home).is_ok_and(|m| m.uid() == uid));
+    let owned = fs::metadata(path).is_ok_and(|m| m.uid() == uid && m.gid() == gid);
+    if !home_is_theirs || owned {
+        return;
+    }
+    if let Err(e) = chown(path, Some(uid), Some(gid)) {
+        debug!(
+            "Failed to give {} back to uid {}: {}",
+            path.display(),
+            uid,
+            e
+        );
+    }
+}
+
+/// Path of a scratch file in the data directory, for files that are handed
+/// to privileged commands.
+pub fn work_file(name: &str) -> Result<String> {
+    Ok(data_dir()?.join(name).to_string_lossy().into_owned())
+}
+
+/// Loads `name` from the data directory, or the default value if the file
+/// doesn't exist yet.
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> Result<T> {
+    let path = data_dir()?.join(name);
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+pub fn save<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    let path = data_dir()?.join(name);
+    fs::write(&path, serde_json::to_string_pretty(value)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    hand_back(&path);
+    Ok(())
+}
@@ -0,0 +1,256 @@
+//! Built-in host discovery for machines without nmap: a concurrent TCP
+//! connect sweep of the interface's subnet. Any answer, including a refused
+//! connection, proves the host is up; probing also fills the ARP cache so
+//! MACs can be collected afterwards.
+
+use crate::device::{unix_now, Device, DeviceSource};
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use ipnetwork::Ipv4Network;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+/// Ports likely to answer (or actively refuse) on typical home devices;
+/// 62078 is the iOS lockdown service.
+const PROBE_PORTS: [u16; 4] = [80, 443, 22, 62078];
+
+/// Extracts our address and netmask from the first `inet` line of
+/// `ifconfig <iface>` output, e.g.
+/// `inet 192.168.1.23 netmask 0xffffff00 broadcast 192.168.1.255` on macOS,
+/// `inet 10.8.0.2 --> 10.8.0.1 netmask 0xffffffff` on a utun VPN,
+/// `inet 192.168.1.23  netmask 255.255.255.0` on Linux or
+/// `inet addr:192.168.1.23  Bcast:192.168.1.255  Mask:255.255.255.0` on
+/// older net-tools.
+pub fn parse_ifconfig_inet(s: &str) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    s.lines().find_map(|line| {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (&"inet", [addr, rest @ ..]) = parts.split_first()? else {
+            return None;
+        };
+        let addr = addr.strip_prefix("addr:").unwrap_or(addr).parse().ok()?;
+        let mask = rest.iter().enumerate().find_map(|(i, part)| match *part {
+            "netmask" => rest.get(i + 1).copied(),
+            _ => part.strip_prefix("Mask:"),
+        })?;
+        if let Ok(mask) = mask.parse::<Ipv4Addr>() {
+            return Some((addr, mask));
+        }
+        let mask = u32::from_str_radix(mask.strip_prefix("0x").unwrap_or(mask), 16).ok()?;
+        Some((addr, Ipv4Addr::from(mask)))
+    })
+}
+
+/// The subnet the interface described by `ifconfig` output is attached to.
+pub fn interface_network(ifconfig: &str) -> Option<Ipv4Network> {
+    let (addr, mask) = parse_ifconfig_inet(ifconfig)?;
+    let network = Ipv4Network::with_netmask(addr, mask).ok()?;
+    Ipv4Network::new(network.network(), network.prefix()).ok()
+}
+
+/// Returns true if `ip` accepts or refuses a TCP connection on any probe
+/// port within `per_port`.
+async fn probe(ip: Ipv4Addr, per_port: Duration) -> bool {
+    for port in PROBE_PORTS {
+        let addr = SocketAddr::from((ip, port));
+        match timeout(per_port, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return true,
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Probes every host address in `network` (except `skip`, usually our own
+/// address, and those in `exclude`), `concurrency` at a time, and returns
+/// the ones that answered. `progress` counts the hosts probed so far.
+pub async fn ping_sweep(
+    network: Ipv4Network,
+    skip: Option<Ipv4Addr>,
+    exclude: &[Ipv4Network],
+    per_port: Duration,
+    concurrency: usize,
+    progress: &ProgressBar,
+) -> Result<Vec<Device>> {
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = JoinSet::new();
+    for ip in network.iter() {
+        if ip == network.network()
+            || ip == network.broadcast()
+            || Some(ip) == skip
+            || exclude.iter().any(|excluded| excluded.contains(ip))
+        {
+            continue;
+        }
+        let semaphore = Arc::clone(&semaphore);
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.ok()?;
+            probe(ip, per_port).await.then_some(ip)
+        });
+    }
+
+    let phase_style = progress.style();
+    progress.set_length(tasks.len() as u64);
+    progress.set_position(0);
+    if let Ok(style) =
+        ProgressStyle::with_template("{spinner} {msg}: {pos}/{len} hosts [{elapsed}]")
+    {
+        progress.set_style(style);
+    }
+
+    let seen = unix_now();
+    let mut devices = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        progress.inc(1);
+        if let Ok(Some(ip)) = result {
+            devices.push(Device {
+                last_seen: Some(seen),
+                ..Device::new(ip, DeviceSource::Sweep)
+            });
+        }
+    }
+    progress.set_style(phase_style);
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `ifconfig en0` on a MacBook on home Wi-Fi.
+    const WIFI: &str = "\
+en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\toptions=6460<TSO4,TSO6,CHANNEL_IO,PARTIAL_CSUM,ZEROINVERT_CSUM>
+\tether a4:83:e7:12:34:56
+\tinet6 fe80::1c2b:3d4e:5f60:7182%en0 prefixlen 64 secured scopeid 0xb
+\tinet 192.168.1.23 netmask 0xffffff00 broadcast 192.168.1.255
+\tnd6 options=201<PERFORMNUD,DAD>
+\tmedia: autoselect
+\tstatus: active
+";
+
+    /// `ifconfig en8` while tethered to an iPhone over USB, which hands out
+    /// a /28.
+    const TETHERING: &str = "\
+en8: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\toptions=404<VLAN_MTU,CHANNEL_IO>
+\tether 9a:8b:7c:6d:5e:4f
+\tinet6 fe80::18a3:2bff:fe4c:5d6e%en8 prefixlen 64 secured scopeid 0x14
+\tinet 172.20.10.2 netmask 0xfffffff0 broadcast 172.20.10.15
+\tnd6 options=201<PERFORMNUD,DAD>
+\tmedia: autoselect (100baseTX <full-duplex>)
+\tstatus: active
+";
+
+    /// `ifconfig utun4` with a WireGuard tunnel up, a point-to-point link.
+    const UTUN: &str = "\
+utun4: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1420
+\toptions=6463<RXCSUM,TXCSUM,TSO4,TSO6,CHANNEL_IO,PARTIAL_CSUM,ZEROINVERT_CSUM>
+\tinet 10.8.0.2 --> 10.8.0.1 netmask 0xffffffff
+";
+
+    /// A utun that only carries IPv6, as iCloud Private Relay and Back to
+    /// My Mac set up.
+    const UTUN_IPV6_ONLY: &str = "\
+utun0: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1380
+\tinet6 fe80::a1b2:c3d4:e5f6:789%utun0 prefixlen 64 scopeid 0xf
+\tnd6 options=201<PERFORMNUD,DAD>
+";
+
+    fn network(s: &str) -> Ipv4Network {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_a_wifi_interface() {
+        assert_eq!(
+            parse_ifconfig_inet(WIFI),
+            Some((
+                Ipv4Addr::new(192, 168, 1, 23),
+                Ipv4Addr::new(255, 255, 255, 0)
+            ))
+        );
+        assert_eq!(interface_network(WIFI), Some(network("192.168.1.0/24")));
+    }
+
+    #[test]
+    fn parses_a_tethering_interface() {
+        assert_eq!(
+            parse_ifconfig_inet(TETHERING),
+            Some((
+                Ipv4Addr::new(172, 20, 10, 2),
+                Ipv4Addr::new(255, 255, 255, 240)
+            ))
+        );
+        assert_eq!(
+            interface_network(TETHERING),
+            Some(network("172.20.10.0/28"))
+        );
+    }
+
+    #[test]
+    fn parses_a_vpn_tunnel() {
+        assert_eq!(
+            parse_ifconfig_inet(UTUN),
+            Some((Ipv4Addr::new(10, 8, 0, 2), Ipv4Addr::BROADCAST))
+        );
+        assert_eq!(interface_network(UTUN), Some(network("10.8.0.2/32")));
+        assert_eq!(parse_ifconfig_inet(UTUN_IPV6_ONLY), None);
+        assert_eq!(interface_network(UTUN_IPV6_ONLY), None);
+    }
+
+    #[test]
+    fn computes_a_22_from_the_host_address() {
+        let ifconfig = WIFI.replace(
+            "inet 192.168.1.23 netmask 0xffffff00 broadcast 192.168.1.255",
+            "inet 10.20.6.141 netmask 0xfffffc00 broadcast 10.20.7.255",
+        );
+        assert_eq!(interface_network(&ifconfig), Some(network("10.20.4.0/22")));
+    }
+
+    #[test]
+    fn converts_hex_netmasks() {
+        let mask = |hex: &str| {
+            let ifconfig = WIFI.replace("0xffffff00", hex);
+            parse_ifconfig_inet(&ifconfig).map(|(_, mask)| mask)
+        };
+        assert_eq!(mask("0xffff0000"), Some(Ipv4Addr::new(255, 255, 0, 0)));
+        assert_eq!(mask("0xfffffe00"), Some(Ipv4Addr::new(255, 255, 254, 0)));
+        assert_eq!(mask("0xffffff80"), Some(Ipv4Addr::new(255, 255, 255, 128)));
+        assert_eq!(mask("0xfffffffc"), Some(Ipv4Addr::new(255, 255, 255, 252)));
+        assert_eq!(mask("0xnotamask"), None);
+    }
+
+    #[test]
+    fn reads_the_first_address_of_an_interface() {
+        // A second address added with `ifconfig en0 alias`
+        let ifconfig = WIFI.replace(
+            "\tnd6 options",
+            "\tinet 10.0.0.5 netmask 0xff000000 broadcast 10.255.255.255\n\tnd6 options",
+        );
+        assert_eq!(
+            parse_ifconfig_inet(&ifconfig).map(|(addr, _)| addr),
+            Some(Ipv4Addr::new(192, 168, 1, 23))
+        );
+    }
+
+    #[test]
+    fn parses_linux_netmasks() {
+        let net_tools = "wlan0: flags=4163<UP,BROADCAST,RUNNING,MULTICAST>  mtu 1500\n        inet 192.168.8.10  netmask 255.255.252.0  broadcast 192.168.11.255\n";
+        assert_eq!(
+            interface_network(net_tools),
+            Some(network("192.168.8.0/22"))
+        );
+
+        let old_net_tools = "wlan0     Link encap:Ethernet  HWaddr 52:54:00:12:34:56\n          inet addr:192.168.1.23  Bcast:192.168.1.255  Mask:255.255.255.0\n";
+        assert_eq!(
+            interface_network(old_net_tools),
+            Some(network("192.168.1.0/24"))
+        );
+    }
+}
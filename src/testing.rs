@@ -0,0 +1,78 @@
+//! Shared setup for tests that drive the firewall code through a
+//! [`MockRunner`].
+
+use crate::firewall::{PfFirewall, PF_RULES_FILE};
+use crate::{store, MockRunner, RunContext};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+
+/// HOME is process-wide, so tests that keep state take turns.
+static HOME: Mutex<()> = Mutex::new(());
+
+/// What every mocked command prints: enough for `pfctl -s info` to report
+/// PF enabled and for `pfctl -sr` to list our anchor hook.
+const PFCTL_OUTPUT: &str = "Status: Enabled for 0 days 00:10:00\nanchor \"com.wifi-kicker\" all\n";
+
+/// A PF backend whose pf.conf (already hooked up) and anchor file live in a
+/// fresh directory that is also HOME, so the data directory is in there too
+/// and nothing outside it is read or written.
+pub struct Fixture {
+    _home: MutexGuard<'static, ()>,
+    pub dir: PathBuf,
+    pub runner: MockRunner,
+    pub firewall: PfFirewall,
+}
+
+impl Fixture {
+    pub fn new(name: &str) -> Self {
+        let home = HOME.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = env::temp_dir().join(format!("wifi-kicker-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        env::set_var("HOME", &dir);
+
+        let pf_conf = dir.join("pf.conf");
+        fs::write(
+            &pf_conf,
+            "anchor \"com.apple/*\"\n\
+             dummynet-anchor \"com.wifi-kicker\"\n\
+             anchor \"com.wifi-kicker\"\n\
+             load anchor \"com.wifi-kicker\" from \"/etc/pf.anchors/com.wifi-kicker\"\n",
+        )
+        .unwrap();
+        let anchor_file = dir.join("com.wifi-kicker");
+        fs::write(&anchor_file, "").unwrap();
+
+        Fixture {
+            _home: home,
+            firewall: PfFirewall {
+                pf_conf: pf_conf.to_string_lossy().into_owned(),
+                anchor_file: anchor_file.to_string_lossy().into_owned(),
+            },
+            runner: MockRunner::new(PFCTL_OUTPUT, false),
+            dir,
+        }
+    }
+
+    pub fn ctx(&self) -> RunContext<'_> {
+        RunContext {
+            dry_run: false,
+            runner: &self.runner,
+            firewall: &self.firewall,
+        }
+    }
+
+    /// The ruleset last handed to pfctl.
+    pub fn rules(&self) -> String {
+        fs::read_to_string(store::work_file(PF_RULES_FILE).unwrap()).unwrap_or_default()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
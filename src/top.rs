@@ -0,0 +1,251 @@
+//! `top`: a full-screen view of the managed devices and their live traffic
+//! rates, computed from the deltas of the rule counters `usage` reads.
+
+use crate::alias::{self, AliasTarget};
+use crate::check_root;
+use crate::device::format_bytes;
+use crate::firewall::{Firewall, Usage};
+use crate::runner::CommandRunner;
+use crate::state::{ManagedTarget, RuleKind, State};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::interval;
+
+const REFRESH: Duration = Duration::from_secs(1);
+/// Rates at or above this share of their cap are highlighted, as a sign the
+/// limit is being enforced.
+const NEAR_CAP: f64 = 0.9;
+
+/// A traffic rate in KB/s (matching the caps given to `limit`), `None`
+/// until two samples exist.
+struct Rate {
+    kbytes_per_sec: Option<f64>,
+    cap: Option<u32>,
+}
+
+impl Rate {
+    fn cell(&self) -> Cell<'static> {
+        let Some(rate) = self.kbytes_per_sec else {
+            return Cell::from("-");
+        };
+        match self.cap {
+            Some(cap) => {
+                let cell = Cell::from(format!("{:.1} / {} KB/s", rate, cap));
+                if rate >= f64::from(cap) * NEAR_CAP {
+                    cell.style(Style::new().fg(Color::Yellow).bold())
+                } else {
+                    cell
+                }
+            }
+            None => Cell::from(format!("{:.1} KB/s", rate)),
+        }
+    }
+}
+
+struct DeviceRow {
+    name: String,
+    ip: String,
+    rules: String,
+    down: Rate,
+    up: Rate,
+    total: u64,
+}
+
+/// The alias naming a target, by its IP or its MAC.
+fn alias_for(aliases: &BTreeMap<String, AliasTarget>, target: &ManagedTarget) -> Option<String> {
+    aliases.iter().find_map(|(name, alias)| {
+        let matches = match alias {
+            AliasTarget::Ip(ip) => ip.to_string() == target.ip,
+            AliasTarget::Mac(mac) => Some(*mac) == target.mac,
+        };
+        matches.then(|| name.clone())
+    })
+}
+
+/// Reads the counters and turns them into one row per managed IP, with
+/// rates relative to the `previous` sample, which is then replaced.
+fn sample(
+    firewall: &dyn Firewall,
+    runner: &dyn CommandRunner,
+    previous: &mut Option<(Instant, BTreeMap<String, Usage>)>,
+) -> Result<Vec<DeviceRow>> {
+    let state = State::load()?;
+    let aliases = alias::load()?;
+    let usage = firewall.usage(runner)?;
+    let now = Instant::now();
+
+    // A device can be blocked and limited at once, as two targets
+    let mut by_ip: Vec<(&str, Vec<&ManagedTarget>)> = Vec::new();
+    for target in &state.targets {
+        match by_ip.iter_mut().find(|(ip, _)| *ip == target.ip) {
+            Some((_, targets)) => targets.push(target),
+            None => by_ip.push((&target.ip, vec![target])),
+        }
+    }
+
+    let rows = by_ip
+        .into_iter()
+        .map(|(ip, targets)| {
+            let current = usage.get(ip).copied().unwrap_or_default();
+            let rate = |bytes: fn(&Usage) -> u64| {
+                let (then, old) = previous.as_ref()?;
+                let old = old.get(ip).copied().unwrap_or_default();
+                let elapsed = now.duration_since(*then).as_secs_f64();
+                // Counters restart from zero after a reset or reload
+                let delta = bytes(&current).saturating_sub(bytes(&old));
+                Some(delta as f64 / 1000.0 / elapsed.max(0.001))
+            };
+            let limit = targets.iter().find(|t| t.kind == RuleKind::Limit);
+            DeviceRow {
+                name: targets
+                    .iter()
+                    .find_map(|t| alias_for(&aliases, t))
+                    .unwrap_or_else(|| "-".to_string()),
+                ip: ip.to_string(),
+                rules: targets
+                    .iter()
+                    .map(|t| t.rule_summary())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                down: Rate {
+                    kbytes_per_sec: rate(|u| u.in_bytes),
+                    cap: limit.and_then(|t| t.download),
+                },
+                up: Rate {
+                    kbytes_per_sec: rate(|u| u.out_bytes),
+                    cap: limit.and_then(|t| t.upload),
+                },
+                total: current.in_bytes + current.out_bytes,
+            }
+        })
+        .collect();
+    *previous = Some((now, usage));
+    Ok(rows)
+}
+
+fn draw(frame: &mut Frame, rows: &[DeviceRow], error: Option<&str>) {
+    let [table_area, footer_area] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+
+    let header =
+        Row::new(["NAME", "IP", "RULES", "DOWN", "UP", "TOTAL"]).style(Style::new().bold());
+    let body: Vec<Row> = if rows.is_empty() {
+        vec![Row::new([
+            "No managed devices; add one with monitor or limit",
+        ])]
+    } else {
+        rows.iter()
+            .map(|row| {
+                Row::new([
+                    Cell::from(row.name.clone()),
+                    Cell::from(row.ip.clone()),
+                    Cell::from(row.rules.clone()),
+                    row.down.cell(),
+                    row.up.cell(),
+                    Cell::from(format_bytes(row.total)),
+                ])
+            })
+            .collect()
+    };
+    let widths = [
+        Constraint::Fill(1),
+        Constraint::Length(18),
+        Constraint::Fill(2),
+        Constraint::Length(20),
+        Constraint::Length(20),
+        Constraint::Length(11),
+    ];
+    let table = Table::new(body, widths)
+        .header(header)
+        .block(Block::bordered().title(" wifi-kicker top "));
+    frame.render_widget(table, table_area);
+
+    let footer = match error {
+        Some(error) => Paragraph::new(format!("Reading counters failed: {}", error)).red(),
+        None => Paragraph::new(
+            "Refreshes every second. Rates near a limit are highlighted. Press q to quit.",
+        )
+        .dark_gray(),
+    };
+    frame.render_widget(footer, footer_area);
+}
+
+/// Forwards terminal events until the receiving side is gone. crossterm
+/// reads input with blocking calls, so this runs on its own thread.
+fn forward_events(events: mpsc::Sender<Event>) {
+    while !events.is_closed() {
+        match event::poll(Duration::from_millis(200)) {
+            Ok(true) => {
+                let Ok(event) = event::read() else {
+                    return;
+                };
+                if events.blocking_send(event).is_err() {
+                    return;
+                }
+            }
+            Ok(false) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+fn is_quit(event: &Event) -> bool {
+    let Event::Key(key) = event else {
+        return false;
+    };
+    key.kind == KeyEventKind::Press
+        && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)))
+}
+
+async fn refresh_loop(
+    terminal: &mut DefaultTerminal,
+    firewall: &dyn Firewall,
+    runner: &dyn CommandRunner,
+    events: &mut mpsc::Receiver<Event>,
+) -> Result<()> {
+    let mut ticks = interval(REFRESH);
+    let mut previous = None;
+    let mut rows = Vec::new();
+    let mut error = None;
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                match sample(firewall, runner, &mut previous) {
+                    Ok(sampled) => {
+                        rows = sampled;
+                        error = None;
+                    }
+                    Err(e) => error = Some(format!("{:#}", e)),
+                }
+            }
+            event = events.recv() => match event {
+                Some(event) if is_quit(&event) => return Ok(()),
+                // Redraw on resizes and any other key
+                Some(_) => {}
+                None => return Ok(()),
+            },
+        }
+        terminal.draw(|frame| draw(frame, &rows, error.as_deref()))?;
+    }
+}
+
+/// Runs the view until the user quits, restoring the terminal afterwards.
+///
+/// Needs root.
+pub async fn run(firewall: &dyn Firewall, runner: &dyn CommandRunner) -> Result<()> {
+    check_root()?;
+    let (sender, mut events) = mpsc::channel(16);
+    std::thread::spawn(move || forward_events(sender));
+    let mut terminal = ratatui::init();
+    let result = refresh_loop(&mut terminal, firewall, runner, &mut events).await;
+    ratatui::restore();
+    result
+}
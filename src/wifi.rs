@@ -0,0 +1,244 @@
+//! Wireless link details for the scan header: `wdutil info` or the legacy
+//! `airport -I` on macOS, `iw dev <interface> link` on Linux.
+
+use log::debug;
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+
+/// Removed in recent macOS releases, but still present on older ones.
+const AIRPORT: &str =
+    "/System/Library/PrivateFrameworks/Apple80211.framework/Versions/Current/Resources/airport";
+
+/// What the interface is associated with. Every field is optional since
+/// the tools omit (or redact) values depending on OS version and privileges.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WifiLink {
+    pub ssid: Option<String>,
+    pub bssid: Option<String>,
+    pub channel: Option<u16>,
+    /// "2.4 GHz", "5 GHz" or "6 GHz"
+    pub band: Option<String>,
+    pub rssi_dbm: Option<i32>,
+    pub noise_dbm: Option<i32>,
+    pub tx_rate_mbps: Option<f64>,
+}
+
+impl WifiLink {
+    fn is_empty(&self) -> bool {
+        *self == WifiLink::default()
+    }
+}
+
+/// Band for macOS-style channel numbers, guessing 5 GHz for the overlap
+/// with 6 GHz channel numbers since that is far more common.
+fn band_for_channel(channel: u16) -> Option<String> {
+    match channel {
+        1..=14 => Some("2.4 GHz".to_string()),
+        32..=177 => Some("5 GHz".to_string()),
+        _ => None,
+    }
+}
+
+/// Channel and band from a frequency in MHz, as `iw` reports it.
+fn channel_for_freq(mhz: u32) -> Option<(u16, String)> {
+    let (channel, band) = match mhz {
+        2484 => (14, "2.4 GHz"),
+        2412..=2472 => ((mhz - 2407) / 5, "2.4 GHz"),
+        5160..=5885 => ((mhz - 5000) / 5, "5 GHz"),
+        5955..=7115 => ((mhz - 5950) / 5, "6 GHz"),
+        _ => return None,
+    };
+    Some((channel as u16, band.to_string()))
+}
+
+/// The leading number of values like `-52 dBm` or `866.0 Mbps`.
+fn leading_number<T: std::str::FromStr>(value: &str) -> Option<T> {
+    value.split_whitespace().next()?.parse().ok()
+}
+
+/// Drops values macOS hides without location permission.
+fn visible(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty() && value != "<redacted>" && value != "None").then(|| value.to_string())
+}
+
+/// Parses the WIFI section of `wdutil info`, if it describes `interface`:
+///
+/// ```text
+///     Interface Name       : en0
+///     SSID                 : HomeNet
+///     BSSID                : 00:11:22:33:44:55
+///     RSSI                 : -52 dBm
+///     Noise                : -94 dBm
+///     Tx Rate              : 866.0 Mbps
+///     Channel              : 5g149/80
+/// ```
+pub fn parse_wdutil(s: &str, interface: &str) -> Option<WifiLink> {
+    let mut link = WifiLink::default();
+    let mut in_wifi = false;
+    for line in s.lines() {
+        let trimmed = line.trim();
+        // Sections are a bare title between separator lines
+        if !trimmed.contains(':') && trimmed.chars().all(|c| c.is_ascii_uppercase() || c == ' ') {
+            if !trimmed.is_empty() {
+                in_wifi = trimmed == "WIFI";
+            }
+            continue;
+        }
+        let Some((key, value)) = trimmed.split_once(" : ") else {
+            continue;
+        };
+        if !in_wifi {
+            continue;
+        }
+        match key.trim() {
+            "Interface Name" if value.trim() != interface => return None,
+            "SSID" => link.ssid = visible(value),
+            "BSSID" => link.bssid = visible(value),
+            "RSSI" => link.rssi_dbm = leading_number(value),
+            "Noise" => link.noise_dbm = leading_number(value),
+            "Tx Rate" => link.tx_rate_mbps = leading_number(value),
+            "Channel" => {
+                // 2g6/20, 5g149/80 or 6g37/160
+                let value = value.trim();
+                let (band, rest) = value.split_once('g').unwrap_or(("", value));
+                link.channel = rest.split('/').next().and_then(|c| c.parse().ok());
+                link.band = match band {
+                    "2" => Some("2.4 GHz".to_string()),
+                    "5" => Some("5 GHz".to_string()),
+                    "6" => Some("6 GHz".to_string()),
+                    _ => link.channel.and_then(band_for_channel),
+                };
+            }
+            _ => {}
+        }
+    }
+    (!link.is_empty()).then_some(link)
+}
+
+/// Parses `airport -I`: `key: value` lines such as `agrCtlRSSI: -52`,
+/// `lastTxRate: 866` and `channel: 149,80`.
+pub fn parse_airport(s: &str) -> Option<WifiLink> {
+    let mut link = WifiLink::default();
+    for line in s.lines() {
+        let Some((key, value)) = line.trim().split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "SSID" => link.ssid = visible(value),
+            // The value itself contains colons
+            "BSSID" => link.bssid = visible(line.trim().trim_start_matches("BSSID:")),
+            "agrCtlRSSI" => link.rssi_dbm = value.parse().ok(),
+            "agrCtlNoise" => link.noise_dbm = value.parse().ok(),
+            "lastTxRate" => link.tx_rate_mbps = value.parse().ok(),
+            "channel" => {
+                link.channel = value.split(',').next().and_then(|c| c.parse().ok());
+                link.band = link.channel.and_then(band_for_channel);
+            }
+            _ => {}
+        }
+    }
+    (!link.is_empty()).then_some(link)
+}
+
+/// Parses `iw dev <interface> link`:
+///
+/// ```text
+/// Connected to 00:11:22:33:44:55 (on wlan0)
+///         SSID: HomeNet
+///         freq: 5745
+///         signal: -52 dBm
+///         tx bitrate: 866.7 MBit/s VHT-MCS 9 80MHz VHT-NSS 2
+/// ```
+///
+/// `Not connected.` yields `None`.
+pub fn parse_iw_link(s: &str) -> Option<WifiLink> {
+    let mut link = WifiLink::default();
+    for line in s.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Connected to ") {
+            link.bssid = rest.split_whitespace().next().map(String::from);
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key {
+            "SSID" => link.ssid = visible(value),
+            "freq" => {
+                if let Some((channel, band)) =
+                    leading_number::<f64>(value).and_then(|mhz| channel_for_freq(mhz as u32))
+                {
+                    link.channel = Some(channel);
+                    link.band = Some(band);
+                }
+            }
+            "signal" => link.rssi_dbm = leading_number(value),
+            "tx bitrate" => link.tx_rate_mbps = leading_number(value),
+            _ => {}
+        }
+    }
+    (!link.is_empty()).then_some(link)
+}
+
+fn stdout(cmd: &str, args: &[&str]) -> Option<String> {
+    match Command::new(cmd).args(args).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            debug!("{} exited with {}", cmd, output.status);
+            None
+        }
+        Err(e) => {
+            debug!("Failed to run {}: {}", cmd, e);
+            None
+        }
+    }
+}
+
+/// Link details of `interface`, or `None` for wired interfaces and when no
+/// tool could tell.
+pub fn link(interface: &str) -> Option<WifiLink> {
+    if cfg!(target_os = "linux") {
+        return parse_iw_link(&stdout("iw", &["dev", interface, "link"])?);
+    }
+    // wdutil needs root, which scans usually have
+    if let Some(link) = stdout("wdutil", &["info"]).and_then(|s| parse_wdutil(&s, interface)) {
+        return Some(link);
+    }
+    if Path::new(AIRPORT).exists() {
+        return parse_airport(&stdout(AIRPORT, &["-I"])?);
+    }
+    None
+}
+
+/// Prints `link` as the scan header's Wi-Fi lines.
+pub fn print_link(link: &WifiLink) {
+    let unknown = || "?".to_string();
+    println!(
+        "Wi-Fi: {} ({})",
+        link.ssid.clone().unwrap_or_else(unknown),
+        link.bssid.clone().unwrap_or_else(unknown)
+    );
+    let mut details = Vec::new();
+    if let Some(channel) = link.channel {
+        match &link.band {
+            Some(band) => details.push(format!("channel {} ({})", channel, band)),
+            None => details.push(format!("channel {}", channel)),
+        }
+    }
+    if let Some(rssi) = link.rssi_dbm {
+        details.push(format!("RSSI {} dBm", rssi));
+    }
+    if let Some(noise) = link.noise_dbm {
+        details.push(format!("noise {} dBm", noise));
+    }
+    if let Some(rate) = link.tx_rate_mbps {
+        details.push(format!("tx rate {} Mbps", rate));
+    }
+    if !details.is_empty() {
+        println!("       {}", details.join(", "));
+    }
+}
@@ -0,0 +1,38 @@
+//! Wake-on-LAN: the magic packet is six 0xFF bytes followed by the target
+//! MAC sixteen times, broadcast over UDP to the local subnet.
+
+use crate::mac::MacAddr;
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+
+pub const DEFAULT_PORT: u16 = 9;
+
+pub fn magic_packet(mac: MacAddr) -> Vec<u8> {
+    let mut packet = vec![0xFF; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(&mac.0);
+    }
+    packet
+}
+
+/// Sends `count` magic packets for `mac` to `broadcast:port`, from `local`
+/// so they leave through the interface that owns that address.
+pub fn wake(
+    mac: MacAddr,
+    local: Ipv4Addr,
+    broadcast: Ipv4Addr,
+    port: u16,
+    count: u32,
+) -> Result<()> {
+    let socket = UdpSocket::bind((local, 0))
+        .with_context(|| format!("Failed to bind a UDP socket to {}", local))?;
+    socket.set_broadcast(true)?;
+    let packet = magic_packet(mac);
+    let dest = SocketAddrV4::new(broadcast, port);
+    for _ in 0..count {
+        socket
+            .send_to(&packet, dest)
+            .with_context(|| format!("Failed to send the magic packet to {}", dest))?;
+    }
+    Ok(())
+}